@@ -0,0 +1,494 @@
+//! Derives for the wire format of DNS records and other structures of the `dns` crate.
+//!
+//! Fields are laid out in declaration order. Their wire format follows from their type,
+//! or from a `#[dns(...)]` attribute where the type alone is ambiguous:
+//!
+//! - `u8`, `u16` and `u32` are written in network byte order
+//! - `[u8; N]` and `&[u8; N]` are written as is, e.g. addresses
+//! - `#[dns(u16)]` is a 16 bit integer converted with `From<u16>` and `Into<u16>`, e.g. `Type`
+//! - `#[dns(u48)]` is a 48 bit integer kept in a `u64`
+//! - `#[dns(character_string)]` is bytes prefixed by their length as `u8`
+//! - `#[dns(u16_prefixed)]` is bytes prefixed by their length as `u16`
+//! - `#[dns(remaining)]` is bytes taking up the rest of the rdata
+//! - `#[dns(skip)]` is not part of the wire format
+//! - `Box<dyn RecordData>` or `Arc<dyn RecordData>` is rdata of a registered codec
+//! - any other type implements `Parse`, `Serialize` and `size`, e.g. `DomainName`
+//!
+//! Bytes are kept in a `&[u8]` or a `Cow<[u8]>`.
+//!
+//! On enums every variant is laid out like a struct. Variants are tied to their record
+//! type with `#[dns(type = A)]`; variants without one carry it in a skipped `r#type`
+//! field and are never parsed.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    Data, DeriveInput, Error, Expr, Fields, GenericParam, Generics, Ident, Lifetime, LifetimeParam,
+    Path, Result, Type, Variant, parse_macro_input, parse_quote, spanned::Spanned,
+};
+
+/// Derives `Parse` for structs and `parse_rdata` for record enums.
+#[proc_macro_derive(Parse, attributes(dns))]
+pub fn derive_parse(input: TokenStream) -> TokenStream {
+    expand(parse_macro_input!(input as DeriveInput), parse)
+}
+
+/// Derives `Serialize`.
+#[proc_macro_derive(Serialize, attributes(dns))]
+pub fn derive_serialize(input: TokenStream) -> TokenStream {
+    expand(parse_macro_input!(input as DeriveInput), serialize)
+}
+
+/// Derives a `size` method returning the size of the uncompressed wire format.
+#[proc_macro_derive(Size, attributes(dns))]
+pub fn derive_size(input: TokenStream) -> TokenStream {
+    expand(parse_macro_input!(input as DeriveInput), size)
+}
+
+/// Derives `From<&Record> for Type` for record enums.
+#[proc_macro_derive(RecordType, attributes(dns))]
+pub fn derive_record_type(input: TokenStream) -> TokenStream {
+    expand(parse_macro_input!(input as DeriveInput), record_type)
+}
+
+fn expand(input: DeriveInput, derive: fn(&DeriveInput) -> Result<TokenStream2>) -> TokenStream {
+    derive(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// How a field is laid out on the wire.
+enum Kind {
+    U8,
+    U16,
+    U32,
+    U48,
+    Convert16,
+    Array(Expr),
+    CharacterString,
+    U16Prefixed,
+    Remaining,
+    Dynamic,
+    Nested,
+    Skip,
+}
+
+struct Field {
+    ident: Ident,
+    kind: Kind,
+}
+
+impl Field {
+    fn all(fields: &Fields) -> Result<Vec<Self>> {
+        match fields {
+            Fields::Named(named) => named.named.iter().map(Self::new).collect(),
+            Fields::Unit => Ok(Vec::new()),
+            Fields::Unnamed(_) => Err(Error::new(fields.span(), "fields must be named")),
+        }
+    }
+
+    fn new(field: &syn::Field) -> Result<Self> {
+        let mut kind = None;
+
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("dns"))
+        {
+            attr.parse_nested_meta(|meta| {
+                kind = Some(
+                    match meta.path.get_ident().map(Ident::to_string).as_deref() {
+                        Some("u16") => Kind::Convert16,
+                        Some("u48") => Kind::U48,
+                        Some("character_string") => Kind::CharacterString,
+                        Some("u16_prefixed") => Kind::U16Prefixed,
+                        Some("remaining") => Kind::Remaining,
+                        Some("skip") => Kind::Skip,
+                        _ => return Err(meta.error("unknown dns field attribute")),
+                    },
+                );
+
+                Ok(())
+            })?;
+        }
+
+        let kind = match kind {
+            Some(kind) => kind,
+            None => Self::infer(&field.ty)?,
+        };
+
+        Ok(Self {
+            ident: field.ident.clone().expect("named field"),
+            kind,
+        })
+    }
+
+    fn infer(ty: &Type) -> Result<Kind> {
+        let kind = match ty {
+            Type::Path(ty) if ty.path.is_ident("u8") => Kind::U8,
+            Type::Path(ty) if ty.path.is_ident("u16") => Kind::U16,
+            Type::Path(ty) if ty.path.is_ident("u32") => Kind::U32,
+            Type::Path(ty)
+                if ty
+                    .path
+                    .segments
+                    .last()
+                    .is_some_and(|s| s.ident == "Box" || s.ident == "Arc") =>
+            {
+                Kind::Dynamic
+            }
+            Type::Array(array) => Kind::Array(array.len.clone()),
+            Type::Reference(reference) => match &*reference.elem {
+                Type::Array(array) => Kind::Array(array.len.clone()),
+                _ => {
+                    return Err(Error::new(
+                        ty.span(),
+                        "byte slices need #[dns(character_string)], #[dns(u16_prefixed)] or #[dns(remaining)]",
+                    ));
+                }
+            },
+            _ => Kind::Nested,
+        };
+
+        Ok(kind)
+    }
+
+    fn parse(&self) -> Result<TokenStream2> {
+        let expr = match &self.kind {
+            Kind::U8 => quote!(parser.consume_u8()?),
+            Kind::U16 => quote!(parser.consume_u16()?),
+            Kind::U32 => quote!(parser.consume_u32()?),
+            Kind::U48 => {
+                quote!((u64::from(parser.consume_u16()?) << 32) | u64::from(parser.consume_u32()?))
+            }
+            Kind::Convert16 => quote!(parser.consume_u16()?.into()),
+            Kind::Array(len) => quote!({
+                let start = parser.position();
+                let bytes = parser.consume_bytes(#len)?;
+                bytes.try_into().map_err(|_| {
+                    ::dns::proto::ParseError::new(::dns::proto::ParseErrorKind::FormatError, start)
+                })?
+            }),
+            Kind::CharacterString => quote!({
+                let len = parser.consume_u8()?;
+                ::core::convert::Into::into(parser.consume_bytes(len.into())?)
+            }),
+            Kind::U16Prefixed => quote!({
+                let len = parser.consume_u16()?;
+                ::core::convert::Into::into(parser.consume_bytes(len.into())?)
+            }),
+            Kind::Remaining => quote!(::core::convert::Into::into(parser.consume_remaining()?)),
+            Kind::Nested => quote!(::dns::proto::Parse::parse(parser)?),
+            Kind::Dynamic | Kind::Skip => {
+                return Err(Error::new(self.ident.span(), "field can't be parsed"));
+            }
+        };
+
+        let ident = &self.ident;
+        Ok(quote!(#ident: #expr))
+    }
+
+    fn serialize(&self) -> TokenStream2 {
+        let ident = &self.ident;
+
+        match &self.kind {
+            Kind::U8 => quote!(serializer.write_u8(#ident)?;),
+            Kind::U16 => quote!(serializer.write_u16(#ident)?;),
+            Kind::U32 => quote!(serializer.write_u32(#ident)?;),
+            Kind::U48 => quote! {
+                serializer.write_u16((#ident >> 32) as u16)?;
+                serializer.write_u32(#ident as u32)?;
+            },
+            Kind::Convert16 => quote!(serializer.write_u16(#ident.into())?;),
+            Kind::Array(_) | Kind::Remaining => quote!(serializer.write_bytes(#ident.as_ref())?;),
+            Kind::CharacterString => quote! {
+                serializer.write_u8(::core::convert::TryFrom::try_from(#ident.len()).map_err(
+                    |_| ::dns::proto::SerializeError::InvalidLength(#ident.len()),
+                )?)?;
+                serializer.write_bytes(#ident.as_ref())?;
+            },
+            Kind::U16Prefixed => quote! {
+                serializer.write_u16(::core::convert::TryFrom::try_from(#ident.len()).map_err(
+                    |_| ::dns::proto::SerializeError::InvalidLength(#ident.len()),
+                )?)?;
+                serializer.write_bytes(#ident.as_ref())?;
+            },
+            Kind::Nested => quote!(::dns::proto::Serialize::serialize(#ident, serializer)?;),
+            Kind::Dynamic => quote!(::dns::RecordData::serialize(#ident.as_ref(), serializer)?;),
+            Kind::Skip => quote!(),
+        }
+    }
+
+    fn size(&self) -> TokenStream2 {
+        let ident = &self.ident;
+
+        match &self.kind {
+            Kind::U8 => quote!(::core::mem::size_of::<u8>()),
+            Kind::U16 | Kind::Convert16 => quote!(::core::mem::size_of::<u16>()),
+            Kind::U32 => quote!(::core::mem::size_of::<u32>()),
+            // written as the upper 16 bits followed by the lower 32 bits
+            Kind::U48 => quote!(::core::mem::size_of::<u16>() + ::core::mem::size_of::<u32>()),
+            Kind::Array(_) | Kind::Remaining => quote!(#ident.len()),
+            Kind::CharacterString => quote!(::core::mem::size_of::<u8>() + #ident.len()),
+            Kind::U16Prefixed => quote!(::core::mem::size_of::<u16>() + #ident.len()),
+            Kind::Nested | Kind::Dynamic => quote!(#ident.size()),
+            Kind::Skip => quote!(0),
+        }
+    }
+
+    /// The field in a destructuring pattern, skipped fields are ignored.
+    fn pattern(&self) -> TokenStream2 {
+        let ident = &self.ident;
+
+        match &self.kind {
+            Kind::Skip => quote!(#ident: _),
+            _ => quote!(#ident),
+        }
+    }
+
+    /// The field in a destructuring pattern for its size, fields of a fixed size are
+    /// ignored.
+    fn size_pattern(&self) -> TokenStream2 {
+        let ident = &self.ident;
+
+        match &self.kind {
+            Kind::U8 | Kind::U16 | Kind::U32 | Kind::U48 | Kind::Convert16 | Kind::Skip => {
+                quote!(#ident: _)
+            }
+            _ => quote!(#ident),
+        }
+    }
+}
+
+/// Generics of the impl, with a lifetime for the parser or serializer added unless the
+/// type already has one.
+fn with_lifetime(generics: &Generics) -> (Generics, Lifetime) {
+    if let Some(lifetime) = generics.lifetimes().next() {
+        return (generics.clone(), lifetime.lifetime.clone());
+    }
+
+    let lifetime: Lifetime = parse_quote!('a);
+    let mut generics = generics.clone();
+    generics.params.insert(
+        0,
+        GenericParam::Lifetime(LifetimeParam::new(lifetime.clone())),
+    );
+
+    (generics, lifetime)
+}
+
+/// The record type a variant is tied to by `#[dns(type = ...)]`.
+fn variant_type(variant: &Variant) -> Result<Option<Path>> {
+    let mut r#type = None;
+
+    for attr in variant
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("dns"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if !meta.path.is_ident("type") {
+                return Err(meta.error("unknown dns variant attribute"));
+            }
+
+            r#type = Some(meta.value()?.parse()?);
+            Ok(())
+        })?;
+    }
+
+    Ok(r#type)
+}
+
+fn parse(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let (generics, lifetime) = with_lifetime(&input.generics);
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+
+    match &input.data {
+        Data::Struct(data) => {
+            let fields = Field::all(&data.fields)?
+                .iter()
+                .map(Field::parse)
+                .collect::<Result<Vec<_>>>()?;
+
+            Ok(quote! {
+                impl #impl_generics ::dns::proto::Parse<#lifetime> for #name #ty_generics #where_clause {
+                    fn parse(
+                        parser: &mut ::dns::proto::Parser<#lifetime>,
+                    ) -> ::core::result::Result<Self, ::dns::proto::ParseError> {
+                        Ok(Self { #(#fields,)* })
+                    }
+                }
+            })
+        }
+        Data::Enum(data) => {
+            let mut arms = Vec::new();
+
+            for variant in &data.variants {
+                let Some(r#type) = variant_type(variant)? else {
+                    continue;
+                };
+
+                let ident = &variant.ident;
+                let fields = Field::all(&variant.fields)?
+                    .iter()
+                    .map(Field::parse)
+                    .collect::<Result<Vec<_>>>()?;
+
+                arms.push(quote!(::dns::Type::#r#type => Self::#ident { #(#fields,)* },));
+            }
+
+            Ok(quote! {
+                impl #impl_generics #name #ty_generics #where_clause {
+                    /// Parses rdata of `r#type`, or returns `None` if no variant is tied to it.
+                    pub fn parse_rdata(
+                        parser: &mut ::dns::proto::Parser<#lifetime>,
+                        r#type: &::dns::Type,
+                    ) -> ::core::result::Result<::core::option::Option<Self>, ::dns::proto::ParseError> {
+                        Ok(Some(match r#type {
+                            #(#arms)*
+                            _ => return Ok(None),
+                        }))
+                    }
+                }
+            })
+        }
+        Data::Union(_) => Err(Error::new(input.span(), "unions are not supported")),
+    }
+}
+
+fn serialize(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let (generics, lifetime) = with_lifetime(&input.generics);
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let fields = Field::all(&data.fields)?;
+            let patterns = fields.iter().map(Field::pattern);
+            let writes = fields.iter().map(Field::serialize);
+
+            quote! {
+                let Self { #(#patterns,)* } = self;
+                #(#writes)*
+            }
+        }
+        Data::Enum(data) => {
+            let mut arms = Vec::new();
+
+            for variant in &data.variants {
+                let ident = &variant.ident;
+                let fields = Field::all(&variant.fields)?;
+                let patterns = fields.iter().map(Field::pattern);
+                let writes = fields.iter().map(Field::serialize);
+
+                arms.push(quote!(Self::#ident { #(#patterns,)* } => { #(#writes)* }));
+            }
+
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => return Err(Error::new(input.span(), "unions are not supported")),
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::dns::proto::Serialize<#lifetime> for #name #ty_generics #where_clause {
+            fn serialize(
+                self,
+                serializer: &mut ::dns::proto::Serializer<#lifetime>,
+            ) -> ::core::result::Result<usize, ::dns::proto::SerializeError> {
+                #body
+
+                Ok(serializer.position())
+            }
+        }
+    })
+}
+
+fn size(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let sum = |fields: &[Field]| {
+        let sizes = fields.iter().map(Field::size);
+        quote!(0 #(+ #sizes)*)
+    };
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let fields = Field::all(&data.fields)?;
+            let patterns = fields.iter().map(Field::size_pattern);
+            let sum = sum(&fields);
+
+            quote! {
+                let Self { #(#patterns,)* } = self;
+                #sum
+            }
+        }
+        Data::Enum(data) => {
+            let mut arms = Vec::new();
+
+            for variant in &data.variants {
+                let ident = &variant.ident;
+                let fields = Field::all(&variant.fields)?;
+                let patterns = fields.iter().map(Field::size_pattern);
+                let sum = sum(&fields);
+
+                arms.push(quote!(Self::#ident { #(#patterns,)* } => #sum,));
+            }
+
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => return Err(Error::new(input.span(), "unions are not supported")),
+    };
+
+    Ok(quote! {
+        impl #impl_generics #name #ty_generics #where_clause {
+            /// Size of the uncompressed wire format.
+            pub fn size(&self) -> usize {
+                #body
+            }
+        }
+    })
+}
+
+fn record_type(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new(input.span(), "only record enums have a type"));
+    };
+
+    let mut arms = Vec::new();
+    for variant in &data.variants {
+        let ident = &variant.ident;
+
+        let arm = match variant_type(variant)? {
+            Some(r#type) => quote!(#name::#ident { .. } => ::dns::Type::#r#type,),
+            None => quote!(#name::#ident { r#type, .. } => r#type.clone(),),
+        };
+
+        arms.push(arm);
+    }
+
+    Ok(quote! {
+        impl #impl_generics ::core::convert::From<&#name #ty_generics> for ::dns::Type #where_clause {
+            fn from(value: &#name #ty_generics) -> Self {
+                match value {
+                    #(#arms)*
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,8 @@
+use dns::proto::Size;
+
+#[derive(Size)]
+struct Fields<'a> {
+    data: &'a [u8],
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use dns::proto::RecordType;
+
+#[derive(RecordType)]
+struct Fields {
+    value: u16,
+}
+
+fn main() {}
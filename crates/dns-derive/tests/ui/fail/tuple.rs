@@ -0,0 +1,6 @@
+use dns::proto::Size;
+
+#[derive(Size)]
+struct Fields(u16, u32);
+
+fn main() {}
@@ -0,0 +1,9 @@
+use dns::proto::Serialize;
+
+#[derive(Serialize)]
+union Fields {
+    short: u16,
+    long: u32,
+}
+
+fn main() {}
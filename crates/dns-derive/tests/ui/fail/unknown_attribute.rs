@@ -0,0 +1,9 @@
+use dns::proto::Size;
+
+#[derive(Size)]
+struct Fields {
+    #[dns(u24)]
+    value: u32,
+}
+
+fn main() {}
@@ -0,0 +1,51 @@
+use std::borrow::Cow;
+
+use dns::Type;
+use dns::proto::{Parse, Parser, RecordType, Serialize, Serializer, Size};
+
+#[derive(Debug, Parse, Serialize, Size, RecordType)]
+enum Record<'a> {
+    #[dns(type = A)]
+    A { address: [u8; 4] },
+
+    #[dns(type = TXT)]
+    TXT {
+        #[dns(remaining)]
+        text: Cow<'a, [u8]>,
+    },
+
+    Other {
+        #[dns(skip)]
+        r#type: Type,
+        #[dns(remaining)]
+        data: Cow<'a, [u8]>,
+    },
+}
+
+fn main() {
+    let a = Record::A {
+        address: [192, 0, 2, 1],
+    };
+    assert_eq!(Type::from(&a), Type::A);
+    assert_eq!(a.size(), 4);
+
+    let mut buf = [0; 4];
+    a.serialize(&mut Serializer::new(&mut buf)).unwrap();
+    assert_eq!(buf, [192, 0, 2, 1]);
+
+    let mut parser = Parser::new(&buf);
+    parser.set_rdata(Some(0..4));
+    let parsed = Record::parse_rdata(&mut parser, &Type::A).unwrap().unwrap();
+    assert!(matches!(parsed, Record::A { address: [192, 0, 2, 1] }));
+
+    // only variants tied to a type are parsed
+    let mut parser = Parser::new(&buf);
+    assert!(Record::parse_rdata(&mut parser, &Type::MX).unwrap().is_none());
+
+    let other = Record::Other {
+        r#type: Type::Unknown(65280),
+        data: Cow::Borrowed(b"xyz"),
+    };
+    assert_eq!(Type::from(&other), Type::Unknown(65280));
+    assert_eq!(other.size(), 3);
+}
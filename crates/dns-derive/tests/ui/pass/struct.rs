@@ -0,0 +1,53 @@
+use std::borrow::Cow;
+
+use dns::proto::{Parse, Parser, Serialize, Serializer, Size};
+use dns::{DomainName, Type};
+
+#[derive(Debug, Parse, Serialize, Size)]
+struct Fields<'a> {
+    byte: u8,
+    short: u16,
+    long: u32,
+    #[dns(u48)]
+    time: u64,
+    #[dns(u16)]
+    r#type: Type,
+    address: [u8; 4],
+    name: DomainName<'a>,
+    #[dns(character_string)]
+    text: Cow<'a, [u8]>,
+    #[dns(u16_prefixed)]
+    mac: &'a [u8],
+    #[dns(remaining)]
+    rest: Cow<'a, [u8]>,
+}
+
+fn main() {
+    let fields = Fields {
+        byte: 1,
+        short: 0x0203,
+        long: 0x0405_0607,
+        time: 0x0809_0a0b_0c0d,
+        r#type: Type::MX,
+        address: [192, 0, 2, 1],
+        name: "example.com".parse().unwrap(),
+        text: Cow::Borrowed(b"hello"),
+        mac: &[0xaa, 0xbb],
+        rest: Cow::Borrowed(b"rest"),
+    };
+    let expected = format!("{:?}", fields);
+
+    let size = fields.size();
+    assert_eq!(size, 1 + 2 + 4 + 6 + 2 + 4 + 13 + 6 + 4 + 4);
+
+    let mut buf = vec![0; size];
+    let len = fields.serialize(&mut Serializer::new(&mut buf)).unwrap();
+    assert_eq!(len, size);
+    assert_eq!(buf[..13], [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13]);
+    assert_eq!(buf[13..15], [0, 15]);
+
+    let mut parser = Parser::new(&buf);
+    parser.set_rdata(Some(0..buf.len()));
+    let parsed = Fields::parse(&mut parser).unwrap();
+    assert_eq!(format!("{:?}", parsed), expected);
+}
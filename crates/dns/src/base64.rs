@@ -0,0 +1,94 @@
+//! Standard base64 with padding, as per [RFC 4648 Section 4](https://www.rfc-editor.org/rfc/rfc4648#section-4),
+//! the encoding of binary rdata in presentation format and of TSIG secrets.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | u32::from(byte) << (16 - 8 * i)
+        });
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Decodes `encoded`, the padding is optional.
+///
+/// Returns `None` for characters outside of the alphabet and for a length that
+/// leaves a single character in the last group.
+pub fn decode(encoded: &str) -> Option<Vec<u8>> {
+    let encoded = encoded.trim_end_matches('=');
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+
+    for c in encoded.bytes() {
+        let value = ALPHABET.iter().position(|&a| a == c)?;
+
+        bits = (bits << 6) | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            decoded.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+
+    if count >= 6 {
+        return None;
+    }
+
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The test vectors of [RFC 4648 Section 10](https://www.rfc-editor.org/rfc/rfc4648#section-10).
+    const VECTORS: [(&str, &str); 7] = [
+        ("", ""),
+        ("f", "Zg=="),
+        ("fo", "Zm8="),
+        ("foo", "Zm9v"),
+        ("foob", "Zm9vYg=="),
+        ("fooba", "Zm9vYmE="),
+        ("foobar", "Zm9vYmFy"),
+    ];
+
+    #[test]
+    fn test_vectors() {
+        for (data, encoded) in VECTORS {
+            assert_eq!(encode(data.as_bytes()), encoded);
+            assert_eq!(decode(encoded).unwrap(), data.as_bytes());
+            assert_eq!(
+                decode(encoded.trim_end_matches('=')).unwrap(),
+                data.as_bytes()
+            );
+        }
+
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode(&encode(&data)).unwrap(), data);
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(decode("Zm9v YmFy"), None);
+        assert_eq!(decode("Zm9v-mFy"), None);
+        assert_eq!(decode("Zm9vY"), None);
+    }
+}
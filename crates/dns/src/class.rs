@@ -1,3 +1,8 @@
+use core::fmt::Display;
+use core::str::FromStr;
+
+use crate::proto::{ParseError, ParseErrorKind};
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[repr(u16)]
 pub enum Class {
@@ -45,3 +50,38 @@ impl From<Class> for u16 {
         }
     }
 }
+
+/// Mnemonic of the class as per [RFC 3597 Section 5](https://www.rfc-editor.org/rfc/rfc3597#section-5),
+/// classes without one are written as `CLASS` followed by the code.
+impl Display for Class {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::IN => f.write_str("IN"),
+            Self::CH => f.write_str("CH"),
+            Self::HS => f.write_str("HS"),
+            Self::NONE => f.write_str("NONE"),
+            Self::ANY => f.write_str("ANY"),
+            Self::OPT(code) => write!(f, "CLASS{}", code),
+        }
+    }
+}
+
+/// Reads a mnemonic or the generic `CLASS` form, ignoring case.
+impl FromStr for Class {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "IN" => Ok(Self::IN),
+            "CH" => Ok(Self::CH),
+            "HS" => Ok(Self::HS),
+            "NONE" => Ok(Self::NONE),
+            "ANY" => Ok(Self::ANY),
+            other => other
+                .strip_prefix("CLASS")
+                .and_then(|code| code.parse::<u16>().ok())
+                .map(Self::from)
+                .ok_or(ParseError::new(ParseErrorKind::UnknownMnemonic, 0)),
+        }
+    }
+}
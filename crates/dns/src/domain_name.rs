@@ -1,30 +1,258 @@
-use std::fmt::Display;
-use std::str;
+use alloc::borrow::Cow;
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Display;
+use core::str::{self, FromStr};
+#[cfg(feature = "std")]
+use std::{
+    format,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    string::ToString,
+};
 
-use crate::proto::{Parse, ParseError, Parser, Serialize, SerializeError, Serializer};
+use crate::proto::{
+    Parse, ParseError, ParseErrorKind, Parser, Serialize, SerializeError, Serializer,
+};
 
-#[derive(Debug)]
+/// Octets a name may take up on the wire, as per
+/// [RFC 1035 Section 3.1](https://www.rfc-editor.org/rfc/rfc1035#section-3.1).
+const MAX_SIZE: usize = 255;
+
+#[derive(Debug, Clone, Default)]
 pub struct DomainName<'a> {
-    pub labels: Vec<&'a str>,
+    pub labels: Vec<Cow<'a, str>>,
+
+    /// The compressed wire encoding this name was parsed from, only kept when the
+    /// parser preserves compression.
+    origin: Option<&'a [u8]>,
 }
 
 impl<'a> DomainName<'a> {
+    /// Size of the uncompressed wire encoding, including the terminating root label.
     pub fn size(&self) -> usize {
-        self.labels.iter().map(|l| l.len() + 1).sum()
+        self.labels.iter().map(|l| l.len() + 1).sum::<usize>() + 1
+    }
+
+    /// Copies borrowed labels, detaching the name from the message it was parsed from.
+    pub fn into_owned(self) -> DomainName<'static> {
+        DomainName {
+            labels: self
+                .labels
+                .into_iter()
+                .map(|label| Cow::Owned(label.into_owned()))
+                .collect(),
+            origin: None,
+        }
+    }
+
+    /// Checks whether this name is equal to or below `suffix`, comparing labels
+    /// case-insensitively.
+    pub fn ends_with(&self, suffix: &DomainName) -> bool {
+        self.labels.len() >= suffix.labels.len()
+            && self
+                .labels
+                .iter()
+                .rev()
+                .zip(suffix.labels.iter().rev())
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+
+    /// Checks whether the compressed `wire` encoding, with its pointers resolved against
+    /// the bytes already `written`, still spells out exactly this name.
+    fn resolves_to(&self, wire: &[u8], written: &[u8]) -> bool {
+        let mut labels = self.labels.iter();
+        let (mut buf, mut pos, mut limit) = (wire, 0, written.len());
+
+        loop {
+            let Some(&len) = buf.get(pos) else {
+                return false;
+            };
+
+            match len as usize {
+                0 => return labels.next().is_none(),
+                len if len & 0xC0 == 0xC0 => {
+                    let Some(&low) = buf.get(pos + 1) else {
+                        return false;
+                    };
+
+                    let pointer = ((len & 0x3F) << 8) | low as usize;
+                    if pointer >= limit {
+                        return false;
+                    }
+
+                    (buf, pos, limit) = (written, pointer, pointer);
+                }
+                len => {
+                    let Some(label) = buf.get(pos + 1..pos + 1 + len) else {
+                        return false;
+                    };
+
+                    if labels.next().map(|l| l.as_bytes()) != Some(label) {
+                        return false;
+                    }
+
+                    pos += len + 1;
+                }
+            }
+        }
     }
 }
 
+/// Reverse mapping names as per [RFC 1035 Section 3.5](https://www.rfc-editor.org/rfc/rfc1035#section-3.5)
+/// and [RFC 3596 Section 2.5](https://www.rfc-editor.org/rfc/rfc3596#section-2.5).
+#[cfg(feature = "std")]
+impl DomainName<'_> {
+    /// The in-addr.arpa or ip6.arpa name for looking up the PTR record of `address`.
+    pub fn reverse_from(address: IpAddr) -> DomainName<'static> {
+        let mut labels: Vec<Cow<str>> = match address {
+            IpAddr::V4(address) => address
+                .octets()
+                .iter()
+                .rev()
+                .map(|octet| Cow::Owned(octet.to_string()))
+                .collect(),
+            IpAddr::V6(address) => address
+                .octets()
+                .iter()
+                .rev()
+                .flat_map(|octet| [octet & 0x0F, octet >> 4])
+                .map(|nibble| Cow::Owned(format!("{:x}", nibble)))
+                .collect(),
+        };
+
+        let suffix = match address {
+            IpAddr::V4(_) => "in-addr",
+            IpAddr::V6(_) => "ip6",
+        };
+        labels.extend([Cow::Borrowed(suffix), Cow::Borrowed("arpa")]);
+
+        DomainName {
+            labels,
+            origin: None,
+        }
+    }
+
+    /// The address this in-addr.arpa or ip6.arpa name maps back to, if it names a
+    /// complete address.
+    pub fn to_ip(&self) -> Option<IpAddr> {
+        let (labels, suffix) = self.labels.split_last_chunk::<2>()?;
+        if !suffix[1].eq_ignore_ascii_case("arpa") {
+            return None;
+        }
+
+        if suffix[0].eq_ignore_ascii_case("in-addr") {
+            let mut octets = [0u8; 4];
+            if labels.len() != octets.len() {
+                return None;
+            }
+
+            // octets are written in decimal without leading zeros, which would name
+            // another address in the tree
+            for (octet, label) in octets.iter_mut().rev().zip(labels) {
+                if !label.bytes().all(|b| b.is_ascii_digit())
+                    || (label.len() > 1 && label.starts_with('0'))
+                {
+                    return None;
+                }
+                *octet = label.parse().ok()?;
+            }
+
+            Some(Ipv4Addr::from(octets).into())
+        } else if suffix[0].eq_ignore_ascii_case("ip6") {
+            let mut octets = [0u8; 16];
+            if labels.len() != 2 * octets.len() {
+                return None;
+            }
+
+            for (octet, pair) in octets.iter_mut().rev().zip(labels.chunks(2)) {
+                let [low, high] = pair else {
+                    return None;
+                };
+                let nibble = |label: &str| match label.as_bytes() {
+                    [digit] => (*digit as char).to_digit(16),
+                    _ => None,
+                };
+                *octet = (nibble(high)? << 4 | nibble(low)?) as u8;
+            }
+
+            Some(Ipv6Addr::from(octets).into())
+        } else {
+            None
+        }
+    }
+}
+
+/// Names compare case-insensitively as per [RFC 4343](https://www.rfc-editor.org/rfc/rfc4343)
+impl PartialEq for DomainName<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.labels.len() == other.labels.len() && self.ends_with(other)
+    }
+}
+
+impl Eq for DomainName<'_> {}
+
 impl<'a> From<Vec<&'a str>> for DomainName<'a> {
     fn from(labels: Vec<&'a str>) -> Self {
-        Self { labels }
+        Self {
+            labels: labels.into_iter().map(Cow::Borrowed).collect(),
+            origin: None,
+        }
     }
 }
 
+/// Reads a name in presentation format, with or without the trailing root label.
+impl FromStr for DomainName<'static> {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.strip_suffix('.').unwrap_or(s);
+        if name.is_empty() {
+            return Ok(Self::default());
+        }
+
+        let mut labels = Vec::new();
+        let mut offset = 0;
+        for label in name.split('.') {
+            if label.is_empty() || label.len() > 63 {
+                return Err(ParseError::new(
+                    ParseErrorKind::InvalidLabelLength(label.len()),
+                    offset,
+                ));
+            }
+
+            labels.push(Cow::Owned(label.to_owned()));
+            offset += label.len() + 1;
+        }
+
+        let name = Self {
+            labels,
+            origin: None,
+        };
+        if name.size() > MAX_SIZE {
+            return Err(ParseError::new(ParseErrorKind::NameTooLong(name.size()), 0));
+        }
+
+        Ok(name)
+    }
+}
+
+/// Reads the labels of a name, following compression pointers. Pointers have to point
+/// before the labels read so far, which rules out loops, and are followed iteratively
+/// so that chains of them can't exhaust the stack.
 impl<'a> Parse<'a> for Vec<&'a str> {
     fn parse(parser: &mut Parser<'a>) -> Result<Self, ParseError> {
         let mut labels = vec![];
+        let mut size = 1;
+
+        // where the labels read so far start, and where to go on after the name if
+        // it was compressed
+        let mut limit = parser.position();
+        let mut end = None;
 
         loop {
+            let start = parser.position();
             let len = parser.consume_u8()? as usize;
 
             match len {
@@ -32,38 +260,69 @@ impl<'a> Parse<'a> for Vec<&'a str> {
                 len if len & 0xC0 == 0xC0 => {
                     let pointer: u16 = (((len & 0x3F) as u16) << 8) | parser.consume_u8()? as u16;
 
-                    let pos = parser.position();
-
-                    parser.seek(pointer.into())?;
-
-                    labels.extend(Self::parse(parser)?);
+                    if usize::from(pointer) >= limit {
+                        return Err(ParseError::new(
+                            ParseErrorKind::InvalidPointer(pointer),
+                            start,
+                        ));
+                    }
 
-                    parser.seek(pos)?;
-
-                    break;
+                    end.get_or_insert(parser.position());
+                    limit = pointer.into();
+                    parser.seek(limit)?;
                 }
                 1..=63 => {
+                    size += len + 1;
+                    if size > MAX_SIZE {
+                        return Err(ParseError::new(ParseErrorKind::NameTooLong(size), start));
+                    }
+
                     let label = str::from_utf8(parser.consume_bytes(len)?)
-                        .map_err(|_| ParseError::InvalidUtf8)?;
+                        .map_err(|_| ParseError::new(ParseErrorKind::InvalidUtf8, start + 1))?;
 
                     labels.push(label);
                 }
-                _ => return Err(ParseError::InvalidLabelLength(len)),
+                _ => {
+                    return Err(ParseError::new(
+                        ParseErrorKind::InvalidLabelLength(len),
+                        start,
+                    ));
+                }
             }
         }
 
+        if let Some(end) = end {
+            parser.seek(end)?;
+        }
+
         Ok(labels)
     }
 }
 
 impl<'a> Parse<'a> for DomainName<'a> {
     fn parse(parser: &mut Parser<'a>) -> Result<Self, ParseError> {
-        Ok(Vec::<&str>::parse(parser)?.into())
+        let start = parser.position();
+        let mut name: Self = Vec::<&str>::parse(parser)?.into();
+        let end = parser.position();
+
+        if parser.preserves_compression() && end - start < name.size() {
+            parser.seek(start)?;
+            name.origin = Some(parser.consume_bytes(end - start)?);
+        }
+
+        Ok(name)
     }
 }
 
 impl<'a> Serialize<'a> for DomainName<'a> {
     fn serialize(self, serializer: &mut Serializer<'a>) -> Result<usize, SerializeError> {
+        if let Some(wire) = self.origin
+            && self.resolves_to(wire, serializer.written())
+        {
+            serializer.write_bytes(wire)?;
+            return Ok(serializer.position());
+        }
+
         for label in self.labels {
             serializer.write_u8(label.len() as u8)?;
             serializer.write_bytes(label.as_bytes())?;
@@ -74,14 +333,8 @@ impl<'a> Serialize<'a> for DomainName<'a> {
     }
 }
 
-impl Default for DomainName<'_> {
-    fn default() -> Self {
-        Self { labels: Vec::new() }
-    }
-}
-
 impl Display for DomainName<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let mut result = String::with_capacity(self.size());
 
         for (i, part) in self.labels.iter().enumerate() {
@@ -96,3 +349,178 @@ impl Display for DomainName<'_> {
         f.write_str(&result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use alloc::string::ToString;
+
+    use super::*;
+    use crate::Packet;
+
+    /// A response for www.example.com, a CNAME to web.example.com and its address, with
+    /// every name after the question compressed.
+    const RESPONSE: &[u8] = &[
+        0x12, 0x34, 0x81, 0x80, 0, 1, 0, 2, 0, 0, 0, 0, //
+        3, b'w', b'w', b'w', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+        0, 1, 0, 1, //
+        0xC0, 12, 0, 5, 0, 1, 0, 0, 1, 44, 0, 6, 3, b'w', b'e', b'b', 0xC0, 16, //
+        0xC0, 45, 0, 1, 0, 1, 0, 0, 1, 44, 0, 4, 192, 0, 2, 1,
+    ];
+
+    fn parse(buf: &[u8]) -> Packet<'_> {
+        Packet::parse(&mut Parser::new(buf).preserve_compression(true)).unwrap()
+    }
+
+    fn serialize(packet: Packet) -> Vec<u8> {
+        let mut buf = vec![0; 512];
+        let len = packet.serialize(&mut Serializer::new(&mut buf)).unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    fn owner(record: &crate::ResourceRecord) -> String {
+        match record {
+            crate::ResourceRecord::Record { name, .. } => name.to_string(),
+            _ => String::new(),
+        }
+    }
+
+    #[test]
+    fn compressed_message_round_trips() {
+        let packet = parse(RESPONSE);
+        assert_eq!(owner(&packet.answers[0]), "www.example.com.");
+        assert_eq!(owner(&packet.answers[1]), "web.example.com.");
+
+        assert_eq!(serialize(packet), RESPONSE);
+    }
+
+    #[test]
+    fn pointer_that_no_longer_resolves_is_expanded() {
+        // without the CNAME the pointer to web.example.com points into the question
+        let mut packet = parse(RESPONSE);
+        packet.answers.remove(0);
+        packet.header.ancount = 1;
+
+        // the CNAME record took 18 octets, the name takes 17 instead of 2
+        let buf = serialize(packet);
+        assert_eq!(buf.len(), RESPONSE.len() - 18 + 15);
+        let packet = Packet::parse(&mut Parser::new(&buf)).unwrap();
+        assert_eq!(owner(&packet.answers[0]), "web.example.com.");
+
+        // a name changed after parsing isn't written with its old encoding
+        let mut packet = parse(RESPONSE);
+        if let crate::ResourceRecord::Record { name, .. } = &mut packet.answers[1] {
+            name.labels[0] = Cow::Borrowed("www");
+        }
+        let buf = serialize(packet);
+        let packet = Packet::parse(&mut Parser::new(&buf)).unwrap();
+        assert_eq!(owner(&packet.answers[1]), "www.example.com.");
+    }
+
+    #[test]
+    fn long_pointer_chains_are_followed_iteratively() {
+        // a label, then pointers that each point to the one before
+        let mut buf = vec![1, b'a', 0];
+        let mut previous = 0u16;
+        while buf.len() < 0x3FFE {
+            let position = buf.len() as u16;
+            buf.extend_from_slice(&(0xC000 | previous).to_be_bytes());
+            previous = position;
+        }
+
+        let mut parser = Parser::new(&buf);
+        parser.seek(previous.into()).unwrap();
+        let name = DomainName::parse(&mut parser).unwrap();
+        assert_eq!(name.to_string(), "a.");
+        assert_eq!(parser.remaining(), 0);
+    }
+
+    #[test]
+    fn invalid_pointers() {
+        let parse = |buf: &[u8], start: usize| {
+            let mut parser = Parser::new(buf);
+            parser.seek(start).unwrap();
+            DomainName::parse(&mut parser).map(|name| name.to_string())
+        };
+
+        // forward, to itself and back into the labels of the same name
+        assert!(parse(&[0xC0, 2, 0], 0).is_err());
+        assert!(parse(&[0xC0, 0], 0).is_err());
+        assert!(parse(&[1, b'a', 0xC0, 0], 0).is_err());
+        assert!(parse(&[0, 1, b'a', 0xC0, 0], 1).is_ok());
+    }
+
+    #[test]
+    fn reverse_names_round_trip() {
+        let addresses: [IpAddr; 4] = [
+            Ipv4Addr::new(192, 0, 2, 1).into(),
+            Ipv4Addr::new(10, 0, 0, 0).into(),
+            Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).into(),
+            Ipv6Addr::UNSPECIFIED.into(),
+        ];
+        for address in addresses {
+            let name = DomainName::reverse_from(address);
+            assert_eq!(name.to_ip(), Some(address), "{}", name);
+            assert_eq!(
+                name.to_string().parse::<DomainName>().unwrap().to_ip(),
+                Some(address)
+            );
+        }
+
+        assert_eq!(
+            DomainName::reverse_from(Ipv4Addr::new(192, 0, 2, 1).into()).to_string(),
+            "1.2.0.192.in-addr.arpa."
+        );
+        assert_eq!(
+            DomainName::reverse_from(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0xab).into())
+                .to_string(),
+            "b.a.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa."
+        );
+    }
+
+    #[test]
+    fn reverse_names_of_no_address() {
+        let ip = |name: &str| name.parse::<DomainName>().unwrap().to_ip();
+
+        assert_eq!(
+            ip("1.0.0.10.IN-ADDR.ARPA"),
+            Some(Ipv4Addr::new(10, 0, 0, 1).into())
+        );
+        assert_eq!(ip("01.0.0.10.in-addr.arpa"), None);
+        assert_eq!(ip("1.00.0.10.in-addr.arpa"), None);
+        assert_eq!(ip("256.0.0.10.in-addr.arpa"), None);
+        assert_eq!(ip("+1.0.0.10.in-addr.arpa"), None);
+        assert_eq!(ip("0.0.10.in-addr.arpa"), None);
+        assert_eq!(ip("1.0.0.0.10.in-addr.arpa"), None);
+        assert_eq!(ip("1.0.0.10.in-addr.example"), None);
+
+        let ip6 = "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2";
+        assert!(ip(&format!("{}.ip6.arpa", ip6)).is_some());
+        assert_eq!(ip(&format!("{}.ip6.arpa", &ip6[2..])), None);
+        assert_eq!(ip(&format!("0.{}.ip6.arpa", ip6)), None);
+        assert_eq!(ip(&format!("g.{}.ip6.arpa", &ip6[2..])), None);
+        assert_eq!(ip(&format!("01.{}.ip6.arpa", &ip6[2..])), None);
+    }
+
+    #[test]
+    fn names_are_at_most_255_octets() {
+        // 4 labels of 63 octets take up 257 octets, 3 of them and one of 61 take 255
+        let mut buf = Vec::new();
+        for len in [63, 63, 63, 61] {
+            buf.push(len);
+            buf.extend(core::iter::repeat_n(b'a', usize::from(len)));
+        }
+        buf.push(0);
+        let name = DomainName::parse(&mut Parser::new(&buf)).unwrap();
+        assert_eq!(name.size(), 255);
+
+        buf[3 * 64] = 63;
+        buf.splice(3 * 64 + 1..3 * 64 + 1, [b'a', b'a']);
+        let err = DomainName::parse(&mut Parser::new(&buf)).unwrap_err();
+        assert!(matches!(err.kind, ParseErrorKind::NameTooLong(257)));
+
+        let text = vec!["a".repeat(63); 4].join(".");
+        assert!(text.parse::<DomainName>().is_err());
+    }
+}
@@ -0,0 +1,493 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use log::{debug, warn};
+
+use crate::{
+    header::{Header, OpCode, QR, RCode},
+    proto::{Parse, ParseError, ParseErrorKind, Parser, Serialize, SerializeError, Serializer},
+};
+
+/// DNS stateful operations message layout as per [RFC 8490 Section 5.4](https://www.rfc-editor.org/rfc/rfc8490#section-5.4)
+///
+/// ```text
+/// +---------------------+
+/// |        Header       | all counts zero
+/// +---------------------+
+/// |     Primary TLV     |
+/// +---------------------+
+/// |   Additional TLVs   |
+/// +---------------------+
+/// ```
+///
+/// A message with a message id of zero is unidirectional and never answered.
+#[derive(Debug)]
+pub struct Dso<'a> {
+    pub header: Header,
+    pub tlvs: Vec<Tlv<'a>>,
+}
+
+impl<'a> Dso<'a> {
+    pub fn new(id: u16, tlvs: Vec<Tlv<'a>>) -> Self {
+        let mut header = Header {
+            id,
+            ..Default::default()
+        };
+        header.set_opcode(OpCode::DSO);
+
+        Self { header, tlvs }
+    }
+
+    /// The response to this message, carrying `tlvs` and `rcode`.
+    pub fn response(&self, rcode: RCode, tlvs: Vec<Tlv<'a>>) -> Self {
+        let mut response = Self::new(self.header.id, tlvs);
+        response.header.set_qr(QR::Response);
+        response.header.set_rcode(rcode);
+        response
+    }
+
+    pub fn is_unidirectional(&self) -> bool {
+        self.header.id == 0
+    }
+
+    pub fn primary(&self) -> Option<&Tlv<'a>> {
+        self.tlvs.first()
+    }
+}
+
+impl<'a> Parse<'a> for Dso<'a> {
+    fn parse(parser: &mut Parser<'a>) -> Result<Self, ParseError> {
+        let start = parser.position();
+        let header = Header::parse(parser)?;
+
+        if header.opcode() != OpCode::DSO
+            || header.qdcount != 0
+            || header.ancount != 0
+            || header.nscount != 0
+            || header.arcount != 0
+        {
+            return Err(ParseError::new(ParseErrorKind::FormatError, start));
+        }
+
+        let mut tlvs = Vec::new();
+        while parser.remaining() > 0 {
+            tlvs.push(Tlv::parse(parser)?);
+        }
+
+        Ok(Self { header, tlvs })
+    }
+}
+
+impl<'a> Serialize<'a> for Dso<'a> {
+    fn serialize(self, serializer: &mut Serializer<'a>) -> Result<usize, SerializeError> {
+        self.header.serialize(serializer)?;
+
+        for tlv in self.tlvs {
+            tlv.serialize(serializer)?;
+        }
+
+        Ok(serializer.position())
+    }
+}
+
+/// DSO TLV layout as per [RFC 8490 Section 5.4.4](https://www.rfc-editor.org/rfc/rfc8490#section-5.4.4)
+///
+/// ```text
+///   0  1  2  3  4  5  6  7  8  9 10 11 12 13 14 15
+/// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+/// |                   DSO-TYPE                    |
+/// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+/// |                  DSO-LENGTH                   |
+/// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+/// /                   DSO-DATA                    /
+/// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+/// ```
+#[derive(Debug)]
+pub enum Tlv<'a> {
+    /// [RFC 8490 Section 7.1](https://www.rfc-editor.org/rfc/rfc8490#section-7.1), both in milliseconds
+    Keepalive {
+        inactivity_timeout: u32,
+        keepalive_interval: u32,
+    },
+
+    /// [RFC 8490 Section 7.2](https://www.rfc-editor.org/rfc/rfc8490#section-7.2), in milliseconds
+    RetryDelay {
+        retry_delay: u32,
+    },
+
+    /// [RFC 8490 Section 7.3](https://www.rfc-editor.org/rfc/rfc8490#section-7.3)
+    EncryptionPadding {
+        padding: &'a [u8],
+    },
+
+    Unknown {
+        r#type: u16,
+        data: &'a [u8],
+    },
+}
+
+impl Tlv<'_> {
+    pub fn r#type(&self) -> u16 {
+        match self {
+            Self::Keepalive { .. } => 1,
+            Self::RetryDelay { .. } => 2,
+            Self::EncryptionPadding { .. } => 3,
+            Self::Unknown { r#type, .. } => *r#type,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Keepalive { .. } => size_of::<u32>() + size_of::<u32>(),
+            Self::RetryDelay { .. } => size_of::<u32>(),
+            Self::EncryptionPadding { padding } => padding.len(),
+            Self::Unknown { data, .. } => data.len(),
+        }
+    }
+}
+
+impl<'a> Parse<'a> for Tlv<'a> {
+    fn parse(parser: &mut Parser<'a>) -> Result<Self, ParseError> {
+        let r#type = parser.consume_u16()?;
+        let len = parser.consume_u16()?.into();
+        let start = parser.position();
+
+        let tlv = match r#type {
+            1 if len == 8 => Self::Keepalive {
+                inactivity_timeout: parser.consume_u32()?,
+                keepalive_interval: parser.consume_u32()?,
+            },
+            2 if len == 4 => Self::RetryDelay {
+                retry_delay: parser.consume_u32()?,
+            },
+            1 | 2 => return Err(ParseError::new(ParseErrorKind::FormatError, start)),
+            3 => Self::EncryptionPadding {
+                padding: parser.consume_bytes(len)?,
+            },
+            r#type => Self::Unknown {
+                r#type,
+                data: parser.consume_bytes(len)?,
+            },
+        };
+
+        Ok(tlv)
+    }
+}
+
+impl<'a> Serialize<'a> for Tlv<'a> {
+    fn serialize(self, serializer: &mut Serializer<'a>) -> Result<usize, SerializeError> {
+        serializer.write_u16(self.r#type())?;
+        serializer.write_u16(self.size() as u16)?;
+
+        match self {
+            Self::Keepalive {
+                inactivity_timeout,
+                keepalive_interval,
+            } => {
+                serializer.write_u32(inactivity_timeout)?;
+                serializer.write_u32(keepalive_interval)?;
+            }
+            Self::RetryDelay { retry_delay } => {
+                serializer.write_u32(retry_delay)?;
+            }
+            Self::EncryptionPadding { padding: data } | Self::Unknown { data, .. } => {
+                serializer.write_bytes(data)?;
+            }
+        }
+
+        Ok(serializer.position())
+    }
+}
+
+/// What the connection should do after a DSO message was handled.
+#[derive(Debug)]
+pub enum Outcome<'a> {
+    Respond(Dso<'a>),
+    Ignore,
+    Close,
+}
+
+/// Server side state of a DSO session on a single connection, as per
+/// [RFC 8490 Section 6](https://www.rfc-editor.org/rfc/rfc8490#section-6).
+///
+/// All times are in milliseconds, `now` is measured from an arbitrary fixed point.
+#[derive(Debug)]
+pub struct Session {
+    established: bool,
+    inactivity_timeout: u32,
+    keepalive_interval: u32,
+    last_activity: u64,
+    retry_delay_sent: bool,
+
+    /// Message id of the last request sent to the client.
+    last_id: u16,
+
+    /// Message id of the Retry Delay request, until the client answers it.
+    outstanding: Option<u16>,
+}
+
+impl Session {
+    /// Retry delay asked of clients that are disconnected for inactivity.
+    const RETRY_DELAY: u32 = 5_000;
+
+    pub fn new(inactivity_timeout: u32, keepalive_interval: u32, now: u64) -> Self {
+        Self {
+            established: false,
+            inactivity_timeout,
+            keepalive_interval,
+            last_activity: now,
+            retry_delay_sent: false,
+            last_id: 0,
+            outstanding: None,
+        }
+    }
+
+    pub fn is_established(&self) -> bool {
+        self.established
+    }
+
+    /// Records traffic other than DSO messages on the connection.
+    ///
+    /// A client that became active again is sent another retry delay when it idles
+    /// past the timeout once more.
+    pub fn activity(&mut self, now: u64) {
+        self.last_activity = now;
+        self.retry_delay_sent = false;
+    }
+
+    /// Handles a DSO request received from the client.
+    pub fn handle<'a>(&mut self, request: &Dso<'a>, now: u64) -> Outcome<'a> {
+        self.activity(now);
+
+        if request.header.is_response() {
+            // the client closes the connection after acknowledging the retry delay, as
+            // per RFC 8490 Section 7.2.1
+            if self.outstanding == Some(request.header.id) {
+                self.outstanding = None;
+                return Outcome::Ignore;
+            }
+
+            warn!("unexpected dso response {}", request.header.id);
+            return Outcome::Close;
+        }
+
+        match (request.primary(), request.is_unidirectional()) {
+            (
+                Some(Tlv::Keepalive {
+                    inactivity_timeout,
+                    keepalive_interval,
+                }),
+                false,
+            ) => {
+                debug!(
+                    "client asked for inactivity timeout {}ms and keepalive interval {}ms",
+                    inactivity_timeout, keepalive_interval
+                );
+
+                self.established = true;
+
+                Outcome::Respond(request.response(
+                    RCode::NoError,
+                    vec![Tlv::Keepalive {
+                        inactivity_timeout: self.inactivity_timeout,
+                        keepalive_interval: self.keepalive_interval,
+                    }],
+                ))
+            }
+            // unknown primary tlvs are answered with DSOTYPENI, unless they need no answer
+            (Some(Tlv::Unknown { .. }), false) => {
+                Outcome::Respond(request.response(RCode::DSOTYPENI, Vec::new()))
+            }
+            (Some(Tlv::Unknown { .. }), true) => Outcome::Ignore,
+            // keepalive as unidirectional message, retry delay and padding as primary
+            // tlv and messages without tlvs are all protocol errors
+            _ => Outcome::Close,
+        }
+    }
+
+    /// Checks the inactivity timeout, as per [RFC 8490 Section 6.4](https://www.rfc-editor.org/rfc/rfc8490#section-6.4).
+    ///
+    /// Once the timeout has passed the client is asked to reconnect later, if it is
+    /// still connected after twice the timeout the connection is closed.
+    pub fn poll(&mut self, now: u64) -> Outcome<'static> {
+        if !self.established || self.inactivity_timeout == u32::MAX {
+            return Outcome::Ignore;
+        }
+
+        let idle = now.saturating_sub(self.last_activity);
+        let timeout = u64::from(self.inactivity_timeout);
+
+        if idle > 2 * timeout.max(5_000) {
+            Outcome::Close
+        } else if idle > timeout && !self.retry_delay_sent {
+            self.retry_delay_sent = true;
+
+            // a retry delay is a request the client has to answer, as per RFC 8490
+            // Section 7.2
+            let id = self.next_id();
+            self.outstanding = Some(id);
+
+            Outcome::Respond(Dso::new(
+                id,
+                vec![Tlv::RetryDelay {
+                    retry_delay: Self::RETRY_DELAY,
+                }],
+            ))
+        } else {
+            Outcome::Ignore
+        }
+    }
+
+    /// Allocates the message id of a request to the client, skipping zero which marks
+    /// unidirectional messages.
+    fn next_id(&mut self) -> u16 {
+        self.last_id = self.last_id.checked_add(1).unwrap_or(1);
+        self.last_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keepalive(id: u16) -> Dso<'static> {
+        Dso::new(
+            id,
+            vec![Tlv::Keepalive {
+                inactivity_timeout: 0,
+                keepalive_interval: 0,
+            }],
+        )
+    }
+
+    #[test]
+    fn retry_delay_is_a_request() {
+        let mut session = Session::new(1_000, 1_000, 0);
+        assert!(matches!(
+            session.handle(&keepalive(7), 0),
+            Outcome::Respond(_)
+        ));
+
+        let Outcome::Respond(request) = session.poll(1_001) else {
+            panic!("no retry delay after the inactivity timeout");
+        };
+        assert!(!request.is_unidirectional());
+        assert!(!request.header.is_response());
+        assert!(matches!(request.primary(), Some(Tlv::RetryDelay { .. })));
+
+        // sent once only
+        assert!(matches!(session.poll(1_500), Outcome::Ignore));
+
+        let response = request.response(RCode::NoError, Vec::new());
+        assert!(matches!(session.handle(&response, 1_600), Outcome::Ignore));
+
+        // answering it twice is a protocol error
+        assert!(matches!(session.handle(&response, 1_700), Outcome::Close));
+    }
+
+    #[test]
+    fn tlvs_round_trip() {
+        let tlvs = vec![
+            Tlv::Keepalive {
+                inactivity_timeout: 15_000,
+                keepalive_interval: 0xffff_fffe,
+            },
+            Tlv::RetryDelay { retry_delay: 5_000 },
+            Tlv::EncryptionPadding { padding: &[0; 5] },
+            Tlv::Unknown {
+                r#type: 0xf901,
+                data: b"data",
+            },
+            Tlv::Unknown {
+                r#type: 0xf902,
+                data: &[],
+            },
+        ];
+
+        let mut buf = vec![0; 128];
+        let len = Dso::new(3, tlvs)
+            .serialize(&mut Serializer::new(&mut buf))
+            .unwrap();
+        assert_eq!(len, 12 + (4 + 8) + (4 + 4) + (4 + 5) + (4 + 4) + 4);
+
+        let dso = Dso::parse(&mut Parser::new(&buf[..len])).unwrap();
+        assert_eq!(dso.header.id, 3);
+        assert!(matches!(
+            dso.tlvs[..],
+            [
+                Tlv::Keepalive {
+                    inactivity_timeout: 15_000,
+                    keepalive_interval: 0xffff_fffe,
+                },
+                Tlv::RetryDelay { retry_delay: 5_000 },
+                Tlv::EncryptionPadding {
+                    padding: &[0, 0, 0, 0, 0]
+                },
+                Tlv::Unknown {
+                    r#type: 0xf901,
+                    data: b"data",
+                },
+                Tlv::Unknown {
+                    r#type: 0xf902,
+                    data: &[],
+                },
+            ]
+        ));
+    }
+
+    #[test]
+    fn fixed_size_tlvs_with_another_length_are_rejected() {
+        // a keepalive with a single u32 and a retry delay with two
+        for tlv in [
+            &[0, 1, 0, 4, 0, 0, 0, 1][..],
+            &[0, 2, 0, 8, 0, 0, 0, 1, 0, 0, 0, 1],
+        ] {
+            assert!(Tlv::parse(&mut Parser::new(tlv)).is_err());
+        }
+    }
+
+    #[test]
+    fn keepalive_timeout() {
+        let mut session = Session::new(10_000, 1_000, 0);
+
+        // no timeout before the session is established
+        assert!(matches!(session.poll(100_000), Outcome::Ignore));
+
+        session.handle(&keepalive(7), 100_000);
+        assert!(matches!(session.poll(110_000), Outcome::Ignore));
+        assert!(matches!(session.poll(110_001), Outcome::Respond(_)));
+        assert!(matches!(session.poll(120_000), Outcome::Ignore));
+
+        // traffic restarts the timeout, which asks for a retry delay again
+        session.activity(115_000);
+        assert!(matches!(session.poll(125_000), Outcome::Ignore));
+        assert!(matches!(session.poll(125_001), Outcome::Respond(_)));
+
+        // the connection is closed at twice the timeout
+        assert!(matches!(session.poll(135_000), Outcome::Ignore));
+        assert!(matches!(session.poll(135_001), Outcome::Close));
+    }
+
+    #[test]
+    fn short_and_infinite_keepalive_timeouts() {
+        // closing waits for at least twice five seconds
+        let mut session = Session::new(1_000, 1_000, 0);
+        session.handle(&keepalive(7), 0);
+        assert!(matches!(session.poll(1_001), Outcome::Respond(_)));
+        assert!(matches!(session.poll(10_000), Outcome::Ignore));
+        assert!(matches!(session.poll(10_001), Outcome::Close));
+
+        let mut session = Session::new(u32::MAX, 1_000, 0);
+        session.handle(&keepalive(7), 0);
+        assert!(matches!(session.poll(u64::MAX), Outcome::Ignore));
+    }
+
+    #[test]
+    fn unsolicited_response_closes() {
+        let mut session = Session::new(1_000, 1_000, 0);
+        session.handle(&keepalive(7), 0);
+
+        let response = keepalive(9).response(RCode::NoError, Vec::new());
+        assert!(matches!(session.handle(&response, 10), Outcome::Close));
+    }
+}
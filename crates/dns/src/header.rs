@@ -1,6 +1,8 @@
 use log::warn;
 
-use crate::proto::{Parse, ParseError, Parser, Serialize, SerializeError, Serializer};
+use crate::proto::{
+    Parse, ParseError, ParseErrorKind, Parser, Serialize, SerializeError, Serializer,
+};
 
 /// DNS header field layout as per [RFC 1035 Section 4.1.1](https://www.rfc-editor.org/rfc/rfc1035#section-4.1.1)
 ///
@@ -20,7 +22,8 @@ use crate::proto::{Parse, ParseError, Parser, Serialize, SerializeError, Seriali
 /// |                    ARCOUNT                    |
 /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
     pub id: u16,
     pub flags: Flags,
@@ -30,11 +33,106 @@ pub struct Header {
     pub arcount: u16,
 }
 
-impl<'a> Parse<'_> for Header {
+impl Header {
+    /// Size of the header in wire format.
+    pub const SIZE: usize = 6 * size_of::<u16>();
+
+    pub fn qr(&self) -> QR {
+        self.flags.qr
+    }
+
+    pub fn set_qr(&mut self, qr: QR) {
+        self.flags.qr = qr;
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.flags.qr == QR::Response
+    }
+
+    pub fn opcode(&self) -> OpCode {
+        self.flags.opcode
+    }
+
+    pub fn set_opcode(&mut self, opcode: OpCode) {
+        self.flags.opcode = opcode;
+    }
+
+    /// Authoritative answer (AA)
+    pub fn authoritative(&self) -> bool {
+        self.flags.aa
+    }
+
+    pub fn set_authoritative(&mut self, aa: bool) {
+        self.flags.aa = aa;
+    }
+
+    /// Truncation (TC)
+    pub fn truncated(&self) -> bool {
+        self.flags.tc
+    }
+
+    pub fn set_truncated(&mut self, tc: bool) {
+        self.flags.tc = tc;
+    }
+
+    /// Recursion desired (RD)
+    pub fn recursion_desired(&self) -> bool {
+        self.flags.rd
+    }
+
+    pub fn set_recursion_desired(&mut self, rd: bool) {
+        self.flags.rd = rd;
+    }
+
+    /// Recursion available (RA)
+    pub fn recursion_available(&self) -> bool {
+        self.flags.ra
+    }
+
+    pub fn set_recursion_available(&mut self, ra: bool) {
+        self.flags.ra = ra;
+    }
+
+    /// Authentic data (AD)
+    pub fn authentic_data(&self) -> bool {
+        self.flags.ad
+    }
+
+    pub fn set_authentic_data(&mut self, ad: bool) {
+        self.flags.ad = ad;
+    }
+
+    /// Checking disabled (CD)
+    pub fn checking_disabled(&self) -> bool {
+        self.flags.cd
+    }
+
+    pub fn set_checking_disabled(&mut self, cd: bool) {
+        self.flags.cd = cd;
+    }
+
+    pub fn rcode(&self) -> RCode {
+        self.flags.rcode
+    }
+
+    pub fn set_rcode(&mut self, rcode: RCode) {
+        self.flags.rcode = rcode;
+    }
+}
+
+impl Parse<'_> for Header {
     fn parse(parser: &mut Parser<'_>) -> Result<Self, ParseError> {
+        let id = parser.consume_u16()?;
+
+        let flags_offset = parser.position();
+        let flags: Flags = parser.consume_u16()?.into();
+        if flags.z {
+            parser.violation(ParseErrorKind::ReservedBitSet, flags_offset)?;
+        }
+
         Ok(Header {
-            id: parser.consume_u16()?,
-            flags: parser.consume_u16()?.into(),
+            id,
+            flags,
             qdcount: parser.consume_u16()?,
             ancount: parser.consume_u16()?,
             nscount: parser.consume_u16()?,
@@ -46,7 +144,7 @@ impl<'a> Parse<'_> for Header {
 impl<'a> Serialize<'a> for Header {
     fn serialize(self, serializer: &mut Serializer<'a>) -> Result<usize, SerializeError> {
         serializer.write_u16(self.id)?;
-        serializer.write_u16(self.flags.into())?;
+        serializer.write_u16(self.flags.try_into()?)?;
         serializer.write_u16(self.qdcount)?;
         serializer.write_u16(self.ancount)?;
         serializer.write_u16(self.nscount)?;
@@ -65,42 +163,55 @@ impl<'a> Serialize<'a> for Header {
 /// |QR|   Opcode  |AA|TC|RD|RA| Z|AD|CD|   RCODE   |
 /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Flags {
-    pub qr: u8,         // 1 bit
-    pub opcode: OpCode, // 4 bits
-    pub aa: u8,         // 1 bit
-    pub tc: u8,         // 1 bit
-    pub rd: u8,         // 1 bit
-    pub ra: u8,         // 1 bit
-    pub z: u8,          // 1 bit
-    pub ad: u8,         // 1 bit
-    pub cd: u8,         // 1 bit
-    pub rcode: RCode,   // 4 bits
+    pub qr: QR,
+    pub opcode: OpCode,
+    pub aa: bool,
+    pub tc: bool,
+    pub rd: bool,
+    pub ra: bool,
+    pub z: bool,
+    pub ad: bool,
+    pub cd: bool,
+    pub rcode: RCode,
 }
 
 impl From<u16> for Flags {
     fn from(value: u16) -> Self {
         Flags {
-            qr: ((value >> 15) & 0b1) as u8,
+            qr: ((value >> 15) & 0b1 == 1).into(),
             opcode: (((value >> 11) & 0b1111) as u8).into(),
-            aa: ((value >> 10) & 0b1) as u8,
-            tc: ((value >> 9) & 0b1) as u8,
-            rd: ((value >> 8) & 0b1) as u8,
-            ra: ((value >> 7) & 0b1) as u8,
-            z: ((value >> 6) & 0b1) as u8,
-            ad: ((value >> 5) & 0b1) as u8,
-            cd: ((value >> 4) & 0b1) as u8,
+            aa: (value >> 10) & 0b1 == 1,
+            tc: (value >> 9) & 0b1 == 1,
+            rd: (value >> 8) & 0b1 == 1,
+            ra: (value >> 7) & 0b1 == 1,
+            z: (value >> 6) & 0b1 == 1,
+            ad: (value >> 5) & 0b1 == 1,
+            cd: (value >> 4) & 0b1 == 1,
             rcode: (value & 0b1111).into(),
         }
     }
 }
 
-impl From<Flags> for u16 {
-    fn from(val: Flags) -> Self {
+impl TryFrom<Flags> for u16 {
+    type Error = SerializeError;
+
+    fn try_from(val: Flags) -> Result<Self, Self::Error> {
+        let opcode = u8::from(val.opcode);
+        if opcode > 0b1111 {
+            return Err(SerializeError::InvalidOpCode(opcode));
+        }
+
+        let rcode = u16::from(val.rcode);
+        if rcode > 0b1111 {
+            return Err(SerializeError::InvalidRCode(rcode));
+        }
+
         let mut value = 0u16;
-        value |= (val.qr as u16) << 15;
-        value |= (u8::from(val.opcode) as u16 & 0b1111) << 11;
+        value |= (bool::from(val.qr) as u16) << 15;
+        value |= (opcode as u16) << 11;
         value |= (val.aa as u16) << 10;
         value |= (val.tc as u16) << 9;
         value |= (val.rd as u16) << 8;
@@ -108,15 +219,44 @@ impl From<Flags> for u16 {
         value |= (val.z as u16) << 6;
         value |= (val.ad as u16) << 5;
         value |= (val.cd as u16) << 4;
-        value |= val.rcode.low() as u16;
-        value
+        value |= rcode;
+        Ok(value)
     }
 }
 
-#[derive(Debug)]
+/// Whether a message is a query or a response, the QR bit of the [`Flags`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum QR {
+    #[default]
+    Query,
+    Response,
+}
+
+impl From<bool> for QR {
+    fn from(value: bool) -> Self {
+        match value {
+            false => Self::Query,
+            true => Self::Response,
+        }
+    }
+}
+
+impl From<QR> for bool {
+    fn from(val: QR) -> Self {
+        match val {
+            QR::Query => false,
+            QR::Response => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum OpCode {
     /// [RFC 1035](https://www.rfc-editor.org/rfc/rfc1035#section-4.1.1)
+    #[default]
     Query,
 
     /// [RFC 1035](https://www.rfc-editor.org/rfc/rfc1035#section-4.1.1)
@@ -163,10 +303,12 @@ impl From<OpCode> for u8 {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u16)]
 pub enum RCode {
     /// [RFC 1035](https://www.rfc-editor.org/rfc/rfc1035#section-4.1.1)
+    #[default]
     NoError,
 
     /// [RFC 1035](https://www.rfc-editor.org/rfc/rfc1035#section-4.1.1)
@@ -234,13 +376,23 @@ pub enum RCode {
     Unknown(u16),
 }
 
+/// Extended rcodes as per [RFC 6891 Section 6.1.3](https://www.rfc-editor.org/rfc/rfc6891#section-6.1.3),
+/// which only fit into the header along with the upper bits in an OPT record.
 impl RCode {
+    /// The lower 4 bits, which go into the header.
     pub fn low(self) -> u8 {
         (u16::from(self) & 0x000F) as u8
     }
 
+    /// The upper 8 bits, which go into the extended rcode field of an OPT record.
     pub fn high(self) -> u8 {
-        (u16::from(self) & 0x0FF0) as u8
+        (u16::from(self) >> 4) as u8
+    }
+
+    /// The rcode made up of the `low` bits from the header and the `high` bits from
+    /// an OPT record.
+    pub fn extended(low: u8, high: u8) -> Self {
+        (u16::from(high) << 4 | u16::from(low & 0x0F)).into()
     }
 }
 
@@ -299,3 +451,105 @@ impl From<RCode> for u16 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sets a flag, reads it back and the bit it is in.
+    type Flag = (fn(&mut Flags), fn(&Flags) -> bool, u16);
+
+    fn round_trip(flags: Flags) -> Flags {
+        Flags::from(u16::try_from(flags).unwrap())
+    }
+
+    #[test]
+    fn each_flag_round_trips() {
+        let bits: [Flag; 8] = [
+            (|f| f.qr = QR::Response, |f| f.qr == QR::Response, 1 << 15),
+            (|f| f.aa = true, |f| f.aa, 1 << 10),
+            (|f| f.tc = true, |f| f.tc, 1 << 9),
+            (|f| f.rd = true, |f| f.rd, 1 << 8),
+            (|f| f.ra = true, |f| f.ra, 1 << 7),
+            (|f| f.z = true, |f| f.z, 1 << 6),
+            (|f| f.ad = true, |f| f.ad, 1 << 5),
+            (|f| f.cd = true, |f| f.cd, 1 << 4),
+        ];
+
+        for (set, get, bit) in bits {
+            let mut flags = Flags::default();
+            set(&mut flags);
+            assert_eq!(u16::try_from(flags).unwrap(), bit);
+
+            let parsed = round_trip(flags);
+            assert!(get(&parsed));
+            assert_eq!(u16::try_from(parsed).unwrap(), bit);
+        }
+
+        assert_eq!(u16::try_from(Flags::default()).unwrap(), 0);
+        assert_eq!(round_trip(Flags::default()).qr, QR::Query);
+    }
+
+    #[test]
+    fn opcode_and_rcode_round_trip() {
+        for opcode in [
+            OpCode::Query,
+            OpCode::Status,
+            OpCode::Notify,
+            OpCode::Update,
+            OpCode::DSO,
+            OpCode::Unknown(15),
+        ] {
+            let flags = Flags {
+                opcode,
+                ..Default::default()
+            };
+            assert_eq!(round_trip(flags).opcode, opcode);
+        }
+
+        for rcode in (0..=15).map(RCode::from) {
+            let flags = Flags {
+                rcode,
+                ..Default::default()
+            };
+            assert_eq!(round_trip(flags).rcode, rcode);
+        }
+    }
+
+    #[test]
+    fn out_of_range_opcode_and_rcode_are_rejected() {
+        let flags = Flags {
+            opcode: OpCode::Unknown(16),
+            ..Default::default()
+        };
+        assert!(matches!(
+            u16::try_from(flags),
+            Err(SerializeError::InvalidOpCode(16))
+        ));
+
+        for rcode in [RCode::BADVERS, RCode::BADSIG, RCode::BADCOOKIE] {
+            let flags = Flags {
+                rcode,
+                ..Default::default()
+            };
+            assert!(matches!(
+                u16::try_from(flags),
+                Err(SerializeError::InvalidRCode(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn extended_rcodes_split_into_header_and_opt_bits() {
+        assert_eq!((RCode::BADVERS.low(), RCode::BADVERS.high()), (0, 1));
+        assert_eq!((RCode::BADCOOKIE.low(), RCode::BADCOOKIE.high()), (7, 1));
+        assert_eq!(
+            (RCode::Unknown(0xABC).low(), RCode::Unknown(0xABC).high()),
+            (0xC, 0xAB)
+        );
+        assert_eq!((RCode::NXDomain.low(), RCode::NXDomain.high()), (3, 0));
+
+        assert_eq!(RCode::extended(7, 1), RCode::BADCOOKIE);
+        assert_eq!(RCode::extended(3, 0), RCode::NXDomain);
+    }
+}
@@ -1,18 +1,52 @@
+#![no_std]
+
+#[cfg(not(feature = "alloc"))]
+compile_error!("the dns crate needs either the `std` or the `alloc` feature");
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+extern crate self as dns;
+
+pub mod base64;
 mod class;
 mod domain_name;
+pub mod dso;
 mod header;
+mod notify;
 mod packet;
 pub mod proto;
 mod question;
+mod record_data;
 mod rr;
+#[cfg(feature = "serde")]
+mod serde_impl;
+mod serial;
+#[cfg(feature = "sig0")]
+pub mod sig0;
+#[cfg(feature = "tsig")]
+pub mod tsig;
 mod r#type;
+mod update;
 
 pub use crate::class::Class;
 pub use crate::domain_name::DomainName;
+pub use crate::header::Flags;
 pub use crate::header::Header;
 pub use crate::header::OpCode;
+pub use crate::header::QR;
 pub use crate::header::RCode;
+pub use crate::notify::Notify;
 pub use crate::packet::Packet;
 pub use crate::question::Question;
+pub use crate::record_data::RecordData;
+pub use crate::record_data::Registry;
+pub use crate::rr::Record;
 pub use crate::rr::ResourceRecord;
+pub use crate::serial::Serial;
+pub use crate::serial::SerialPolicy;
 pub use crate::r#type::Type;
+pub use crate::update::Operation;
+pub use crate::update::Prerequisite;
+pub use crate::update::Update;
@@ -0,0 +1,133 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{
+    DomainName,
+    class::Class,
+    header::{Header, OpCode, QR, RCode},
+    packet::Packet,
+    question::Question,
+    rr::{Record, ResourceRecord},
+    serial::Serial,
+    r#type::Type,
+};
+
+/// DNS NOTIFY message as per [RFC 1996 Section 3](https://www.rfc-editor.org/rfc/rfc1996#section-3)
+///
+/// A primary sends it to its secondaries to announce that a zone changed. The
+/// question names the zone with type SOA, the answer may carry the new SOA as a hint.
+#[derive(Debug)]
+pub struct Notify<'a> {
+    pub header: Header,
+    pub zone: Question<'a>,
+    pub soa: Option<ResourceRecord<'a>>,
+}
+
+impl<'a> Notify<'a> {
+    pub fn new(id: u16, zone: DomainName<'a>, class: Class) -> Self {
+        let mut header = Header {
+            id,
+            ..Default::default()
+        };
+        header.set_opcode(OpCode::Notify);
+        header.set_authoritative(true);
+
+        Self {
+            header,
+            zone: Question {
+                name: zone,
+                r#type: Type::SOA,
+                class,
+            },
+            soa: None,
+        }
+    }
+
+    /// The serial of the SOA hint, if the notify carries one.
+    pub fn serial(&self) -> Option<Serial> {
+        match &self.soa {
+            Some(ResourceRecord::Record {
+                data: Record::SOA { serial, .. },
+                ..
+            }) => Some(*serial),
+            _ => None,
+        }
+    }
+
+    /// The acknowledgement of this notify, as per [RFC 1996 Section 4.7](https://www.rfc-editor.org/rfc/rfc1996#section-4.7).
+    pub fn acknowledge(&self) -> Packet<'a> {
+        let mut header = Header {
+            id: self.header.id,
+            qdcount: 1,
+            ..Default::default()
+        };
+        header.set_qr(QR::Response);
+        header.set_opcode(OpCode::Notify);
+        header.set_authoritative(true);
+        header.set_rcode(RCode::NoError);
+
+        Packet {
+            header,
+            questions: vec![self.zone.clone()],
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        }
+    }
+}
+
+impl<'a> From<Notify<'a>> for Packet<'a> {
+    fn from(notify: Notify<'a>) -> Self {
+        let answers: Vec<_> = notify.soa.into_iter().collect();
+
+        let mut header = notify.header;
+        header.qdcount = 1;
+        header.ancount = answers.len() as u16;
+        header.nscount = 0;
+        header.arcount = 0;
+
+        Packet {
+            header,
+            questions: vec![notify.zone],
+            answers,
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        }
+    }
+}
+
+impl<'a> TryFrom<Packet<'a>> for Notify<'a> {
+    type Error = RCode;
+
+    /// Fails with [`RCode::FormatErr`] unless the packet is a notify with exactly one
+    /// SOA question, as per [RFC 1996 Section 3.7](https://www.rfc-editor.org/rfc/rfc1996#section-3.7).
+    fn try_from(packet: Packet<'a>) -> Result<Self, Self::Error> {
+        if packet.header.opcode() != OpCode::Notify || packet.questions.len() != 1 {
+            return Err(RCode::FormatErr);
+        }
+
+        let zone = packet
+            .questions
+            .into_iter()
+            .next()
+            .ok_or(RCode::FormatErr)?;
+        if zone.r#type != Type::SOA {
+            return Err(RCode::FormatErr);
+        }
+
+        // anything but an SOA for the zone itself is ignored, the answer is only a hint
+        let soa = packet.answers.into_iter().find(|record| {
+            matches!(record, ResourceRecord::Record {
+                name,
+                data: Record::SOA { .. },
+                ..
+            } if *name == zone.name)
+        });
+
+        Ok(Self {
+            header: packet.header,
+            zone,
+            soa,
+        })
+    }
+}
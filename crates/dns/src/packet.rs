@@ -1,5 +1,9 @@
+use alloc::vec::Vec;
+
 use crate::header::Header;
-use crate::proto::{Parse, ParseError, Parser, Serialize, SerializeError, Serializer};
+use crate::proto::{
+    Parse, ParseError, ParseErrorKind, Parser, Section, Serialize, SerializeError, Serializer,
+};
 use crate::question::Question;
 use crate::rr::ResourceRecord;
 
@@ -18,7 +22,8 @@ use crate::rr::ResourceRecord;
 /// |      Additional     | RRs holding additional information
 /// +---------------------+
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Packet<'a> {
     pub header: Header,
     pub questions: Vec<Question<'a>>,
@@ -29,26 +34,23 @@ pub struct Packet<'a> {
 
 impl<'a> Parse<'a> for Packet<'a> {
     fn parse(parser: &mut Parser<'a>) -> Result<Self, ParseError> {
-        let header = Header::parse(parser)?;
+        let header = Header::parse(parser).map_err(|e| e.in_section(Section::Header, 0))?;
 
         let mut questions = Vec::with_capacity(header.qdcount.into());
-        for _ in 0..header.qdcount {
-            questions.push(Question::parse(parser)?);
-        }
-
-        let mut answers = Vec::with_capacity(header.ancount.into());
-        for _ in 0..header.ancount {
-            answers.push(ResourceRecord::parse(parser)?);
+        for i in 0..header.qdcount.into() {
+            questions
+                .push(Question::parse(parser).map_err(|e| e.in_section(Section::Question, i))?);
         }
 
-        let mut authorities = Vec::with_capacity(header.nscount.into());
-        for _ in 0..header.nscount {
-            authorities.push(ResourceRecord::parse(parser)?);
-        }
+        let answers = Self::parse_section(parser, Section::Answer, header.ancount)?;
+        let authorities = Self::parse_section(parser, Section::Authority, header.nscount)?;
+        let additionals = Self::parse_section(parser, Section::Additional, header.arcount)?;
 
-        let mut additionals = Vec::with_capacity(header.arcount.into());
-        for _ in 0..header.arcount {
-            additionals.push(ResourceRecord::parse(parser)?);
+        if parser.remaining() > 0 {
+            parser.violation(
+                ParseErrorKind::TrailingBytes(parser.remaining()),
+                parser.position(),
+            )?;
         }
 
         Ok(Packet {
@@ -61,6 +63,57 @@ impl<'a> Parse<'a> for Packet<'a> {
     }
 }
 
+impl<'a> Packet<'a> {
+    /// Size of the uncompressed wire format.
+    pub fn size(&self) -> usize {
+        Header::SIZE
+            + self.questions.iter().map(Question::size).sum::<usize>()
+            + self
+                .answers
+                .iter()
+                .chain(&self.authorities)
+                .chain(&self.additionals)
+                .map(ResourceRecord::size)
+                .sum::<usize>()
+    }
+
+    /// Copies borrowed names and bytes, detaching the packet from the buffer it was
+    /// parsed from.
+    pub fn into_owned(self) -> Packet<'static> {
+        let owned = |records: Vec<ResourceRecord>| {
+            records
+                .into_iter()
+                .map(ResourceRecord::into_owned)
+                .collect()
+        };
+
+        Packet {
+            header: self.header,
+            questions: self
+                .questions
+                .into_iter()
+                .map(Question::into_owned)
+                .collect(),
+            answers: owned(self.answers),
+            authorities: owned(self.authorities),
+            additionals: owned(self.additionals),
+        }
+    }
+
+    fn parse_section(
+        parser: &mut Parser<'a>,
+        section: Section,
+        count: u16,
+    ) -> Result<Vec<ResourceRecord<'a>>, ParseError> {
+        let mut records = Vec::with_capacity(count.into());
+        for i in 0..count.into() {
+            records.push(ResourceRecord::parse(parser).map_err(|e| e.in_section(section, i))?);
+        }
+
+        Ok(records)
+    }
+}
+
 impl<'a> Serialize<'a> for Packet<'a> {
     fn serialize(self, serializer: &mut Serializer<'a>) -> Result<usize, SerializeError> {
         self.header.serialize(serializer)?;
@@ -3,7 +3,14 @@ mod serializer;
 
 pub use crate::proto::parser::Parse;
 pub use crate::proto::parser::ParseError;
+pub use crate::proto::parser::ParseErrorKind;
+pub use crate::proto::parser::ParseMode;
 pub use crate::proto::parser::Parser;
+pub use crate::proto::parser::Section;
 pub use crate::proto::serializer::Serialize;
 pub use crate::proto::serializer::SerializeError;
 pub use crate::proto::serializer::Serializer;
+pub use dns_derive::Parse;
+pub use dns_derive::RecordType;
+pub use dns_derive::Serialize;
+pub use dns_derive::Size;
@@ -1,21 +1,200 @@
+use core::fmt::Display;
+use core::ops::Range;
+
+use log::warn;
+
+use crate::{record_data::Registry, r#type::Type};
+
+/// The part of a DNS message that was being decoded when parsing failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Header,
+    Question,
+    Answer,
+    Authority,
+    Additional,
+}
+
+impl Display for Section {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Self::Header => "header",
+            Self::Question => "question",
+            Self::Answer => "answer",
+            Self::Authority => "authority",
+            Self::Additional => "additional",
+        })
+    }
+}
+
 #[derive(Debug)]
-pub enum ParseError {
+pub enum ParseErrorKind {
     BufferOverflow(usize, usize),
     InvalidLabelLength(usize),
+    InvalidPointer(u16),
+    NameTooLong(usize),
+    RDataLengthMismatch(usize, usize),
+    TrailingBytes(usize),
+    ReservedBitSet,
+    InvalidOptName,
     FormatError,
     InvalidUtf8,
+    UnknownMnemonic,
     NotImplemented,
 }
 
+impl Display for ParseErrorKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::BufferOverflow(end, len) => {
+                write!(f, "read up to byte {} exceeds buffer of {} bytes", end, len)
+            }
+            Self::InvalidLabelLength(len) => write!(f, "invalid label length {}", len),
+            Self::InvalidPointer(pointer) => write!(f, "invalid compression pointer {}", pointer),
+            Self::NameTooLong(size) => write!(f, "name of {} octets exceeds 255", size),
+            Self::RDataLengthMismatch(expected, actual) => write!(
+                f,
+                "rdata length {} does not match {} bytes consumed",
+                expected, actual
+            ),
+            Self::TrailingBytes(len) => write!(f, "{} trailing bytes after message", len),
+            Self::ReservedBitSet => f.write_str("reserved z bit is set"),
+            Self::InvalidOptName => f.write_str("opt record owner name is not the root"),
+            Self::FormatError => f.write_str("format error"),
+            Self::InvalidUtf8 => f.write_str("invalid utf-8 in label"),
+            Self::UnknownMnemonic => f.write_str("unknown type or class mnemonic"),
+            Self::NotImplemented => f.write_str("not implemented"),
+        }
+    }
+}
+
+/// Error returned when a DNS message could not be decoded.
+///
+/// Besides the [`ParseErrorKind`] it records the byte offset at which decoding failed
+/// and, where known, the section, the index of the entry within that section and the
+/// type of the record being decoded.
+#[derive(Debug)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub offset: usize,
+    pub section: Option<Section>,
+    pub index: Option<usize>,
+    pub r#type: Option<Type>,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, offset: usize) -> Self {
+        Self {
+            kind,
+            offset,
+            section: None,
+            index: None,
+            r#type: None,
+        }
+    }
+
+    /// Attaches the section and entry index, keeping any context that was already set.
+    pub fn in_section(mut self, section: Section, index: usize) -> Self {
+        if self.section.is_none() {
+            self.section = Some(section);
+            self.index = Some(index);
+        }
+        self
+    }
+
+    /// Attaches the record type, keeping any type that was already set.
+    pub fn with_type(mut self, r#type: Type) -> Self {
+        if self.r#type.is_none() {
+            self.r#type = Some(r#type);
+        }
+        self
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} at offset {}", self.kind, self.offset)?;
+
+        if let Some(section) = self.section {
+            write!(f, " in {}", section)?;
+
+            if let (Some(index), false) = (self.index, section == Section::Header) {
+                write!(f, " #{}", index)?;
+            }
+        }
+
+        if let Some(r#type) = &self.r#type {
+            write!(f, " ({:?} record)", r#type)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+/// How a [`Parser`] deals with messages that are decodable but violate the protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    /// Reject rdata length mismatches, trailing bytes, a set z bit and opt records
+    /// with an owner name other than the root.
+    Strict,
+
+    /// Log such violations and carry on decoding, useful when analysing captures.
+    #[default]
+    Lenient,
+}
+
 #[derive(Debug)]
 pub struct Parser<'a> {
     buf: &'a [u8],
     pos: usize,
+    mode: ParseMode,
+    preserve_compression: bool,
+    registry: Option<&'a Registry>,
+    rdata: Option<Range<usize>>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(buf: &'a [u8]) -> Self {
-        Self { buf, pos: 0 }
+        Self::with_mode(buf, ParseMode::default())
+    }
+
+    pub fn with_mode(buf: &'a [u8], mode: ParseMode) -> Self {
+        Self {
+            buf,
+            pos: 0,
+            mode,
+            preserve_compression: false,
+            registry: None,
+            rdata: None,
+        }
+    }
+
+    /// Keeps the compressed encoding of every parsed name, so that serializing an
+    /// unmodified message reproduces the original bytes exactly.
+    pub fn preserve_compression(mut self, preserve: bool) -> Self {
+        self.preserve_compression = preserve;
+        self
+    }
+
+    /// Parses record types without built-in support with the codecs of `registry`.
+    pub fn with_registry(mut self, registry: &'a Registry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    pub fn mode(&self) -> ParseMode {
+        self.mode
+    }
+
+    pub fn preserves_compression(&self) -> bool {
+        self.preserve_compression
+    }
+
+    pub fn registry(&self) -> Option<&'a Registry> {
+        self.registry
     }
 
     pub fn remaining(&self) -> usize {
@@ -26,9 +205,29 @@ impl<'a> Parser<'a> {
         self.pos
     }
 
+    /// Creates an error of the given kind located at the current position.
+    pub fn error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError::new(kind, self.pos)
+    }
+
+    /// Reports a protocol violation found at `offset`.
+    ///
+    /// Fails in [`ParseMode::Strict`] and only logs a warning in [`ParseMode::Lenient`].
+    pub fn violation(&self, kind: ParseErrorKind, offset: usize) -> Result<(), ParseError> {
+        let err = ParseError::new(kind, offset);
+
+        match self.mode {
+            ParseMode::Strict => Err(err),
+            ParseMode::Lenient => {
+                warn!("{}", err);
+                Ok(())
+            }
+        }
+    }
+
     pub fn seek(&mut self, pos: usize) -> Result<(), ParseError> {
         if pos > self.buf.len() {
-            return Err(ParseError::BufferOverflow(self.pos, self.buf.len()));
+            return Err(self.error(ParseErrorKind::BufferOverflow(pos, self.buf.len())));
         }
 
         self.pos = pos;
@@ -57,7 +256,7 @@ impl<'a> Parser<'a> {
 
     pub fn read_u8(&self) -> Result<u8, ParseError> {
         if self.pos >= self.buf.len() {
-            return Err(ParseError::BufferOverflow(self.pos, self.buf.len()));
+            return Err(self.error(ParseErrorKind::BufferOverflow(self.pos + 1, self.buf.len())));
         }
 
         Ok(self.buf[self.pos])
@@ -65,13 +264,36 @@ impl<'a> Parser<'a> {
 
     pub fn consume_bytes(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
         if self.pos + len > self.buf.len() {
-            return Err(ParseError::BufferOverflow(self.pos + len, self.buf.len()));
+            return Err(self.error(ParseErrorKind::BufferOverflow(
+                self.pos + len,
+                self.buf.len(),
+            )));
         }
 
         let bytes = &self.buf[self.pos..self.pos + len];
         self.pos += len;
         Ok(bytes)
     }
+
+    /// Marks the bytes of the rdata that is being parsed, for [`Parser::consume_remaining`].
+    pub fn set_rdata(&mut self, rdata: Option<Range<usize>>) {
+        self.rdata = rdata;
+    }
+
+    /// Consumes the rest of the rdata that is being parsed, or the rest of the buffer
+    /// outside of rdata.
+    pub fn consume_remaining(&mut self) -> Result<&'a [u8], ParseError> {
+        let Some(rdata) = self.rdata.clone() else {
+            return self.consume_bytes(self.remaining());
+        };
+
+        let consumed = self.pos.saturating_sub(rdata.start);
+        if consumed > rdata.len() {
+            return Err(self.error(ParseErrorKind::RDataLengthMismatch(rdata.len(), consumed)));
+        }
+
+        self.consume_bytes(rdata.end - self.pos)
+    }
 }
 
 pub trait Parse<'a>: Sized {
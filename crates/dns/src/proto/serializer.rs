@@ -2,15 +2,22 @@
 pub enum SerializeError {
     BufferOverflow(usize, usize),
     InvalidLabelLength(usize),
+
+    /// Bytes too long for the length prefix in front of them.
+    InvalidLength(usize),
+    InvalidOpCode(u8),
+
+    /// An extended rcode, whose upper bits belong into an OPT record.
+    InvalidRCode(u16),
 }
 
 pub struct Serializer<'a> {
-    buf: &'a mut [u8; 4096],
+    buf: &'a mut [u8],
     pos: usize,
 }
 
 impl<'a> Serializer<'a> {
-    pub fn new(buf: &'a mut [u8; 4096]) -> Self {
+    pub fn new(buf: &'a mut [u8]) -> Self {
         Self { buf, pos: 0 }
     }
 
@@ -22,6 +29,25 @@ impl<'a> Serializer<'a> {
         self.pos
     }
 
+    /// The bytes written so far.
+    pub fn written(&self) -> &[u8] {
+        &self.buf[..self.pos]
+    }
+
+    /// Overwrites a previously written u16, used to fill in length fields.
+    pub fn write_u16_at(&mut self, pos: usize, value: u16) -> Result<(), SerializeError> {
+        if pos + size_of::<u16>() > self.pos {
+            return Err(SerializeError::BufferOverflow(
+                pos + size_of::<u16>(),
+                self.pos,
+            ));
+        }
+
+        self.buf[pos..pos + size_of::<u16>()].copy_from_slice(&value.to_be_bytes());
+
+        Ok(())
+    }
+
     pub fn write_u32(&mut self, value: u32) -> Result<(), SerializeError> {
         self.write_bytes(&value.to_be_bytes())
     }
@@ -35,14 +61,14 @@ impl<'a> Serializer<'a> {
     }
 
     pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), SerializeError> {
-        if self.pos + bytes.len() >= self.buf.len() {
+        if self.pos + bytes.len() > self.buf.len() {
             return Err(SerializeError::BufferOverflow(
                 self.pos + bytes.len(),
                 self.buf.len(),
             ));
         }
 
-        self.buf[self.pos..self.pos + bytes.len()].copy_from_slice(&bytes);
+        self.buf[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
         self.pos += bytes.len();
 
         Ok(())
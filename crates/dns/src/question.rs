@@ -1,4 +1,4 @@
-use std::fmt::Debug;
+use core::fmt::Debug;
 
 use crate::{
     DomainName,
@@ -21,7 +21,8 @@ use crate::{
 /// |                     QCLASS                    |
 /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Question<'a> {
     pub name: DomainName<'a>,
     pub r#type: Type,
@@ -38,6 +39,21 @@ impl<'a> Parse<'a> for Question<'a> {
     }
 }
 
+impl Question<'_> {
+    /// Size of the uncompressed wire format.
+    pub fn size(&self) -> usize {
+        self.name.size() + size_of::<u16>() + size_of::<u16>()
+    }
+
+    pub fn into_owned(self) -> Question<'static> {
+        Question {
+            name: self.name.into_owned(),
+            r#type: self.r#type,
+            class: self.class,
+        }
+    }
+}
+
 impl<'a> Serialize<'a> for Question<'a> {
     fn serialize(self, serializer: &mut Serializer<'a>) -> Result<usize, SerializeError> {
         self.name.serialize(serializer)?;
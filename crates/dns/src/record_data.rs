@@ -0,0 +1,69 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::fmt::{Debug, Display};
+
+use crate::{
+    proto::{ParseError, Parser, SerializeError, Serializer},
+    r#type::Type,
+};
+
+/// Typed rdata of a record type the crate doesn't implement itself.
+///
+/// Implementations are registered for a type code with a [`Registry`], which is handed
+/// to the [`Parser`]. Records of that type are then parsed into
+/// [`Record::Custom`](crate::Record::Custom) instead of being kept as unknown bytes.
+/// [`Display`] renders the rdata in presentation format.
+pub trait RecordData: Debug + Display + Send + Sync {
+    /// Parses `len` bytes of rdata starting at the current position of the parser.
+    fn parse(parser: &mut Parser<'_>, len: usize) -> Result<Self, ParseError>
+    where
+        Self: Sized;
+
+    /// Writes the rdata without its length, which is filled in by the caller.
+    fn serialize(&self, serializer: &mut Serializer<'_>) -> Result<usize, SerializeError>;
+
+    /// Size of the uncompressed rdata.
+    fn size(&self) -> usize;
+}
+
+type ParseFn = fn(&mut Parser<'_>, usize) -> Result<Arc<dyn RecordData>, ParseError>;
+
+/// Codecs for record types without built-in support, by type code.
+#[derive(Debug, Default)]
+pub struct Registry {
+    codecs: BTreeMap<u16, ParseFn>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses records of `r#type` as `T`, replacing any codec registered before.
+    ///
+    /// Types the crate implements itself are always parsed into their own variant.
+    pub fn register<T: RecordData + 'static>(&mut self, r#type: Type) {
+        self.codecs.insert(r#type.into(), parse_boxed::<T>);
+    }
+
+    pub fn contains(&self, r#type: &Type) -> bool {
+        self.codecs.contains_key(&r#type.clone().into())
+    }
+
+    pub(crate) fn parse(
+        &self,
+        r#type: &Type,
+        parser: &mut Parser<'_>,
+        len: usize,
+    ) -> Option<Result<Arc<dyn RecordData>, ParseError>> {
+        let codec = self.codecs.get(&r#type.clone().into())?;
+        Some(codec(parser, len))
+    }
+}
+
+fn parse_boxed<T: RecordData + 'static>(
+    parser: &mut Parser<'_>,
+    len: usize,
+) -> Result<Arc<dyn RecordData>, ParseError> {
+    Ok(Arc::new(T::parse(parser, len)?))
+}
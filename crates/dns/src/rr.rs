@@ -1,10 +1,22 @@
+use alloc::borrow::Cow;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
 use log::warn;
 
 use crate::{
     DomainName,
     class::Class,
-    proto::{Parse, ParseError, Serialize, SerializeError, Serializer},
+    header::RCode,
+    proto::{
+        Parse, ParseError, ParseErrorKind, Parser, RecordType, Serialize, SerializeError,
+        Serializer, Size,
+    },
+    record_data::RecordData,
     rr,
+    serial::{Serial, SerialPolicy},
     r#type::Type,
 };
 
@@ -31,11 +43,15 @@ use crate::{
 /// /                                               /
 /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
 pub enum ResourceRecord<'a> {
     Record {
         name: DomainName<'a>,
+        class: Class,
         ttl: u32,
+        #[cfg_attr(feature = "serde", serde(flatten))]
         data: Record<'a>,
     },
     OPTRecord {
@@ -48,26 +64,46 @@ pub enum ResourceRecord<'a> {
         r#type: Type,
         class: Class,
         ttl: u32,
-        data: &'a [u8],
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_impl::base64"))]
+        data: Cow<'a, [u8]>,
     },
 }
 
 impl<'a> Parse<'a> for ResourceRecord<'a> {
-    fn parse(parser: &mut crate::proto::Parser<'a>) -> Result<Self, ParseError> {
+    fn parse(parser: &mut Parser<'a>) -> Result<Self, ParseError> {
         let name = DomainName::parse(parser)?;
-        let r#type = parser.consume_u16()?.into();
+        let r#type: Type = parser.consume_u16()?.into();
+
+        let record = Self::parse_fields(parser, name, r#type.clone());
+        parser.set_rdata(None);
+
+        record.map_err(|e| e.with_type(r#type))
+    }
+}
+
+impl<'a> ResourceRecord<'a> {
+    fn parse_fields(
+        parser: &mut Parser<'a>,
+        name: DomainName<'a>,
+        r#type: Type,
+    ) -> Result<Self, ParseError> {
         let class = parser.consume_u16()?;
         let ttl = parser.consume_u32()?;
         let rd_length = parser.consume_u16()?.into();
+        let start = parser.position();
+        parser.set_rdata(Some(start..start + rd_length));
 
-        match &r#type {
+        let record = match &r#type {
             Type::OPT => {
-                return Ok(ResourceRecord::OPTRecord {
+                if !name.labels.is_empty() {
+                    parser.violation(ParseErrorKind::InvalidOptName, start)?;
+                }
+
+                ResourceRecord::OPTRecord {
                     size: class,
                     flags: ttl,
                     options: {
                         let mut options = Vec::new();
-                        let start = parser.position();
 
                         while (parser.position() - start) < rd_length {
                             options.push({
@@ -75,140 +111,140 @@ impl<'a> Parse<'a> for ResourceRecord<'a> {
                                 let len = parser.consume_u16()?;
                                 let data = parser.consume_bytes(len.into())?;
 
-                                match &code {
-                                    x => {
-                                        warn!("known edns option not implemented {:?}", x);
-                                        rr::Option::Unknown { code, len, data }
-                                    }
+                                warn!("known edns option not implemented {:?}", code);
+                                rr::Option::Unknown {
+                                    code,
+                                    len,
+                                    data: data.into(),
                                 }
                             });
                         }
 
                         options
                     },
-                });
+                }
             }
-            Type::Unknown(_) => {
-                return Ok(ResourceRecord::Unknown {
+            // empty rdata is how dynamic updates refer to whole rrsets
+            _ if rd_length == 0 && matches!(Class::from(class), Class::ANY | Class::NONE) => {
+                ResourceRecord::Unknown {
                     name,
                     r#type,
                     class: class.into(),
                     ttl,
-                    data: parser.consume_bytes(rd_length)?,
-                });
+                    data: Cow::Borrowed(&[]),
+                }
             }
-            other => {
-                let data = match other {
-                    Type::A => Record::A {
-                        address: parser
-                            .consume_bytes(rd_length)?
-                            .try_into()
-                            .map_err(|_| ParseError::FormatError)?,
-                    },
-                    Type::NS => Record::NS {
-                        nsdname: DomainName::parse(parser)?,
-                    },
-                    Type::CNAME => Record::CNAME {
-                        cname: DomainName::parse(parser)?,
-                    },
-                    Type::SOA => Record::SOA {
-                        mname: DomainName::parse(parser)?,
-                        rname: DomainName::parse(parser)?,
-                        serial: parser.consume_u32()?,
-                        refresh: parser.consume_u32()?,
-                        retry: parser.consume_u32()?,
-                        expire: parser.consume_u32()?,
-                        minimum: parser.consume_u32()?,
-                    },
-                    Type::PTR => Record::PTR {
-                        ptrdname: DomainName::parse(parser)?,
-                    },
-                    Type::MX => Record::MX {
-                        preference: parser.consume_u16()?,
-                        exchange: DomainName::parse(parser)?,
-                    },
-                    Type::TXT => Record::TXT {
-                        text: parser.consume_bytes(rd_length)?,
-                    },
-                    Type::AAAA => Record::AAAA {
-                        address: parser
-                            .consume_bytes(rd_length)?
-                            .try_into()
-                            .map_err(|_| ParseError::FormatError)?,
-                    },
-                    _ => {
+            other => match Record::parse_rdata(parser, other)? {
+                Some(data) => ResourceRecord::Record {
+                    name,
+                    class: class.into(),
+                    ttl,
+                    data,
+                },
+                None => {
+                    if !matches!(other, Type::Unknown(_))
+                        && !parser.registry().is_some_and(|r| r.contains(other))
+                    {
                         warn!("known record type not implemented {:?}", other);
-
-                        return Ok(ResourceRecord::Unknown {
-                            name,
-                            r#type,
-                            class: class.into(),
-                            ttl,
-                            data: parser.consume_bytes(rd_length)?,
-                        });
                     }
-                };
 
-                Ok(ResourceRecord::Record { name, ttl, data })
-            }
+                    return Self::parse_registered(
+                        parser,
+                        name,
+                        r#type,
+                        class.into(),
+                        ttl,
+                        rd_length,
+                    );
+                }
+            },
+        };
+
+        Self::check_length(parser, start, rd_length)?;
+
+        Ok(record)
+    }
+
+    /// Makes sure the rdata starting at `start` consumed exactly `rd_length` bytes.
+    fn check_length(
+        parser: &mut Parser<'a>,
+        start: usize,
+        rd_length: usize,
+    ) -> Result<(), ParseError> {
+        let consumed = parser.position() - start;
+        if consumed != rd_length {
+            parser.violation(
+                ParseErrorKind::RDataLengthMismatch(rd_length, consumed),
+                start,
+            )?;
+            parser.seek(start + rd_length)?;
         }
+
+        Ok(())
+    }
+
+    /// Parses rdata with the codec registered for the type, if any, and keeps it as
+    /// unknown bytes otherwise.
+    fn parse_registered(
+        parser: &mut Parser<'a>,
+        name: DomainName<'a>,
+        r#type: Type,
+        class: Class,
+        ttl: u32,
+        rd_length: usize,
+    ) -> Result<Self, ParseError> {
+        let start = parser.position();
+
+        let record = match parser
+            .registry()
+            .and_then(|registry| registry.parse(&r#type, parser, rd_length))
+        {
+            Some(data) => {
+                let data = data?;
+                Self::check_length(parser, start, rd_length)?;
+
+                ResourceRecord::Record {
+                    name,
+                    class,
+                    ttl,
+                    data: Record::Custom { r#type, data },
+                }
+            }
+            None => ResourceRecord::Unknown {
+                name,
+                r#type,
+                class,
+                ttl,
+                data: parser.consume_bytes(rd_length)?.into(),
+            },
+        };
+
+        Ok(record)
     }
 }
 
 impl<'a> Serialize<'a> for ResourceRecord<'a> {
     fn serialize(self, serializer: &mut Serializer<'a>) -> Result<usize, SerializeError> {
         match self {
-            ResourceRecord::Record { name, ttl, data } => {
+            ResourceRecord::Record {
+                name,
+                class,
+                ttl,
+                data,
+            } => {
                 name.serialize(serializer)?;
                 serializer.write_u16(Type::from(&data).into())?;
-                serializer.write_u16(Class::IN.into())?;
+                serializer.write_u16(class.into())?;
                 serializer.write_u32(ttl)?;
-                serializer.write_u16(data.size() as u16)?;
 
-                match data {
-                    Record::A { address } => {
-                        serializer.write_bytes(address)?;
-                    }
-                    Record::NS { nsdname } => {
-                        nsdname.serialize(serializer)?;
-                    }
-                    Record::CNAME { cname } => {
-                        cname.serialize(serializer)?;
-                    }
-                    Record::SOA {
-                        mname,
-                        rname,
-                        serial,
-                        refresh,
-                        retry,
-                        expire,
-                        minimum,
-                    } => {
-                        mname.serialize(serializer)?;
-                        rname.serialize(serializer)?;
-                        serializer.write_u32(serial)?;
-                        serializer.write_u32(refresh)?;
-                        serializer.write_u32(retry)?;
-                        serializer.write_u32(expire)?;
-                        serializer.write_u32(minimum)?;
-                    }
-                    Record::PTR { ptrdname } => {
-                        ptrdname.serialize(serializer)?;
-                    }
-                    Record::MX {
-                        preference,
-                        exchange,
-                    } => {
-                        serializer.write_u16(preference)?;
-                        exchange.serialize(serializer)?;
-                    }
-                    Record::TXT { text } => {
-                        serializer.write_bytes(text)?;
-                    }
-                    Record::AAAA { address } => {
-                        serializer.write_bytes(address)?;
-                    }
-                };
+                // names in the rdata may be compressed, so the length is only known afterwards
+                let rd_length_pos = serializer.position();
+                serializer.write_u16(0)?;
+
+                data.serialize(serializer)?;
+
+                let rd_length = serializer.position() - rd_length_pos - size_of::<u16>();
+                serializer.write_u16_at(rd_length_pos, rd_length as u16)?;
             }
             ResourceRecord::OPTRecord {
                 size,
@@ -222,13 +258,7 @@ impl<'a> Serialize<'a> for ResourceRecord<'a> {
                 serializer.write_u16((options.iter().map(|o| o.size()).sum::<usize>()) as u16)?;
 
                 for option in options {
-                    match option {
-                        rr::Option::Unknown { code, len, data } => {
-                            serializer.write_u16(code.into())?;
-                            serializer.write_u16(len)?;
-                            serializer.write_bytes(data)?;
-                        }
-                    }
+                    option.serialize(serializer)?;
                 }
             }
             ResourceRecord::Unknown {
@@ -243,15 +273,18 @@ impl<'a> Serialize<'a> for ResourceRecord<'a> {
                 serializer.write_u16(class.into())?;
                 serializer.write_u32(ttl)?;
                 serializer.write_u16(data.len() as u16)?;
-                serializer.write_bytes(data)?;
+                serializer.write_bytes(&data)?;
             }
         };
 
-        return Ok(serializer.position());
+        Ok(serializer.position())
     }
 }
 
-#[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Parse, Serialize, Size, RecordType)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "data"))]
 pub enum Record<'a> {
     /// DNS A record field layout as per [RFC 1035 Section 3.4.1](https://www.rfc-editor.org/rfc/rfc1035#section-3.4.1)
     ///
@@ -262,7 +295,11 @@ pub enum Record<'a> {
     /// |                                               |
     /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
     /// ```
-    A { address: &'a [u8; 4] },
+    #[dns(type = A)]
+    A {
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_impl::ipv4"))]
+        address: [u8; 4],
+    },
 
     /// DNS NS record field layout as per [RFC 1035 Section 3.3.11](https://www.rfc-editor.org/rfc/rfc1035#section-3.3.11)
     ///
@@ -273,6 +310,7 @@ pub enum Record<'a> {
     /// /                                               /
     /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
     /// ```
+    #[dns(type = NS)]
     NS { nsdname: DomainName<'a> },
 
     /// DNS CNAME record field layout as per [RFC 1035 Section 3.3.1](https://www.rfc-editor.org/rfc/rfc1035#section-3.3.1)
@@ -284,6 +322,7 @@ pub enum Record<'a> {
     /// /                                               /
     /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
     /// ```
+    #[dns(type = CNAME)]
     CNAME { cname: DomainName<'a> },
 
     /// DNS SOA record field layout as per [RFC 1035 Section 3.3.13](https://www.rfc-editor.org/rfc/rfc1035#section-3.3.13)
@@ -312,10 +351,11 @@ pub enum Record<'a> {
     /// |                                               |
     /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
     /// ```
+    #[dns(type = SOA)]
     SOA {
         mname: DomainName<'a>,
         rname: DomainName<'a>,
-        serial: u32,
+        serial: Serial,
         refresh: u32,
         retry: u32,
         expire: u32,
@@ -330,6 +370,7 @@ pub enum Record<'a> {
     /// /                   PTRDNAME                    /
     /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
     /// ```
+    #[dns(type = PTR)]
     PTR { ptrdname: DomainName<'a> },
 
     /// DNS MX record field layout as per [RFC 1035 Section 3.3.9](https://www.rfc-editor.org/rfc/rfc1035#section-3.3.9)
@@ -343,6 +384,7 @@ pub enum Record<'a> {
     /// /                                               /
     /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
     /// ```
+    #[dns(type = MX)]
     MX {
         preference: u16,
         exchange: DomainName<'a>,
@@ -356,7 +398,15 @@ pub enum Record<'a> {
     /// /                   TXT-DATA                    /
     /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
     /// ```
-    TXT { text: &'a [u8] },
+    #[dns(type = TXT)]
+    TXT {
+        #[dns(remaining)]
+        #[cfg_attr(
+            feature = "serde",
+            serde(with = "crate::serde_impl::character_strings")
+        )]
+        text: Cow<'a, [u8]>,
+    },
 
     /// DNS AAAA record field layout as per [RFC 3596 Section 2.2](https://www.rfc-editor.org/rfc/rfc3596#section-2.2)
     ///
@@ -373,28 +423,400 @@ pub enum Record<'a> {
     /// |                                               |
     /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
     /// ```
-    AAAA { address: &'a [u8; 16] },
+    #[dns(type = AAAA)]
+    AAAA {
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_impl::ipv6"))]
+        address: [u8; 16],
+    },
+
+    /// DNS SRV record field layout as per [RFC 2782](https://www.rfc-editor.org/rfc/rfc2782)
+    ///
+    /// ```text
+    ///   0  1  2  3  4  5  6  7  8  9 10 11 12 13 14 15
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// |                   PRIORITY                    |
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// |                    WEIGHT                     |
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// |                     PORT                      |
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// /                    TARGET                     /
+    /// /                                               /
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// ```
+    #[dns(type = SRV)]
+    SRV {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: DomainName<'a>,
+    },
+
+    /// DNS DNAME record field layout as per [RFC 6672 Section 2.1](https://www.rfc-editor.org/rfc/rfc6672#section-2.1)
+    ///
+    /// ```text
+    ///   0  1  2  3  4  5  6  7  8  9 10 11 12 13 14 15
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// /                    TARGET                     /
+    /// /                                               /
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// ```
+    #[dns(type = DNAME)]
+    DNAME { target: DomainName<'a> },
+
+    /// DNS SIG record field layout as per [RFC 2535 Section 4.1](https://www.rfc-editor.org/rfc/rfc2535#section-4.1),
+    /// used for SIG(0) as per [RFC 2931](https://www.rfc-editor.org/rfc/rfc2931)
+    ///
+    /// ```text
+    ///   0  1  2  3  4  5  6  7  8  9 10 11 12 13 14 15
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// |                 TYPE COVERED                  |
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// |       ALGORITHM       |        LABELS         |
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// |                 ORIGINAL TTL                  |
+    /// |                                               |
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// |             SIGNATURE EXPIRATION              |
+    /// |                                               |
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// |              SIGNATURE INCEPTION              |
+    /// |                                               |
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// |                    KEY TAG                    |
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// /                 SIGNER'S NAME                 /
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// /                   SIGNATURE                   /
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// ```
+    #[dns(type = SIG)]
+    SIG {
+        #[dns(u16)]
+        type_covered: Type,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer: DomainName<'a>,
+        #[dns(remaining)]
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_impl::base64"))]
+        signature: Cow<'a, [u8]>,
+    },
+
+    /// DNS KEY record field layout as per [RFC 2535 Section 3.1](https://www.rfc-editor.org/rfc/rfc2535#section-3.1)
+    ///
+    /// ```text
+    ///   0  1  2  3  4  5  6  7  8  9 10 11 12 13 14 15
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// |                     FLAGS                     |
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// |        PROTOCOL       |       ALGORITHM       |
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// /                  PUBLIC KEY                   /
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// ```
+    #[dns(type = KEY)]
+    KEY {
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        #[dns(remaining)]
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_impl::base64"))]
+        public_key: Cow<'a, [u8]>,
+    },
+
+    /// DNS TSIG record field layout as per [RFC 8945 Section 4.2](https://www.rfc-editor.org/rfc/rfc8945#section-4.2)
+    ///
+    /// ```text
+    ///   0  1  2  3  4  5  6  7  8  9 10 11 12 13 14 15
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// /                 ALGORITHM NAME                /
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// |                                               |
+    /// |                  TIME SIGNED                  |
+    /// |                                               |
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// |                     FUDGE                     |
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// |                   MAC SIZE                    |
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// /                      MAC                      /
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// |                  ORIGINAL ID                  |
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// |                     ERROR                     |
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// |                   OTHER LEN                   |
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// /                  OTHER DATA                   /
+    /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+    /// ```
+    #[dns(type = TSIG)]
+    TSIG {
+        algorithm: DomainName<'a>,
+        #[dns(u48)]
+        time_signed: u64,
+        fudge: u16,
+        #[dns(u16_prefixed)]
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_impl::base64"))]
+        mac: Cow<'a, [u8]>,
+        original_id: u16,
+        #[dns(u16)]
+        error: RCode,
+        #[dns(u16_prefixed)]
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_impl::base64"))]
+        other: Cow<'a, [u8]>,
+    },
+
+    /// Rdata of a type without built-in support, parsed by a codec of the
+    /// [`Registry`](crate::Registry) the parser was given.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Custom {
+        #[dns(skip)]
+        r#type: Type,
+        data: Arc<dyn RecordData>,
+    },
+}
+
+#[cfg(feature = "std")]
+impl<'a> ResourceRecord<'a> {
+    /// An A or AAAA record of class IN for `address`.
+    pub fn address(name: DomainName<'a>, ttl: u32, address: IpAddr) -> Self {
+        Self::Record {
+            name,
+            class: Class::IN,
+            ttl,
+            data: address.into(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Record<'_> {
+    /// The address of an A or AAAA record.
+    pub fn ip(&self) -> core::option::Option<IpAddr> {
+        match self {
+            Self::A { address } => Some(Ipv4Addr::from(*address).into()),
+            Self::AAAA { address } => Some(Ipv6Addr::from(*address).into()),
+            _ => None,
+        }
+    }
+}
+
+impl Record<'_> {
+    /// Advances the serial of an SOA record as per `policy`, for a change at `now` in
+    /// seconds since the epoch. Returns the new serial, or `None` for other records.
+    pub fn advance_serial(
+        &mut self,
+        policy: SerialPolicy,
+        now: u64,
+    ) -> core::option::Option<Serial> {
+        match self {
+            Self::SOA { serial, .. } => {
+                *serial = policy.next(*serial, now);
+                Some(*serial)
+            }
+            _ => None,
+        }
+    }
 }
 
-impl<'a> Record<'a> {
+impl ResourceRecord<'_> {
+    /// Size of the uncompressed wire format.
     pub fn size(&self) -> usize {
+        // type, class, ttl and rdata length
+        let fields = size_of::<u16>() + size_of::<u16>() + size_of::<u32>() + size_of::<u16>();
+
         match self {
-            Self::A { address } => address.len(),
-            Self::NS { nsdname } => nsdname.size(),
-            Self::CNAME { cname } => cname.size(),
-            Self::SOA { mname, rname, .. } => {
-                mname.size()
-                    + rname.size()
-                    + size_of::<u32>()
-                    + size_of::<u32>()
-                    + size_of::<u32>()
-                    + size_of::<u32>()
-                    + size_of::<u32>()
+            Self::Record { name, data, .. } => name.size() + fields + data.size(),
+            Self::OPTRecord { options, .. } => {
+                DomainName::default().size()
+                    + fields
+                    + options.iter().map(Option::size).sum::<usize>()
             }
-            Self::PTR { ptrdname } => ptrdname.size(),
-            Self::MX { exchange, .. } => size_of::<u16>() + exchange.size(),
-            Self::TXT { text } => text.len(),
-            Self::AAAA { address } => address.len(),
+            Self::Unknown { name, data, .. } => name.size() + fields + data.len(),
+        }
+    }
+
+    /// Copies borrowed names and bytes, detaching the record from the message it was
+    /// parsed from.
+    pub fn into_owned(self) -> ResourceRecord<'static> {
+        match self {
+            Self::Record {
+                name,
+                class,
+                ttl,
+                data,
+            } => ResourceRecord::Record {
+                name: name.into_owned(),
+                class,
+                ttl,
+                data: data.into_owned(),
+            },
+            Self::OPTRecord {
+                size,
+                flags,
+                options,
+            } => ResourceRecord::OPTRecord {
+                size,
+                flags,
+                options: options.into_iter().map(Option::into_owned).collect(),
+            },
+            Self::Unknown {
+                name,
+                r#type,
+                class,
+                ttl,
+                data,
+            } => ResourceRecord::Unknown {
+                name: name.into_owned(),
+                r#type,
+                class,
+                ttl,
+                data: Cow::Owned(data.into_owned()),
+            },
+        }
+    }
+}
+
+impl Record<'_> {
+    /// Copies borrowed names and bytes, detaching the rdata from the message it was
+    /// parsed from.
+    pub fn into_owned(self) -> Record<'static> {
+        match self {
+            Self::A { address } => Record::A { address },
+            Self::NS { nsdname } => Record::NS {
+                nsdname: nsdname.into_owned(),
+            },
+            Self::CNAME { cname } => Record::CNAME {
+                cname: cname.into_owned(),
+            },
+            Self::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => Record::SOA {
+                mname: mname.into_owned(),
+                rname: rname.into_owned(),
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            },
+            Self::PTR { ptrdname } => Record::PTR {
+                ptrdname: ptrdname.into_owned(),
+            },
+            Self::MX {
+                preference,
+                exchange,
+            } => Record::MX {
+                preference,
+                exchange: exchange.into_owned(),
+            },
+            Self::TXT { text } => Record::TXT {
+                text: Cow::Owned(text.into_owned()),
+            },
+            Self::AAAA { address } => Record::AAAA { address },
+            Self::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => Record::SRV {
+                priority,
+                weight,
+                port,
+                target: target.into_owned(),
+            },
+            Self::DNAME { target } => Record::DNAME {
+                target: target.into_owned(),
+            },
+            Self::SIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer,
+                signature,
+            } => Record::SIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer: signer.into_owned(),
+                signature: Cow::Owned(signature.into_owned()),
+            },
+            Self::KEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => Record::KEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key: Cow::Owned(public_key.into_owned()),
+            },
+            Self::TSIG {
+                algorithm,
+                time_signed,
+                fudge,
+                mac,
+                original_id,
+                error,
+                other,
+            } => Record::TSIG {
+                algorithm: algorithm.into_owned(),
+                time_signed,
+                fudge,
+                mac: Cow::Owned(mac.into_owned()),
+                original_id,
+                error,
+                other: Cow::Owned(other.into_owned()),
+            },
+            Self::Custom { r#type, data } => Record::Custom { r#type, data },
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Ipv4Addr> for Record<'_> {
+    fn from(address: Ipv4Addr) -> Self {
+        Self::A {
+            address: address.octets(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Ipv6Addr> for Record<'_> {
+    fn from(address: Ipv6Addr) -> Self {
+        Self::AAAA {
+            address: address.octets(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<IpAddr> for Record<'_> {
+    fn from(address: IpAddr) -> Self {
+        match address {
+            IpAddr::V4(address) => address.into(),
+            IpAddr::V6(address) => address.into(),
         }
     }
 }
@@ -413,25 +835,37 @@ impl<'a> Record<'a> {
 /// /                                               /
 /// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
 /// ```
-#[derive(Debug)]
+///
+/// Options are parsed along with the OPT record, as their layout follows from the
+/// option code rather than the record type.
+#[derive(Debug, Clone, Serialize, Size)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Option<'a> {
     Unknown {
+        #[dns(u16)]
         code: OptionCode,
         len: u16,
-        data: &'a [u8],
+        #[dns(remaining)]
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_impl::base64"))]
+        data: Cow<'a, [u8]>,
     },
 }
 
 impl Option<'_> {
-    pub fn size(&self) -> usize {
+    pub fn into_owned(self) -> Option<'static> {
         match self {
-            Self::Unknown { data, .. } => size_of::<u16>() + size_of::<u16>() + data.len(),
+            Self::Unknown { code, len, data } => Option::Unknown {
+                code,
+                len,
+                data: Cow::Owned(data.into_owned()),
+            },
         }
     }
 }
 
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OptionCode {
     /// [RFC 6891](https://www.rfc-editor.org/rfc/rfc6891)
     Zero,
@@ -531,3 +965,288 @@ impl From<OptionCode> for u16 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use alloc::vec;
+    use core::fmt::Display;
+
+    use super::*;
+    use crate::record_data::Registry;
+
+    /// Two big-endian integers, standing in for a type the crate doesn't implement.
+    #[derive(Debug)]
+    struct Pair(u16, u32);
+
+    impl Display for Pair {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            write!(f, "{} {}", self.0, self.1)
+        }
+    }
+
+    impl RecordData for Pair {
+        fn parse(parser: &mut Parser<'_>, _len: usize) -> Result<Self, ParseError> {
+            Ok(Self(parser.consume_u16()?, parser.consume_u32()?))
+        }
+
+        fn serialize(&self, serializer: &mut Serializer<'_>) -> Result<usize, SerializeError> {
+            serializer.write_u16(self.0)?;
+            serializer.write_u32(self.1)?;
+            Ok(6)
+        }
+
+        fn size(&self) -> usize {
+            6
+        }
+    }
+
+    const PAIR: Type = Type::Unknown(65280);
+
+    fn name(name: &str) -> DomainName<'static> {
+        name.parse().unwrap()
+    }
+
+    fn record(data: Record<'static>) -> ResourceRecord<'static> {
+        ResourceRecord::Record {
+            name: name("example.com"),
+            class: Class::IN,
+            ttl: 3600,
+            data,
+        }
+    }
+
+    /// Serializes `record`, parses it back and serializes that again, which has to
+    /// give the same record and the same bytes.
+    fn round_trip(record: ResourceRecord<'static>) {
+        let size = record.size();
+        let expected = format!("{:?}", record);
+
+        let mut buf = vec![0; size];
+        let len = record.serialize(&mut Serializer::new(&mut buf)).unwrap();
+        assert_eq!(len, size, "size of {}", expected);
+
+        let mut registry = Registry::new();
+        registry.register::<Pair>(PAIR);
+        let mut parser = Parser::new(&buf).with_registry(&registry);
+        let parsed = ResourceRecord::parse(&mut parser).unwrap();
+        assert_eq!(parser.remaining(), 0);
+        assert_eq!(format!("{:?}", parsed), expected);
+
+        let mut again = vec![0; size];
+        parsed.serialize(&mut Serializer::new(&mut again)).unwrap();
+        assert_eq!(again, buf, "bytes of {}", expected);
+    }
+
+    fn rdata(record: ResourceRecord<'static>) -> Vec<u8> {
+        let mut buf = vec![0; record.size()];
+        record.serialize(&mut Serializer::new(&mut buf)).unwrap();
+        // name, type, class, ttl and rdlength come first
+        buf.split_off(name("example.com").size() + 10)
+    }
+
+    #[test]
+    fn a() {
+        round_trip(record(Record::A {
+            address: [192, 0, 2, 1],
+        }));
+        assert_eq!(
+            rdata(record(Record::A {
+                address: [192, 0, 2, 1]
+            })),
+            [192, 0, 2, 1]
+        );
+    }
+
+    #[test]
+    fn ns() {
+        round_trip(record(Record::NS {
+            nsdname: name("ns1.example.net"),
+        }));
+    }
+
+    #[test]
+    fn cname() {
+        round_trip(record(Record::CNAME {
+            cname: name("www.example.net"),
+        }));
+    }
+
+    #[test]
+    fn soa() {
+        round_trip(record(Record::SOA {
+            mname: name("ns1.example.com"),
+            rname: name("hostmaster.example.com"),
+            serial: Serial(2024010101),
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 300,
+        }));
+    }
+
+    #[test]
+    fn ptr() {
+        round_trip(record(Record::PTR {
+            ptrdname: name("host.example.com"),
+        }));
+    }
+
+    #[test]
+    fn mx() {
+        let mx = Record::MX {
+            preference: 10,
+            exchange: name("mail.example.com"),
+        };
+        round_trip(record(mx.clone()));
+
+        let mut expected = vec![0, 10];
+        expected.extend_from_slice(b"\x04mail\x07example\x03com\x00");
+        assert_eq!(rdata(record(mx)), expected);
+    }
+
+    #[test]
+    fn txt() {
+        round_trip(record(Record::TXT {
+            text: Cow::Borrowed(b"\x05hello\x05world"),
+        }));
+        round_trip(record(Record::TXT {
+            text: Cow::Borrowed(b"\x00"),
+        }));
+    }
+
+    #[test]
+    fn aaaa() {
+        round_trip(record(Record::AAAA {
+            address: [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        }));
+    }
+
+    #[test]
+    fn srv() {
+        let srv = Record::SRV {
+            priority: 1,
+            weight: 2,
+            port: 5353,
+            target: name("sip.example.com"),
+        };
+        round_trip(record(srv.clone()));
+        assert_eq!(rdata(record(srv))[..6], [0, 1, 0, 2, 0x14, 0xe9]);
+    }
+
+    #[test]
+    fn dname() {
+        round_trip(record(Record::DNAME {
+            target: name("example.net"),
+        }));
+    }
+
+    #[test]
+    fn sig() {
+        let sig = Record::SIG {
+            type_covered: Type::Unknown(0),
+            algorithm: 15,
+            labels: 0,
+            original_ttl: 0,
+            expiration: 1_700_000_300,
+            inception: 1_700_000_000,
+            key_tag: 14272,
+            signer: name("example.com"),
+            signature: Cow::Owned(vec![0xab; 64]),
+        };
+        round_trip(record(sig.clone()));
+        assert_eq!(rdata(record(sig))[..4], [0, 0, 15, 0]);
+    }
+
+    #[test]
+    fn key() {
+        round_trip(record(Record::KEY {
+            flags: 0x0200,
+            protocol: 3,
+            algorithm: 15,
+            public_key: Cow::Owned(vec![0x42; 32]),
+        }));
+    }
+
+    #[test]
+    fn tsig() {
+        let tsig = Record::TSIG {
+            algorithm: name("hmac-sha256"),
+            time_signed: 0x0001_0203_0405,
+            fudge: 300,
+            mac: Cow::Owned(vec![0x11; 32]),
+            original_id: 0x1234,
+            error: RCode::NoError,
+            other: Cow::Borrowed(&[]),
+        };
+        round_trip(ResourceRecord::Record {
+            name: name("key.example.com"),
+            class: Class::ANY,
+            ttl: 0,
+            data: tsig.clone(),
+        });
+
+        // time signed takes 48 bits, the mac and other data are prefixed by their size
+        let rdata = rdata(record(tsig));
+        let time = name("hmac-sha256").size();
+        assert_eq!(rdata[time..time + 6], [0, 1, 2, 3, 4, 5]);
+        assert_eq!(rdata[time + 8..time + 10], [0, 32]);
+        assert_eq!(rdata[rdata.len() - 2..], [0, 0]);
+    }
+
+    #[test]
+    fn oversized_prefixed_bytes_are_rejected() {
+        let record = record(Record::TSIG {
+            algorithm: name("hmac-sha256"),
+            time_signed: 0,
+            fudge: 300,
+            mac: Cow::Owned(vec![0; 0x10000]),
+            original_id: 0,
+            error: RCode::NoError,
+            other: Cow::Borrowed(&[]),
+        });
+
+        let mut buf = vec![0; record.size()];
+        assert!(matches!(
+            record.serialize(&mut Serializer::new(&mut buf)),
+            Err(SerializeError::InvalidLength(0x10000))
+        ));
+    }
+
+    #[test]
+    fn custom() {
+        round_trip(record(Record::Custom {
+            r#type: PAIR,
+            data: Arc::new(Pair(7, 0xdead_beef)),
+        }));
+    }
+
+    #[test]
+    fn opt() {
+        round_trip(ResourceRecord::OPTRecord {
+            size: 1232,
+            flags: 0x8000,
+            options: Vec::new(),
+        });
+        round_trip(ResourceRecord::OPTRecord {
+            size: 4096,
+            flags: 0,
+            options: vec![Option::Unknown {
+                code: OptionCode::from(65001),
+                len: 3,
+                data: Cow::Borrowed(b"abc"),
+            }],
+        });
+    }
+
+    #[test]
+    fn unknown() {
+        round_trip(ResourceRecord::Unknown {
+            name: name("example.com"),
+            r#type: Type::Unknown(65281),
+            class: Class::IN,
+            ttl: 60,
+            data: Cow::Borrowed(&[1, 2, 3, 4]),
+        });
+    }
+}
@@ -0,0 +1,379 @@
+//! Serde support behind the `serde` feature.
+//!
+//! Values use their presentation format where there is one: names are written as
+//! absolute names, types and classes as mnemonics, addresses in their textual form,
+//! character strings with `\DDD` escapes and other binary data as base64. Records are
+//! tagged with their type and carry the rdata fields in `data`.
+
+use alloc::borrow::Cow;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Display;
+use core::str::{self, FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error};
+
+use crate::{DomainName, class::Class, r#type::Type};
+
+/// Serializes through [`Display`] and deserializes through [`FromStr`].
+macro_rules! string_impl {
+    ($ty:ty) => {
+        impl Serialize for $ty {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                from_str(deserializer)
+            }
+        }
+    };
+}
+
+string_impl!(Type);
+string_impl!(Class);
+
+impl Serialize for DomainName<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Deserialized names always own their labels.
+impl<'de> Deserialize<'de> for DomainName<'_> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        from_str::<DomainName<'static>, D>(deserializer)
+    }
+}
+
+fn from_str<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: FromStr,
+    T::Err: Display,
+    D: Deserializer<'de>,
+{
+    let s = Cow::<str>::deserialize(deserializer)?;
+    s.parse().map_err(D::Error::custom)
+}
+
+/// Binary data as base64 with padding.
+pub(crate) mod base64 {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&crate::base64::encode(data))
+    }
+
+    pub fn deserialize<'de, 'a, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Cow<'a, [u8]>, D::Error> {
+        let encoded = Cow::<str>::deserialize(deserializer)?;
+
+        crate::base64::decode(&encoded)
+            .map(Cow::Owned)
+            .ok_or_else(|| D::Error::custom(format_args!("invalid base64 {:?}", encoded)))
+    }
+}
+
+/// A record address as its textual IPv4 form.
+pub(crate) mod ipv4 {
+    use core::net::Ipv4Addr;
+
+    use super::*;
+
+    pub fn serialize<S: Serializer>(address: &[u8; 4], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&Ipv4Addr::from(*address))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 4], D::Error> {
+        from_str::<Ipv4Addr, D>(deserializer).map(|address| address.octets())
+    }
+}
+
+/// A record address as its textual IPv6 form.
+pub(crate) mod ipv6 {
+    use core::net::Ipv6Addr;
+
+    use super::*;
+
+    pub fn serialize<S: Serializer>(address: &[u8; 16], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&Ipv6Addr::from(*address))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 16], D::Error> {
+        from_str::<Ipv6Addr, D>(deserializer).map(|address| address.octets())
+    }
+}
+
+/// A sequence of `<character-string>`s as a list of strings, as per
+/// [RFC 1035 Section 5.1](https://www.rfc-editor.org/rfc/rfc1035#section-5.1).
+///
+/// Bytes outside of printable ASCII and backslashes are escaped as `\DDD`.
+pub(crate) mod character_strings {
+    use core::fmt::Write;
+
+    use serde::ser::{Error as _, SerializeSeq};
+
+    use super::*;
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+
+        let mut rest = data;
+        while let Some((&len, tail)) = rest.split_first() {
+            let Some((string, tail)) = tail.split_at_checked(len.into()) else {
+                return Err(S::Error::custom("character string exceeds rdata"));
+            };
+
+            let mut escaped = String::with_capacity(string.len());
+            for &byte in string {
+                match byte {
+                    b'\\' => escaped.push_str("\\\\"),
+                    0x20..=0x7E => escaped.push(byte as char),
+                    _ => write!(escaped, "\\{:03}", byte).unwrap(),
+                }
+            }
+
+            seq.serialize_element(&escaped)?;
+            rest = tail;
+        }
+
+        seq.end()
+    }
+
+    pub fn deserialize<'de, 'a, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Cow<'a, [u8]>, D::Error> {
+        let strings = Vec::<Cow<str>>::deserialize(deserializer)?;
+
+        let mut data = Vec::new();
+        for string in strings {
+            let len_pos = data.len();
+            data.push(0);
+
+            let mut bytes = string.bytes();
+            while let Some(byte) = bytes.next() {
+                if byte != b'\\' {
+                    data.push(byte);
+                    continue;
+                }
+
+                let byte = match bytes.next() {
+                    Some(digit @ b'0'..=b'9') => {
+                        let digits = [digit, bytes.next().unwrap_or(0), bytes.next().unwrap_or(0)];
+                        str::from_utf8(&digits)
+                            .ok()
+                            .and_then(|digits| digits.parse::<u8>().ok())
+                            .ok_or(D::Error::custom("invalid \\DDD escape"))?
+                    }
+                    Some(byte) => byte,
+                    None => return Err(D::Error::custom("trailing backslash")),
+                };
+                data.push(byte);
+            }
+
+            let len = data.len() - len_pos - 1;
+            data[len_pos] = u8::try_from(len)
+                .map_err(|_| D::Error::custom("character string longer than 255 bytes"))?;
+        }
+
+        Ok(Cow::Owned(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use alloc::string::ToString;
+
+    use serde_json::{Value, from_value, json, to_value};
+
+    use super::*;
+    use crate::{Record, ResourceRecord};
+
+    fn record(data: Record<'static>) -> ResourceRecord<'static> {
+        ResourceRecord::Record {
+            name: "www.example.com".parse().unwrap(),
+            class: Class::IN,
+            ttl: 300,
+            data,
+        }
+    }
+
+    /// Serializes `record` into `expected` and deserializes that back into `record`.
+    fn round_trip(record: ResourceRecord<'static>, expected: Value) {
+        let value = to_value(&record).unwrap();
+        assert_eq!(value, expected);
+
+        let parsed: ResourceRecord = from_value(value).unwrap();
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", record));
+    }
+
+    /// The error of deserializing a record of type `r#type` with `data`.
+    fn error(r#type: &str, data: Value) -> String {
+        let value = json!({
+            "name": "www.example.com.",
+            "class": "IN",
+            "ttl": 300,
+            "type": r#type,
+            "data": data,
+        });
+
+        // the untagged record enum hides errors of its variants
+        let data = json!({ "type": r#type, "data": value["data"] });
+        assert!(from_value::<ResourceRecord>(value).is_err());
+        from_value::<Record>(data).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn names() {
+        let name: DomainName = "www.example.com".parse().unwrap();
+        assert_eq!(to_value(&name).unwrap(), json!("www.example.com."));
+        assert_eq!(
+            from_value::<DomainName>(json!("www.example.com.")).unwrap(),
+            name
+        );
+        assert_eq!(
+            from_value::<DomainName>(json!("www.example.com")).unwrap(),
+            name
+        );
+
+        assert!(from_value::<DomainName>(json!("www..example.com")).is_err());
+        assert!(from_value::<DomainName>(json!(["www", "example", "com"])).is_err());
+    }
+
+    #[test]
+    fn types_and_classes() {
+        assert_eq!(to_value(Type::AAAA).unwrap(), json!("AAAA"));
+        assert_eq!(to_value(Type::from(65280)).unwrap(), json!("TYPE65280"));
+        assert_eq!(to_value(Class::CH).unwrap(), json!("CH"));
+        assert_eq!(to_value(Class::from(1234)).unwrap(), json!("CLASS1234"));
+
+        assert_eq!(from_value::<Type>(json!("aaaa")).unwrap(), Type::AAAA);
+        assert_eq!(
+            from_value::<Type>(json!("TYPE65280")).unwrap(),
+            Type::from(65280)
+        );
+        assert_eq!(from_value::<Class>(json!("in")).unwrap(), Class::IN);
+        assert_eq!(
+            from_value::<Class>(json!("CLASS1234")).unwrap(),
+            Class::from(1234)
+        );
+
+        assert!(from_value::<Type>(json!("BOGUS")).is_err());
+        assert!(from_value::<Type>(json!(28)).is_err());
+        assert!(from_value::<Class>(json!("CLASS65536")).is_err());
+    }
+
+    #[test]
+    fn addresses() {
+        round_trip(
+            record(Record::A {
+                address: [192, 0, 2, 1],
+            }),
+            json!({
+                "name": "www.example.com.",
+                "class": "IN",
+                "ttl": 300,
+                "type": "A",
+                "data": { "address": "192.0.2.1" },
+            }),
+        );
+        round_trip(
+            record(Record::AAAA {
+                address: [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            }),
+            json!({
+                "name": "www.example.com.",
+                "class": "IN",
+                "ttl": 300,
+                "type": "AAAA",
+                "data": { "address": "2001:db8::1" },
+            }),
+        );
+
+        assert!(error("A", json!({ "address": "192.0.2" })).contains("invalid"));
+        assert!(error("A", json!({ "address": "2001:db8::1" })).contains("invalid"));
+        assert!(error("AAAA", json!({ "address": "192.0.2.1" })).contains("invalid"));
+    }
+
+    #[test]
+    fn base64() {
+        round_trip(
+            record(Record::KEY {
+                flags: 0x0200,
+                protocol: 3,
+                algorithm: 15,
+                public_key: Cow::Borrowed(b"foobar!"),
+            }),
+            json!({
+                "name": "www.example.com.",
+                "class": "IN",
+                "ttl": 300,
+                "type": "KEY",
+                "data": {
+                    "flags": 512,
+                    "protocol": 3,
+                    "algorithm": 15,
+                    "public_key": "Zm9vYmFyIQ==",
+                },
+            }),
+        );
+        round_trip(
+            ResourceRecord::Unknown {
+                name: "www.example.com".parse().unwrap(),
+                r#type: Type::from(65280),
+                class: Class::IN,
+                ttl: 300,
+                data: Cow::Borrowed(&[0, 1, 2]),
+            },
+            json!({
+                "name": "www.example.com.",
+                "type": "TYPE65280",
+                "class": "IN",
+                "ttl": 300,
+                "data": "AAEC",
+            }),
+        );
+
+        let key = |public_key| json!({ "flags": 0, "protocol": 3, "algorithm": 15, "public_key": public_key });
+        assert!(error("KEY", key("Zm9v*mFy")).contains("invalid base64"));
+        assert!(error("KEY", key("Zm9vY")).contains("invalid base64"));
+    }
+
+    #[test]
+    fn character_strings() {
+        round_trip(
+            record(Record::TXT {
+                text: Cow::Borrowed(b"\x05a\"\\\x00\xff\x00\x0bhello world"),
+            }),
+            json!({
+                "name": "www.example.com.",
+                "class": "IN",
+                "ttl": 300,
+                "type": "TXT",
+                "data": { "text": ["a\"\\\\\\000\\255", "", "hello world"] },
+            }),
+        );
+
+        // escapes of other characters stand for the character itself
+        let text: Record =
+            from_value(json!({ "type": "TXT", "data": { "text": ["\\a\\.\\065"] } })).unwrap();
+        assert!(matches!(text, Record::TXT { text } if *text == *b"\x03a.A"));
+
+        let text = |text: &str| json!({ "text": [text] });
+        assert!(error("TXT", text("\\256")).contains("invalid \\DDD escape"));
+        assert!(error("TXT", text("\\1")).contains("invalid \\DDD escape"));
+        assert!(error("TXT", text("trailing\\")).contains("trailing backslash"));
+        assert!(error("TXT", text(&"a".repeat(256))).contains("longer than 255 bytes"));
+
+        // a length beyond the rdata can't be serialized
+        let truncated = Record::TXT {
+            text: Cow::Borrowed(b"\x05abc"),
+        };
+        let error = to_value(&truncated).unwrap_err().to_string();
+        assert!(error.contains("character string exceeds rdata"));
+    }
+}
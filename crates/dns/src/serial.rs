@@ -0,0 +1,255 @@
+use core::cmp::Ordering;
+use core::fmt::Display;
+use core::ops::Add;
+
+use crate::proto::{Parse, ParseError, Parser, Serialize, SerializeError, Serializer};
+
+/// Serial number with the arithmetic of [RFC 1982](https://www.rfc-editor.org/rfc/rfc1982),
+/// used for zone serials and signature validity times.
+///
+/// Serials wrap around, a serial is greater than another if it is ahead of it by less
+/// than half of the number space. Serials exactly half the number space apart are not
+/// comparable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct Serial(pub u32);
+
+impl Serial {
+    /// Largest increment that keeps the result comparable to the original serial, as
+    /// per [RFC 1982 Section 3.1](https://www.rfc-editor.org/rfc/rfc1982#section-3.1).
+    pub const MAX_INCREMENT: u32 = (1 << 31) - 1;
+
+    pub fn size(&self) -> usize {
+        size_of::<u32>()
+    }
+}
+
+impl From<u32> for Serial {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Serial> for u32 {
+    fn from(value: Serial) -> Self {
+        value.0
+    }
+}
+
+/// Comparison as per [RFC 1982 Section 3.2](https://www.rfc-editor.org/rfc/rfc1982#section-3.2)
+impl PartialOrd for Serial {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match other.0.wrapping_sub(self.0) {
+            0 => Some(Ordering::Equal),
+            1..0x8000_0000 => Some(Ordering::Less),
+            0x8000_0000 => None,
+            _ => Some(Ordering::Greater),
+        }
+    }
+}
+
+/// Addition as per [RFC 1982 Section 3.1](https://www.rfc-editor.org/rfc/rfc1982#section-3.1),
+/// only defined for increments up to [`Serial::MAX_INCREMENT`].
+impl Add<u32> for Serial {
+    type Output = Self;
+
+    fn add(self, rhs: u32) -> Self::Output {
+        debug_assert!(
+            rhs <= Self::MAX_INCREMENT,
+            "serial increment {} too large",
+            rhs
+        );
+        Self(self.0.wrapping_add(rhs))
+    }
+}
+
+impl Display for Serial {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Parse<'_> for Serial {
+    fn parse(parser: &mut Parser<'_>) -> Result<Self, ParseError> {
+        Ok(Self(parser.consume_u32()?))
+    }
+}
+
+impl<'a> Serialize<'a> for Serial {
+    fn serialize(self, serializer: &mut Serializer<'a>) -> Result<usize, SerializeError> {
+        serializer.write_u32(self.0)?;
+
+        Ok(serializer.position())
+    }
+}
+
+/// How the serial of a zone advances when the zone changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum SerialPolicy {
+    /// The serial is incremented by one.
+    #[default]
+    Increment,
+
+    /// The serial is the time of the change in seconds since the epoch.
+    UnixTime,
+
+    /// The serial is the date of the change as `YYYYMMDDnn`, with `nn` counting the
+    /// changes made on that day.
+    Date,
+}
+
+impl SerialPolicy {
+    /// The serial following `current` for a change at `now`, in seconds since the epoch.
+    ///
+    /// The result is always greater than `current`. When the policy would not advance
+    /// the serial, because the clock went back or a day saw more than 100 changes, it
+    /// is incremented by one instead.
+    pub fn next(self, current: Serial, now: u64) -> Serial {
+        let next = match self {
+            Self::Increment => current + 1,
+            Self::UnixTime => Serial(now as u32),
+            Self::Date => {
+                let (year, month, day) = civil_from_days((now / 86_400) as i64);
+                Serial((year * 10_000 + month * 100 + day).wrapping_mul(100))
+            }
+        };
+
+        if next > current { next } else { current + 1 }
+    }
+}
+
+/// Converts days since the epoch into a proleptic Gregorian (year, month, day), after
+/// Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (u32, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year as u32, month as u32, day as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-02-29 00:00:00 UTC
+    const LEAP_DAY: u64 = 1_709_164_800;
+
+    #[test]
+    fn comparison_wraps_around() {
+        assert!(Serial(1) > Serial(0));
+        assert!(Serial(0) > Serial(u32::MAX));
+        assert!(Serial(0x7fff_ffff) > Serial(0));
+        assert!(Serial(0x8000_0001) < Serial(0));
+        assert!(Serial(5) > Serial(0x8000_0006));
+        assert_eq!(Serial(7).partial_cmp(&Serial(7)), Some(Ordering::Equal));
+
+        // serials half the number space apart are neither equal, lower nor greater
+        for (a, b) in [(0, 0x8000_0000), (0x8000_0000, 0), (u32::MAX, 0x7fff_ffff)] {
+            assert_eq!(Serial(a).partial_cmp(&Serial(b)), None);
+            assert_ne!(Serial(a), Serial(b));
+        }
+    }
+
+    #[test]
+    fn addition_wraps_around() {
+        assert_eq!(Serial(u32::MAX) + 1, Serial(0));
+        assert_eq!(Serial(0xffff_fff0) + 0x20, Serial(0x10));
+
+        let serial = Serial(0x9000_0000) + Serial::MAX_INCREMENT;
+        assert_eq!(serial, Serial(0x0fff_ffff));
+        assert!(serial > Serial(0x9000_0000));
+    }
+
+    #[test]
+    #[should_panic(expected = "serial increment 2147483648 too large")]
+    #[cfg(debug_assertions)]
+    fn increments_beyond_the_maximum_are_undefined() {
+        let _ = Serial(1) + (Serial::MAX_INCREMENT + 1);
+    }
+
+    #[test]
+    fn increment_policy() {
+        let policy = SerialPolicy::Increment;
+        assert_eq!(policy.next(Serial(41), LEAP_DAY), Serial(42));
+        assert_eq!(policy.next(Serial(u32::MAX), LEAP_DAY), Serial(0));
+    }
+
+    #[test]
+    fn unix_time_policy() {
+        let policy = SerialPolicy::UnixTime;
+        assert_eq!(policy.next(Serial(1), LEAP_DAY), Serial(LEAP_DAY as u32));
+
+        // two changes within a second, or a clock that went back
+        let current = Serial(LEAP_DAY as u32);
+        assert_eq!(policy.next(current, LEAP_DAY), current + 1);
+        assert_eq!(policy.next(current, LEAP_DAY - 3_600), current + 1);
+    }
+
+    #[test]
+    fn date_policy() {
+        let policy = SerialPolicy::Date;
+
+        // the first change of a day
+        assert_eq!(
+            policy.next(Serial(2024022805), LEAP_DAY),
+            Serial(2024022900)
+        );
+        assert_eq!(
+            policy.next(Serial(1), LEAP_DAY + 86_399),
+            Serial(2024022900)
+        );
+
+        // further changes on the same day bump nn
+        assert_eq!(
+            policy.next(Serial(2024022900), LEAP_DAY),
+            Serial(2024022901)
+        );
+        assert_eq!(
+            policy.next(Serial(2024022941), LEAP_DAY + 60),
+            Serial(2024022942)
+        );
+
+        // the 100th change overflows nn and is counted towards an unused date, which the
+        // next day still advances from
+        assert_eq!(
+            policy.next(Serial(2024022999), LEAP_DAY),
+            Serial(2024023000)
+        );
+        assert_eq!(
+            policy.next(Serial(2024023000), LEAP_DAY),
+            Serial(2024023001)
+        );
+        assert_eq!(
+            policy.next(Serial(2024023001), LEAP_DAY + 86_400),
+            Serial(2024030100)
+        );
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(59), (1970, 3, 1));
+        assert_eq!(civil_from_days(10_956), (1999, 12, 31));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days((LEAP_DAY / 86_400) as i64), (2024, 2, 29));
+        assert_eq!(civil_from_days(47_540), (2100, 2, 28));
+        assert_eq!(civil_from_days(47_541), (2100, 3, 1));
+    }
+}
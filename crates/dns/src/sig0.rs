@@ -0,0 +1,570 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+use p256::ecdsa::signature::{Signer as _, Verifier as _};
+
+use crate::{
+    header::{Header, RCode},
+    packet::Packet,
+    proto::{Parse, ParseError, Parser, Serialize, SerializeError, Serializer},
+    question::Question,
+    rr::{Record, ResourceRecord},
+    serial::Serial,
+};
+
+/// Protocol field of KEY records used for DNS, as per [RFC 3445 Section 1](https://www.rfc-editor.org/rfc/rfc3445#section-1)
+const PROTOCOL_DNSSEC: u8 = 3;
+
+/// SIG(0) algorithms, numbered as per [RFC 8624](https://www.rfc-editor.org/rfc/rfc8624#section-3.1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// [RFC 6605](https://www.rfc-editor.org/rfc/rfc6605)
+    EcdsaP256Sha256,
+
+    /// [RFC 8080](https://www.rfc-editor.org/rfc/rfc8080)
+    Ed25519,
+}
+
+impl TryFrom<u8> for Algorithm {
+    type Error = Sig0Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            13 => Ok(Self::EcdsaP256Sha256),
+            15 => Ok(Self::Ed25519),
+            _ => Err(Sig0Error::BadKey),
+        }
+    }
+}
+
+impl From<Algorithm> for u8 {
+    fn from(val: Algorithm) -> Self {
+        match val {
+            Algorithm::EcdsaP256Sha256 => 13,
+            Algorithm::Ed25519 => 15,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SigningKey {
+    EcdsaP256(p256::ecdsa::SigningKey),
+    Ed25519(ed25519_dalek::SigningKey),
+}
+
+impl SigningKey {
+    pub fn algorithm(&self) -> Algorithm {
+        match self {
+            Self::EcdsaP256(_) => Algorithm::EcdsaP256Sha256,
+            Self::Ed25519(_) => Algorithm::Ed25519,
+        }
+    }
+
+    /// The public key in the format used by KEY records.
+    pub fn public_key(&self) -> Vec<u8> {
+        match self {
+            // uncompressed point without the leading 0x04, as per RFC 6605 Section 4
+            Self::EcdsaP256(key) => {
+                key.verifying_key().to_encoded_point(false).as_bytes()[1..].to_vec()
+            }
+            Self::Ed25519(key) => key.verifying_key().to_bytes().to_vec(),
+        }
+    }
+
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::EcdsaP256(key) => {
+                let signature: p256::ecdsa::Signature = key.sign(data);
+                signature.to_bytes().to_vec()
+            }
+            Self::Ed25519(key) => key.sign(data).to_bytes().to_vec(),
+        }
+    }
+}
+
+/// A private key together with the owner name and flags of the KEY record publishing it.
+#[derive(Debug)]
+pub struct Signer {
+    pub name: String,
+    pub flags: u16,
+    pub key: SigningKey,
+}
+
+impl Signer {
+    pub fn key_tag(&self) -> u16 {
+        let mut rdata = Vec::new();
+        rdata.extend_from_slice(&self.flags.to_be_bytes());
+        rdata.push(PROTOCOL_DNSSEC);
+        rdata.push(self.key.algorithm().into());
+        rdata.extend_from_slice(&self.key.public_key());
+
+        key_tag(&rdata)
+    }
+
+    /// Serializes and signs a packet, see [`Signer::sign`].
+    pub fn sign_packet(
+        &self,
+        packet: Packet,
+        inception: u32,
+        expiration: u32,
+    ) -> Result<Vec<u8>, Sig0Error> {
+        let mut message = vec![0; packet.size()];
+        let len = packet.serialize(&mut Serializer::new(&mut message))?;
+
+        message.truncate(len);
+        self.sign(&mut message, inception, expiration)?;
+
+        Ok(message)
+    }
+
+    /// Signs a serialized message, appending the SIG(0) record and incrementing ARCOUNT
+    /// as per [RFC 2931 Section 3](https://www.rfc-editor.org/rfc/rfc2931#section-3).
+    ///
+    /// The signature is valid from `inception` until `expiration`, both in seconds since
+    /// the epoch.
+    pub fn sign(
+        &self,
+        message: &mut Vec<u8>,
+        inception: u32,
+        expiration: u32,
+    ) -> Result<(), Sig0Error> {
+        if message.len() < 12 {
+            return Err(Sig0Error::FormErr);
+        }
+
+        let mut rdata = Vec::new();
+        put_rdata(
+            &mut rdata,
+            self.key.algorithm().into(),
+            expiration,
+            inception,
+            self.key_tag(),
+            labels(&self.name),
+        );
+
+        let mut data = rdata.clone();
+        data.extend_from_slice(message);
+        rdata.extend_from_slice(&self.key.sign(&data));
+
+        let arcount = u16::from_be_bytes([message[10], message[11]])
+            .checked_add(1)
+            .ok_or(Sig0Error::FormErr)?;
+        message[10..12].copy_from_slice(&arcount.to_be_bytes());
+
+        message.push(0);
+        message.extend_from_slice(&24u16.to_be_bytes());
+        message.extend_from_slice(&255u16.to_be_bytes());
+        message.extend_from_slice(&0u32.to_be_bytes());
+        message.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        message.extend_from_slice(&rdata);
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum Sig0Error {
+    Parse(ParseError),
+    Serialize(SerializeError),
+    FormErr,
+    Unsigned,
+    BadKey,
+    BadSig,
+    BadTime,
+}
+
+impl Sig0Error {
+    /// The rcode to put into the header of a response to the failed message.
+    pub fn rcode(&self) -> RCode {
+        match self {
+            Self::Parse(_) | Self::FormErr => RCode::FormatErr,
+            Self::Serialize(_) => RCode::ServFail,
+            _ => RCode::NotAuth,
+        }
+    }
+}
+
+impl Display for Sig0Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Parse(err) => write!(f, "malformed signed message: {}", err),
+            Self::Serialize(err) => write!(f, "failed to serialize message: {:?}", err),
+            Self::FormErr => f.write_str("malformed sig(0) record"),
+            Self::Unsigned => f.write_str("message is not signed"),
+            Self::BadKey => f.write_str("no matching key record"),
+            Self::BadSig => f.write_str("signature does not match"),
+            Self::BadTime => f.write_str("signature is not valid at this time"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Sig0Error {}
+
+impl From<ParseError> for Sig0Error {
+    fn from(err: ParseError) -> Self {
+        Self::Parse(err)
+    }
+}
+
+impl From<SerializeError> for Sig0Error {
+    fn from(err: SerializeError) -> Self {
+        Self::Serialize(err)
+    }
+}
+
+/// Verifies the SIG(0) record of a serialized message against the KEY records in
+/// `keys`, as per [RFC 2931 Section 3.2](https://www.rfc-editor.org/rfc/rfc2931#section-3.2).
+///
+/// `now` is the current time in seconds since the epoch. Returns the KEY record the
+/// message was signed with.
+pub fn verify<'k, 'r>(
+    message: &[u8],
+    keys: &'k [ResourceRecord<'r>],
+    now: u32,
+) -> Result<&'k ResourceRecord<'r>, Sig0Error> {
+    let mut parser = Parser::new(message);
+    let header = Header::parse(&mut parser)?;
+
+    if header.arcount == 0 {
+        return Err(Sig0Error::Unsigned);
+    }
+
+    for _ in 0..header.qdcount {
+        Question::parse(&mut parser)?;
+    }
+
+    let records = header.ancount as usize + header.nscount as usize + header.arcount as usize;
+    for _ in 0..records - 1 {
+        ResourceRecord::parse(&mut parser)?;
+    }
+
+    let start = parser.position();
+    let ResourceRecord::Record {
+        name,
+        data:
+            Record::SIG {
+                algorithm,
+                expiration,
+                inception,
+                key_tag: tag,
+                signer,
+                signature,
+                ..
+            },
+        ..
+    } = ResourceRecord::parse(&mut parser)?
+    else {
+        return Err(Sig0Error::Unsigned);
+    };
+
+    if !name.labels.is_empty() {
+        return Err(Sig0Error::FormErr);
+    }
+
+    let algorithm = Algorithm::try_from(algorithm)?;
+    let key = keys
+        .iter()
+        .find(|key| match key {
+            ResourceRecord::Record {
+                name,
+                data:
+                    data @ Record::KEY {
+                        protocol,
+                        algorithm: key_algorithm,
+                        ..
+                    },
+                ..
+            } => {
+                *protocol == PROTOCOL_DNSSEC
+                    && *key_algorithm == u8::from(algorithm)
+                    && *name == signer
+                    && key_tag(&key_rdata(data)) == tag
+            }
+            _ => false,
+        })
+        .ok_or(Sig0Error::BadKey)?;
+
+    let Some(public_key) = (match key {
+        ResourceRecord::Record {
+            data: Record::KEY { public_key, .. },
+            ..
+        } => Some(public_key.as_ref()),
+        _ => None,
+    }) else {
+        return Err(Sig0Error::BadKey);
+    };
+
+    let mut data = Vec::new();
+    put_rdata(
+        &mut data,
+        algorithm.into(),
+        expiration,
+        inception,
+        tag,
+        signer.labels.iter().map(|label| label.as_ref()),
+    );
+    let offset = data.len();
+    data.extend_from_slice(&message[..start]);
+    data[offset + 10..offset + 12].copy_from_slice(&(header.arcount - 1).to_be_bytes());
+
+    check_signature(algorithm, public_key, &data, &signature)?;
+
+    // validity times wrap around and are compared with serial number arithmetic
+    let now = Serial(now);
+    if !(Serial(inception) <= now && now <= Serial(expiration)) {
+        return Err(Sig0Error::BadTime);
+    }
+
+    Ok(key)
+}
+
+fn check_signature(
+    algorithm: Algorithm,
+    public_key: &[u8],
+    data: &[u8],
+    signature: &[u8],
+) -> Result<(), Sig0Error> {
+    match algorithm {
+        Algorithm::EcdsaP256Sha256 => {
+            let mut point = vec![0x04];
+            point.extend_from_slice(public_key);
+
+            let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(&point)
+                .map_err(|_| Sig0Error::BadKey)?;
+            let signature =
+                p256::ecdsa::Signature::from_slice(signature).map_err(|_| Sig0Error::BadSig)?;
+
+            key.verify(data, &signature).map_err(|_| Sig0Error::BadSig)
+        }
+        Algorithm::Ed25519 => {
+            let key = ed25519_dalek::VerifyingKey::from_bytes(
+                public_key.try_into().map_err(|_| Sig0Error::BadKey)?,
+            )
+            .map_err(|_| Sig0Error::BadKey)?;
+            let signature = ed25519_dalek::Signature::from_bytes(
+                signature.try_into().map_err(|_| Sig0Error::BadSig)?,
+            );
+
+            key.verify(data, &signature).map_err(|_| Sig0Error::BadSig)
+        }
+    }
+}
+
+/// Key tag calculation as per [RFC 4034 Appendix B](https://www.rfc-editor.org/rfc/rfc4034#appendix-B)
+pub fn key_tag(rdata: &[u8]) -> u16 {
+    let mut ac: u32 = 0;
+
+    for (i, byte) in rdata.iter().enumerate() {
+        ac += match i & 1 {
+            0 => u32::from(*byte) << 8,
+            _ => u32::from(*byte),
+        };
+    }
+
+    ac += (ac >> 16) & 0xFFFF;
+    (ac & 0xFFFF) as u16
+}
+
+fn key_rdata(record: &Record) -> Vec<u8> {
+    let mut rdata = Vec::new();
+
+    if let Record::KEY {
+        flags,
+        protocol,
+        algorithm,
+        public_key,
+    } = record
+    {
+        rdata.extend_from_slice(&flags.to_be_bytes());
+        rdata.push(*protocol);
+        rdata.push(*algorithm);
+        rdata.extend_from_slice(public_key);
+    }
+
+    rdata
+}
+
+/// The SIG(0) rdata without the signature, with the signer's name in canonical form.
+fn put_rdata<'l>(
+    data: &mut Vec<u8>,
+    algorithm: u8,
+    expiration: u32,
+    inception: u32,
+    key_tag: u16,
+    signer: impl Iterator<Item = &'l str>,
+) {
+    data.extend_from_slice(&0u16.to_be_bytes());
+    data.push(algorithm);
+    data.push(0);
+    data.extend_from_slice(&0u32.to_be_bytes());
+    data.extend_from_slice(&expiration.to_be_bytes());
+    data.extend_from_slice(&inception.to_be_bytes());
+    data.extend_from_slice(&key_tag.to_be_bytes());
+
+    for label in signer {
+        data.push(label.len() as u8);
+        data.extend(label.bytes().map(|b| b.to_ascii_lowercase()));
+    }
+    data.push(0);
+}
+
+fn labels(name: &str) -> impl Iterator<Item = &str> {
+    name.split('.').filter(|label| !label.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::borrow::Cow;
+    use alloc::string::ToString;
+
+    use super::*;
+    use crate::{class::Class, domain_name::DomainName};
+
+    const INCEPTION: u32 = 1_700_000_000;
+    const EXPIRATION: u32 = INCEPTION + 300;
+
+    /// A query for the SOA of `example.com.` with id 0x1234 and RD set.
+    const QUERY: &str = "123401000001000000000000076578616d706c6503636f6d0000060001";
+
+    /// The private key of RFC 8032 Section 7.1, test 1.
+    const ED25519_KEY: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+
+    /// The private key of RFC 6979 Appendix A.2.5.
+    const P256_KEY: &str = "c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721";
+
+    /// The query signed by `example.com.`, computed independently. Both algorithms sign
+    /// deterministically, ECDSA with the nonces of RFC 6979.
+    const SIGNED_ED25519: &str = "123401000001000000000001076578616d706c6503636f6d00000600010000\
+        1800ff00000000005f00000f00000000006553f22c6553f10037c0076578616d706c6503636f6d001ff552dc\
+        7a2bec0a520814bc098f62aaad9bf5b595cb915a156944fe47f10fc56ff15a46d26e144ac6afe411464c837b\
+        01a9febf8bf0ac3b369310a75becbc0b";
+    const SIGNED_P256: &str = "123401000001000000000001076578616d706c6503636f6d000006000100001800\
+        ff00000000005f00000d00000000006553f22c6553f1005d91076578616d706c6503636f6d006a3a477317d3\
+        f5fd5da0c3cfd6bedbc9ed249e16f5781207105afd3895db6a604ef6e6d17171b86061a7d48c1542fc55afe0\
+        93428f794b7a8ce2f85a2e903020";
+
+    fn hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn signer(key: SigningKey) -> Signer {
+        Signer {
+            name: "example.com.".to_string(),
+            flags: 0x0200,
+            key,
+        }
+    }
+
+    fn ed25519() -> Signer {
+        let key: [u8; 32] = hex(ED25519_KEY).try_into().unwrap();
+        signer(SigningKey::Ed25519(ed25519_dalek::SigningKey::from_bytes(
+            &key,
+        )))
+    }
+
+    fn p256() -> Signer {
+        signer(SigningKey::EcdsaP256(
+            p256::ecdsa::SigningKey::from_slice(&hex(P256_KEY)).unwrap(),
+        ))
+    }
+
+    /// The KEY record publishing the public key of `signer`.
+    fn key_record(signer: &Signer) -> ResourceRecord<'static> {
+        ResourceRecord::Record {
+            name: DomainName::from(vec!["example", "com"]).into_owned(),
+            class: Class::IN,
+            ttl: 300,
+            data: Record::KEY {
+                flags: signer.flags,
+                protocol: PROTOCOL_DNSSEC,
+                algorithm: signer.key.algorithm().into(),
+                public_key: Cow::Owned(signer.key.public_key()),
+            },
+        }
+    }
+
+    #[test]
+    fn sign_known_answer() {
+        for (signer, expected, tag) in [
+            (ed25519(), SIGNED_ED25519, 14272),
+            (p256(), SIGNED_P256, 23953),
+        ] {
+            assert_eq!(signer.key_tag(), tag);
+
+            let mut message = hex(QUERY);
+            signer.sign(&mut message, INCEPTION, EXPIRATION).unwrap();
+            assert_eq!(message, hex(expected));
+
+            let keys = [key_record(&signer)];
+            assert!(verify(&message, &keys, INCEPTION + 60).is_ok());
+        }
+    }
+
+    #[test]
+    fn sign_packet_matches_sign() {
+        let signer = ed25519();
+        let packet = Packet::parse(&mut Parser::new(&hex(QUERY)))
+            .unwrap()
+            .into_owned();
+
+        let message = signer.sign_packet(packet, INCEPTION, EXPIRATION).unwrap();
+        assert_eq!(message, hex(SIGNED_ED25519));
+    }
+
+    #[test]
+    fn verify_rejects_tampering() {
+        let signer = p256();
+        let keys = [key_record(&signer)];
+
+        let mut message = hex(SIGNED_P256);
+        message[2] ^= 0x80;
+        assert!(matches!(
+            verify(&message, &keys, INCEPTION),
+            Err(Sig0Error::BadSig)
+        ));
+    }
+
+    #[test]
+    fn verify_checks_validity_period() {
+        let signer = ed25519();
+        let keys = [key_record(&signer)];
+        let message = hex(SIGNED_ED25519);
+
+        assert!(verify(&message, &keys, INCEPTION).is_ok());
+        assert!(verify(&message, &keys, EXPIRATION).is_ok());
+        assert!(matches!(
+            verify(&message, &keys, INCEPTION - 1),
+            Err(Sig0Error::BadTime)
+        ));
+        assert!(matches!(
+            verify(&message, &keys, EXPIRATION + 1),
+            Err(Sig0Error::BadTime)
+        ));
+    }
+
+    #[test]
+    fn verify_needs_matching_key() {
+        let message = hex(SIGNED_ED25519);
+
+        // a key of another algorithm, and no key at all
+        let keys = [key_record(&p256())];
+        assert!(matches!(
+            verify(&message, &keys, INCEPTION),
+            Err(Sig0Error::BadKey)
+        ));
+        assert!(matches!(
+            verify(&message, &[], INCEPTION),
+            Err(Sig0Error::BadKey)
+        ));
+        assert!(matches!(
+            verify(&hex(QUERY), &keys, INCEPTION),
+            Err(Sig0Error::Unsigned)
+        ));
+    }
+}
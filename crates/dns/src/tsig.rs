@@ -0,0 +1,868 @@
+use alloc::borrow::Cow;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+use hmac::{Hmac, Mac, digest::KeyInit};
+use sha2::{Sha256, Sha384, Sha512};
+
+use crate::{
+    Class, DomainName,
+    header::{Header, RCode},
+    proto::{Parse, ParseError, Parser},
+    question::Question,
+    rr::{Record, ResourceRecord},
+};
+
+/// Fudge recommended by [RFC 8945 Section 10](https://www.rfc-editor.org/rfc/rfc8945#section-10)
+pub const DEFAULT_FUDGE: u16 = 300;
+
+/// Messages of a stream that may follow each other without a signature, as per
+/// [RFC 8945 Section 5.3.1](https://www.rfc-editor.org/rfc/rfc8945#section-5.3.1)
+const MAX_UNSIGNED: usize = 99;
+
+/// TSIG algorithms as per [RFC 8945 Section 6](https://www.rfc-editor.org/rfc/rfc8945#section-6)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+
+impl Algorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::HmacSha256 => "hmac-sha256.",
+            Self::HmacSha384 => "hmac-sha384.",
+            Self::HmacSha512 => "hmac-sha512.",
+        }
+    }
+
+    pub fn from_name(name: &DomainName) -> Option<Self> {
+        [Self::HmacSha256, Self::HmacSha384, Self::HmacSha512]
+            .into_iter()
+            .find(|algorithm| name_matches(algorithm.name(), name))
+    }
+
+    pub fn output_len(&self) -> usize {
+        match self {
+            Self::HmacSha256 => 32,
+            Self::HmacSha384 => 48,
+            Self::HmacSha512 => 64,
+        }
+    }
+
+    fn mac(&self, secret: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::HmacSha256 => digest::<Hmac<Sha256>>(secret, data),
+            Self::HmacSha384 => digest::<Hmac<Sha384>>(secret, data),
+            Self::HmacSha512 => digest::<Hmac<Sha512>>(secret, data),
+        }
+    }
+}
+
+fn digest<M: Mac + KeyInit>(secret: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = <M as Mac>::new_from_slice(secret).expect("hmac accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// A shared secret identified by its key name, e.g. `update.example.com.`
+#[derive(Debug, Clone)]
+pub struct Key {
+    pub name: String,
+    pub algorithm: Algorithm,
+    pub secret: Vec<u8>,
+}
+
+/// Lookup of the keys a message may be signed with.
+pub trait Keyring {
+    fn key(&self, name: &DomainName, algorithm: Algorithm) -> Option<&Key>;
+}
+
+impl Keyring for [Key] {
+    fn key(&self, name: &DomainName, algorithm: Algorithm) -> Option<&Key> {
+        self.iter()
+            .find(|key| key.algorithm == algorithm && name_matches(&key.name, name))
+    }
+}
+
+#[derive(Debug)]
+pub enum TsigError {
+    Parse(ParseError),
+    FormErr,
+    Unsigned,
+    BadSig,
+    BadKey,
+    BadTime,
+    BadTrunc,
+}
+
+impl TsigError {
+    /// The rcode to put into the header of a response to the failed message.
+    pub fn rcode(&self) -> RCode {
+        match self {
+            Self::Parse(_) | Self::FormErr => RCode::FormatErr,
+            _ => RCode::NotAuth,
+        }
+    }
+
+    /// The error to put into the TSIG record of a response to the failed message.
+    pub fn tsig_error(&self) -> RCode {
+        match self {
+            Self::BadKey => RCode::BADKEY,
+            Self::BadTime => RCode::BADTIME,
+            Self::BadTrunc => RCode::BADTRUNC,
+            _ => RCode::BADSIG,
+        }
+    }
+
+    fn from_tsig_error(error: RCode) -> Self {
+        match error {
+            RCode::BADKEY => Self::BadKey,
+            RCode::BADTIME => Self::BadTime,
+            RCode::BADTRUNC => Self::BadTrunc,
+            RCode::BADSIG => Self::BadSig,
+            _ => Self::FormErr,
+        }
+    }
+}
+
+impl Display for TsigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Parse(err) => write!(f, "malformed signed message: {}", err),
+            Self::FormErr => f.write_str("malformed tsig record"),
+            Self::Unsigned => f.write_str("message is not signed"),
+            Self::BadSig => f.write_str("mac does not match"),
+            Self::BadKey => f.write_str("unknown key or algorithm"),
+            Self::BadTime => f.write_str("time signed is outside the fudge"),
+            Self::BadTrunc => f.write_str("mac is truncated too far"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TsigError {}
+
+impl From<ParseError> for TsigError {
+    fn from(err: ParseError) -> Self {
+        Self::Parse(err)
+    }
+}
+
+/// The outcome of a successful [`verify`], needed to sign the response.
+#[derive(Debug)]
+pub struct Verified<'k> {
+    pub key: &'k Key,
+    pub mac: Vec<u8>,
+    pub time_signed: u64,
+}
+
+/// Signs a serialized message with `key`, appending the TSIG record and incrementing
+/// ARCOUNT as per [RFC 8945 Section 5.1](https://www.rfc-editor.org/rfc/rfc8945#section-5.1).
+///
+/// Responses are signed together with the MAC of the request. Returns the MAC, which
+/// is needed to verify the response to a signed request.
+pub fn sign(
+    message: &mut Vec<u8>,
+    key: &Key,
+    request_mac: Option<&[u8]>,
+    time_signed: u64,
+    fudge: u16,
+) -> Result<Vec<u8>, TsigError> {
+    let mut data = Vec::new();
+    put_mac(&mut data, request_mac);
+    data.extend_from_slice(message);
+    put_variables(
+        &mut data,
+        &key.name,
+        key.algorithm.name(),
+        time_signed,
+        fudge,
+        RCode::NoError,
+        &[],
+    );
+
+    let mac = key.algorithm.mac(&key.secret, &data);
+    append(
+        message,
+        &key.name,
+        key.algorithm.name(),
+        time_signed,
+        fudge,
+        &mac,
+        RCode::NoError,
+        &[],
+    )?;
+
+    Ok(mac)
+}
+
+/// Verifies the TSIG record of a serialized message as per
+/// [RFC 8945 Section 5.2](https://www.rfc-editor.org/rfc/rfc8945#section-5.2).
+///
+/// `request_mac` is the MAC of the request when verifying a response, `now` the current
+/// time in seconds since the epoch.
+pub fn verify<'k, K: Keyring + ?Sized>(
+    message: &[u8],
+    keyring: &'k K,
+    request_mac: Option<&[u8]>,
+    now: u64,
+) -> Result<Verified<'k>, TsigError> {
+    let tsig = Tsig::extract(message)?.ok_or(TsigError::Unsigned)?;
+
+    if tsig.mac.is_empty() && tsig.error != RCode::NoError {
+        return Err(TsigError::from_tsig_error(tsig.error));
+    }
+
+    let algorithm = Algorithm::from_name(&tsig.algorithm).ok_or(TsigError::BadKey)?;
+    let key = keyring
+        .key(&tsig.name, algorithm)
+        .ok_or(TsigError::BadKey)?;
+
+    let mut data = Vec::new();
+    put_mac(&mut data, request_mac);
+    data.extend_from_slice(&tsig.unsigned(message));
+    put_variables(
+        &mut data,
+        &tsig.name.to_string(),
+        algorithm.name(),
+        tsig.time_signed,
+        tsig.fudge,
+        tsig.error,
+        &tsig.other,
+    );
+
+    check_mac(key, &data, &tsig.mac, request_mac)?;
+
+    // signed error responses such as BADTIME
+    if tsig.error != RCode::NoError {
+        return Err(TsigError::from_tsig_error(tsig.error));
+    }
+
+    check_time(tsig.time_signed, tsig.fudge, now)?;
+
+    Ok(Verified {
+        key,
+        mac: tsig.mac.to_vec(),
+        time_signed: tsig.time_signed,
+    })
+}
+
+/// Appends the TSIG record answering a `request` that failed verification with `error`.
+///
+/// As per [RFC 8945 Section 5.3.2](https://www.rfc-editor.org/rfc/rfc8945#section-5.3.2)
+/// BADTIME responses are signed and carry the server time in their other data, any
+/// other error is answered with an empty MAC.
+pub fn sign_error<K: Keyring + ?Sized>(
+    response: &mut Vec<u8>,
+    request: &[u8],
+    keyring: &K,
+    error: &TsigError,
+    now: u64,
+) -> Result<(), TsigError> {
+    let tsig = Tsig::extract(request)?.ok_or(TsigError::Unsigned)?;
+    let name = tsig.name.to_string();
+    let algorithm = tsig.algorithm.to_string();
+
+    let key = Algorithm::from_name(&tsig.algorithm)
+        .and_then(|algorithm| keyring.key(&tsig.name, algorithm));
+
+    match (error, key) {
+        (TsigError::BadTime, Some(key)) => {
+            let other = &now.to_be_bytes()[2..];
+
+            let mut data = Vec::new();
+            put_mac(&mut data, Some(&tsig.mac));
+            data.extend_from_slice(response);
+            put_variables(
+                &mut data,
+                &name,
+                &algorithm,
+                tsig.time_signed,
+                tsig.fudge,
+                RCode::BADTIME,
+                other,
+            );
+
+            let mac = key.algorithm.mac(&key.secret, &data);
+            append(
+                response,
+                &name,
+                &algorithm,
+                tsig.time_signed,
+                tsig.fudge,
+                &mac,
+                RCode::BADTIME,
+                other,
+            )
+        }
+        _ => append(
+            response,
+            &name,
+            &algorithm,
+            now,
+            tsig.fudge,
+            &[],
+            error.tsig_error(),
+            &[],
+        ),
+    }
+}
+
+/// Signs the messages of a multi-message response such as a zone transfer, as per
+/// [RFC 8945 Section 5.3.1](https://www.rfc-editor.org/rfc/rfc8945#section-5.3.1).
+#[derive(Debug)]
+pub struct StreamSigner<'k> {
+    key: &'k Key,
+    fudge: u16,
+    prior_mac: Vec<u8>,
+    first: bool,
+}
+
+impl<'k> StreamSigner<'k> {
+    pub fn new(key: &'k Key, request_mac: &[u8], fudge: u16) -> Self {
+        Self {
+            key,
+            fudge,
+            prior_mac: request_mac.to_vec(),
+            first: true,
+        }
+    }
+
+    pub fn sign(&mut self, message: &mut Vec<u8>, time_signed: u64) -> Result<(), TsigError> {
+        if self.first {
+            self.first = false;
+            self.prior_mac = sign(
+                message,
+                self.key,
+                Some(&self.prior_mac),
+                time_signed,
+                self.fudge,
+            )?;
+            return Ok(());
+        }
+
+        let mut data = Vec::new();
+        put_mac(&mut data, Some(&self.prior_mac));
+        data.extend_from_slice(message);
+        put_timers(&mut data, time_signed, self.fudge);
+
+        let mac = self.key.algorithm.mac(&self.key.secret, &data);
+        append(
+            message,
+            &self.key.name,
+            self.key.algorithm.name(),
+            time_signed,
+            self.fudge,
+            &mac,
+            RCode::NoError,
+            &[],
+        )?;
+        self.prior_mac = mac;
+
+        Ok(())
+    }
+}
+
+/// Verifies the messages of a multi-message response such as a zone transfer, as per
+/// [RFC 8945 Section 5.3.1](https://www.rfc-editor.org/rfc/rfc8945#section-5.3.1).
+///
+/// The first and the last message must be signed, in between up to 99 unsigned
+/// messages may follow each other.
+#[derive(Debug)]
+pub struct StreamVerifier<'k> {
+    key: &'k Key,
+    prior_mac: Vec<u8>,
+    unsigned: Vec<u8>,
+    unsigned_count: usize,
+    first: bool,
+}
+
+impl<'k> StreamVerifier<'k> {
+    pub fn new(key: &'k Key, request_mac: &[u8]) -> Self {
+        Self {
+            key,
+            prior_mac: request_mac.to_vec(),
+            unsigned: Vec::new(),
+            unsigned_count: 0,
+            first: true,
+        }
+    }
+
+    /// Verifies the next message of the stream, returning whether it was signed.
+    pub fn verify(&mut self, message: &[u8], now: u64) -> Result<bool, TsigError> {
+        let Some(tsig) = Tsig::extract(message)? else {
+            if self.first || self.unsigned_count == MAX_UNSIGNED {
+                return Err(TsigError::Unsigned);
+            }
+
+            self.unsigned.extend_from_slice(message);
+            self.unsigned_count += 1;
+            return Ok(false);
+        };
+
+        if tsig.mac.is_empty() && tsig.error != RCode::NoError {
+            return Err(TsigError::from_tsig_error(tsig.error));
+        }
+
+        if Algorithm::from_name(&tsig.algorithm) != Some(self.key.algorithm)
+            || !name_matches(&self.key.name, &tsig.name)
+        {
+            return Err(TsigError::BadKey);
+        }
+
+        let mut data = Vec::new();
+        put_mac(&mut data, Some(&self.prior_mac));
+        data.append(&mut self.unsigned);
+        data.extend_from_slice(&tsig.unsigned(message));
+
+        if self.first {
+            put_variables(
+                &mut data,
+                &self.key.name,
+                self.key.algorithm.name(),
+                tsig.time_signed,
+                tsig.fudge,
+                tsig.error,
+                &tsig.other,
+            );
+        } else {
+            put_timers(&mut data, tsig.time_signed, tsig.fudge);
+        }
+
+        check_mac(self.key, &data, &tsig.mac, Some(&self.prior_mac))?;
+        check_time(tsig.time_signed, tsig.fudge, now)?;
+
+        self.prior_mac = tsig.mac.to_vec();
+        self.unsigned_count = 0;
+        self.first = false;
+
+        Ok(true)
+    }
+
+    /// Checks that the stream did not end with unsigned messages.
+    pub fn finish(&self) -> Result<(), TsigError> {
+        match self.first || self.unsigned_count > 0 {
+            true => Err(TsigError::Unsigned),
+            false => Ok(()),
+        }
+    }
+}
+
+/// The TSIG record of a received message and where it starts.
+struct Tsig<'a> {
+    start: usize,
+    name: DomainName<'a>,
+    algorithm: DomainName<'a>,
+    time_signed: u64,
+    fudge: u16,
+    mac: Cow<'a, [u8]>,
+    original_id: u16,
+    error: RCode,
+    other: Cow<'a, [u8]>,
+}
+
+impl<'a> Tsig<'a> {
+    /// Finds the TSIG record, which has to be the last record of the additional section.
+    fn extract(message: &'a [u8]) -> Result<Option<Self>, TsigError> {
+        let mut parser = Parser::new(message);
+        let header = Header::parse(&mut parser)?;
+
+        if header.arcount == 0 {
+            return Ok(None);
+        }
+
+        for _ in 0..header.qdcount {
+            Question::parse(&mut parser)?;
+        }
+
+        let records = header.ancount as usize + header.nscount as usize + header.arcount as usize;
+        for _ in 0..records - 1 {
+            ResourceRecord::parse(&mut parser)?;
+        }
+
+        let start = parser.position();
+        match ResourceRecord::parse(&mut parser)? {
+            // RFC 8945 Section 4.2, the record is meta data of class ANY and TTL 0
+            ResourceRecord::Record {
+                class,
+                ttl,
+                data: Record::TSIG { .. },
+                ..
+            } if class != Class::ANY || ttl != 0 => Err(TsigError::FormErr),
+            ResourceRecord::Record {
+                name,
+                data:
+                    Record::TSIG {
+                        algorithm,
+                        time_signed,
+                        fudge,
+                        mac,
+                        original_id,
+                        error,
+                        other,
+                    },
+                ..
+            } => Ok(Some(Self {
+                start,
+                name,
+                algorithm,
+                time_signed,
+                fudge,
+                mac,
+                original_id,
+                error,
+                other,
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    /// The message as it was before it was signed.
+    fn unsigned(&self, message: &[u8]) -> Vec<u8> {
+        let mut unsigned = message[..self.start].to_vec();
+        unsigned[..2].copy_from_slice(&self.original_id.to_be_bytes());
+
+        let arcount = u16::from_be_bytes([unsigned[10], unsigned[11]]) - 1;
+        unsigned[10..12].copy_from_slice(&arcount.to_be_bytes());
+
+        unsigned
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn append(
+    message: &mut Vec<u8>,
+    name: &str,
+    algorithm: &str,
+    time_signed: u64,
+    fudge: u16,
+    mac: &[u8],
+    error: RCode,
+    other: &[u8],
+) -> Result<(), TsigError> {
+    if message.len() < 12 {
+        return Err(TsigError::FormErr);
+    }
+
+    let original_id = [message[0], message[1]];
+    let arcount = u16::from_be_bytes([message[10], message[11]])
+        .checked_add(1)
+        .ok_or(TsigError::FormErr)?;
+    message[10..12].copy_from_slice(&arcount.to_be_bytes());
+
+    let mut rdata = Vec::new();
+    put_name(&mut rdata, algorithm);
+    put_timers(&mut rdata, time_signed, fudge);
+    rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+    rdata.extend_from_slice(mac);
+    rdata.extend_from_slice(&original_id);
+    rdata.extend_from_slice(&u16::from(error).to_be_bytes());
+    rdata.extend_from_slice(&(other.len() as u16).to_be_bytes());
+    rdata.extend_from_slice(other);
+
+    put_name(message, name);
+    message.extend_from_slice(&250u16.to_be_bytes());
+    message.extend_from_slice(&255u16.to_be_bytes());
+    message.extend_from_slice(&0u32.to_be_bytes());
+    message.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    message.extend_from_slice(&rdata);
+
+    Ok(())
+}
+
+fn check_mac(
+    key: &Key,
+    data: &[u8],
+    mac: &[u8],
+    request_mac: Option<&[u8]>,
+) -> Result<(), TsigError> {
+    let len = key.algorithm.output_len();
+    if mac.len() > len || mac.len() < 10.max(len / 2) {
+        return Err(TsigError::FormErr);
+    }
+
+    let expected = key.algorithm.mac(&key.secret, data);
+    let diff = expected
+        .iter()
+        .zip(mac)
+        .fold(0, |diff, (a, b)| diff | (a ^ b));
+    if diff != 0 {
+        return Err(TsigError::BadSig);
+    }
+
+    match request_mac {
+        Some(request_mac) if mac.len() < request_mac.len() => Err(TsigError::BadTrunc),
+        _ => Ok(()),
+    }
+}
+
+fn check_time(time_signed: u64, fudge: u16, now: u64) -> Result<(), TsigError> {
+    match now.abs_diff(time_signed) > fudge.into() {
+        true => Err(TsigError::BadTime),
+        false => Ok(()),
+    }
+}
+
+fn put_mac(data: &mut Vec<u8>, mac: Option<&[u8]>) {
+    if let Some(mac) = mac {
+        data.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+        data.extend_from_slice(mac);
+    }
+}
+
+/// The TSIG variables covered by the MAC, as per
+/// [RFC 8945 Section 4.3.3](https://www.rfc-editor.org/rfc/rfc8945#section-4.3.3)
+fn put_variables(
+    data: &mut Vec<u8>,
+    name: &str,
+    algorithm: &str,
+    time_signed: u64,
+    fudge: u16,
+    error: RCode,
+    other: &[u8],
+) {
+    put_name(data, name);
+    data.extend_from_slice(&255u16.to_be_bytes());
+    data.extend_from_slice(&0u32.to_be_bytes());
+    put_name(data, algorithm);
+    put_timers(data, time_signed, fudge);
+    data.extend_from_slice(&u16::from(error).to_be_bytes());
+    data.extend_from_slice(&(other.len() as u16).to_be_bytes());
+    data.extend_from_slice(other);
+}
+
+fn put_timers(data: &mut Vec<u8>, time_signed: u64, fudge: u16) {
+    data.extend_from_slice(&time_signed.to_be_bytes()[2..]);
+    data.extend_from_slice(&fudge.to_be_bytes());
+}
+
+/// Writes a name given in presentation format in canonical (lowercase) wire format.
+fn put_name(data: &mut Vec<u8>, name: &str) {
+    for label in labels(name) {
+        data.push(label.len() as u8);
+        data.extend(label.bytes().map(|b| b.to_ascii_lowercase()));
+    }
+    data.push(0);
+}
+
+fn labels(name: &str) -> impl Iterator<Item = &str> {
+    name.split('.').filter(|label| !label.is_empty())
+}
+
+fn name_matches(expected: &str, name: &DomainName) -> bool {
+    let expected: Vec<&str> = labels(expected).collect();
+
+    expected.len() == name.labels.len()
+        && expected
+            .iter()
+            .zip(&name.labels)
+            .all(|(a, b)| a.eq_ignore_ascii_case(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    const TIME: u64 = 1_700_000_000;
+
+    /// A query for the SOA of `example.com.` with id 0x1234 and RD set.
+    const QUERY: &str = "123401000001000000000000076578616d706c6503636f6d0000060001";
+
+    /// The MACs were computed independently over the query and the TSIG variables of
+    /// RFC 8945 Section 4.3.3.
+    const QUERY_MAC_SHA256: &str =
+        "0b14a36b1c9305d1a70b8e025cde30800f3d64ccb2848b1ee1e026fce7278e60";
+    const QUERY_MAC_SHA512: &str = "eb5f5f1e94221c8bb2c9a07120654fdb715438f16c17c536658efb52\
+        9780ec6801350547b7c6a57083819e4fc667afd7ccc8e783961072726b2afc7b5fa7cd35";
+
+    /// The response to the query signed a second later, covering the query MAC.
+    const RESPONSE_MAC_SHA256: &str =
+        "4ccac5df0000807596d9baf845faaac751ab84bbd8471dcd63bd8ff3905a9266";
+
+    fn hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn key(algorithm: Algorithm) -> Key {
+        Key {
+            name: "test-key.".to_string(),
+            algorithm,
+            secret: b"0123456789abcdef0123456789abcdef".to_vec(),
+        }
+    }
+
+    #[test]
+    fn sign_known_answer() {
+        for (algorithm, expected) in [
+            (Algorithm::HmacSha256, QUERY_MAC_SHA256),
+            (Algorithm::HmacSha512, QUERY_MAC_SHA512),
+        ] {
+            let key = key(algorithm);
+            let mut message = hex(QUERY);
+            let mac = sign(&mut message, &key, None, TIME, DEFAULT_FUDGE).unwrap();
+
+            assert_eq!(mac, hex(expected));
+            assert_eq!(&message[10..12], &[0, 1]);
+
+            let keys = [key.clone()];
+            let verified = verify(&message, &keys[..], None, TIME).unwrap();
+            assert_eq!(verified.mac, mac);
+            assert_eq!(verified.time_signed, TIME);
+        }
+    }
+
+    #[test]
+    fn sign_response_known_answer() {
+        let keys = [key(Algorithm::HmacSha256)];
+        let request_mac = hex(QUERY_MAC_SHA256);
+
+        let mut response = hex(QUERY);
+        response[2] = 0x81;
+        response[3] = 0x80;
+        let mac = sign(&mut response, &keys[0], Some(&request_mac), TIME + 1, 300).unwrap();
+        assert_eq!(mac, hex(RESPONSE_MAC_SHA256));
+
+        verify(&response, &keys[..], Some(&request_mac), TIME + 1).unwrap();
+        assert!(matches!(
+            verify(&response, &keys[..], None, TIME + 1),
+            Err(TsigError::BadSig)
+        ));
+    }
+
+    #[test]
+    fn verify_rejects_tampering() {
+        let keys = [key(Algorithm::HmacSha256)];
+        let mut message = hex(QUERY);
+        sign(&mut message, &keys[0], None, TIME, DEFAULT_FUDGE).unwrap();
+
+        let mut modified = message.clone();
+        modified[2] = 0;
+        assert!(matches!(
+            verify(&modified, &keys[..], None, TIME),
+            Err(TsigError::BadSig)
+        ));
+
+        assert!(matches!(
+            verify(&message, &keys[..], None, TIME + 301),
+            Err(TsigError::BadTime)
+        ));
+
+        let other = [Key {
+            name: "other-key.".to_string(),
+            ..keys[0].clone()
+        }];
+        assert!(matches!(
+            verify(&message, &other[..], None, TIME),
+            Err(TsigError::BadKey)
+        ));
+
+        assert!(matches!(
+            verify(&hex(QUERY), &keys[..], None, TIME),
+            Err(TsigError::Unsigned)
+        ));
+    }
+
+    #[test]
+    fn verify_requires_class_any_and_ttl_zero() {
+        let keys = [key(Algorithm::HmacSha256)];
+        let mut message = hex(QUERY);
+        sign(&mut message, &keys[0], None, TIME, DEFAULT_FUDGE).unwrap();
+
+        // the owner name `test-key.` takes 10 bytes, followed by the type
+        let class = hex(QUERY).len() + 10 + 2;
+
+        let mut wrong_class = message.clone();
+        wrong_class[class..class + 2].copy_from_slice(&1u16.to_be_bytes());
+        assert!(matches!(
+            verify(&wrong_class, &keys[..], None, TIME),
+            Err(TsigError::FormErr)
+        ));
+
+        let mut wrong_ttl = message.clone();
+        wrong_ttl[class + 5] = 1;
+        assert!(matches!(
+            verify(&wrong_ttl, &keys[..], None, TIME),
+            Err(TsigError::FormErr)
+        ));
+    }
+
+    #[test]
+    fn bad_time_error_is_signed() {
+        let keys = [key(Algorithm::HmacSha256)];
+        let mut request = hex(QUERY);
+        let request_mac = sign(&mut request, &keys[0], None, TIME, DEFAULT_FUDGE).unwrap();
+
+        let now = TIME + 1000;
+        let error = verify(&request, &keys[..], None, now).unwrap_err();
+        assert!(matches!(error, TsigError::BadTime));
+
+        let mut response = hex(QUERY);
+        response[2] = 0x81;
+        sign_error(&mut response, &request, &keys[..], &error, now).unwrap();
+
+        // the client verifies the error with the MAC of its request
+        assert!(matches!(
+            verify(&response, &keys[..], Some(&request_mac), TIME),
+            Err(TsigError::BadTime)
+        ));
+    }
+
+    #[test]
+    fn stream_with_unsigned_messages() {
+        let key = key(Algorithm::HmacSha384);
+        let request_mac = vec![0xab; 48];
+
+        let mut signer = StreamSigner::new(&key, &request_mac, DEFAULT_FUDGE);
+        let mut first = hex(QUERY);
+        signer.sign(&mut first, TIME).unwrap();
+        let middle = hex(QUERY);
+        let mut last = hex(QUERY);
+
+        // the last message covers the unsigned one before it, so sign over both
+        let mut covered = middle.clone();
+        covered.extend_from_slice(&last);
+        let mut data = Vec::new();
+        put_mac(&mut data, Some(&signer.prior_mac));
+        data.extend_from_slice(&covered);
+        put_timers(&mut data, TIME, DEFAULT_FUDGE);
+        let mac = key.algorithm.mac(&key.secret, &data);
+        append(
+            &mut last,
+            &key.name,
+            key.algorithm.name(),
+            TIME,
+            DEFAULT_FUDGE,
+            &mac,
+            RCode::NoError,
+            &[],
+        )
+        .unwrap();
+
+        let mut verifier = StreamVerifier::new(&key, &request_mac);
+        assert!(verifier.verify(&first, TIME).unwrap());
+        assert!(!verifier.verify(&middle, TIME).unwrap());
+        assert!(verifier.finish().is_err());
+        assert!(verifier.verify(&last, TIME).unwrap());
+        verifier.finish().unwrap();
+    }
+
+    #[test]
+    fn stream_must_start_signed() {
+        let key = key(Algorithm::HmacSha256);
+        let mut verifier = StreamVerifier::new(&key, &[0; 32]);
+
+        assert!(matches!(
+            verifier.verify(&hex(QUERY), TIME),
+            Err(TsigError::Unsigned)
+        ));
+    }
+}
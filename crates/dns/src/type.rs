@@ -1,8 +1,14 @@
+use core::fmt::Display;
+use core::str::FromStr;
+
 use log::warn;
 
-use crate::{ResourceRecord, rr::Record};
+use crate::{
+    ResourceRecord,
+    proto::{ParseError, ParseErrorKind},
+};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[repr(u16)]
 pub enum Type {
     /// [RFC 1035](https://www.rfc-editor.org/rfc/rfc1035#section-3.2.2)
@@ -26,12 +32,22 @@ pub enum Type {
     /// [RFC 1035](https://www.rfc-editor.org/rfc/rfc1035#section-3.2.2)
     TXT,
 
+    /// [RFC 2931](https://www.rfc-editor.org/rfc/rfc2931)
+    SIG,
+
+    /// [RFC 2535](https://www.rfc-editor.org/rfc/rfc2535#section-3)
+    /// [RFC 3445](https://www.rfc-editor.org/rfc/rfc3445)
+    KEY,
+
     /// [RFC 3596](https://www.rfc-editor.org/rfc/rfc3596#section-2.1)
     AAAA,
 
     /// [RFC 2782](https://www.rfc-editor.org/rfc/rfc2782)
     SRV,
 
+    /// [RFC 6672](https://www.rfc-editor.org/rfc/rfc6672)
+    DNAME,
+
     /// [RFC 6891](https://www.rfc-editor.org/rfc/rfc6891#section-6.1.1)
     OPT,
 
@@ -61,6 +77,15 @@ pub enum Type {
     /// [RFC 9460](https://www.rfc-editor.org/rfc/rfc9460)
     HTTPS,
 
+    /// [RFC 8945](https://www.rfc-editor.org/rfc/rfc8945)
+    TSIG,
+
+    /// [RFC 5936](https://www.rfc-editor.org/rfc/rfc5936)
+    AXFR,
+
+    /// [RFC 1035](https://www.rfc-editor.org/rfc/rfc1035#section-3.2.3)
+    ANY,
+
     /// [RFC 8659](https://www.rfc-editor.org/rfc/rfc8659)
     CAA,
 
@@ -77,8 +102,11 @@ impl From<u16> for Type {
             12 => Self::PTR,
             15 => Self::MX,
             16 => Self::TXT,
+            24 => Self::SIG,
+            25 => Self::KEY,
             28 => Self::AAAA,
             33 => Self::SRV,
+            39 => Self::DNAME,
             41 => Self::OPT,
             43 => Self::DS,
             46 => Self::RRSIG,
@@ -88,6 +116,9 @@ impl From<u16> for Type {
             51 => Self::NSEC3PARAM,
             64 => Self::SVCB,
             65 => Self::HTTPS,
+            250 => Self::TSIG,
+            252 => Self::AXFR,
+            255 => Self::ANY,
             257 => Self::CAA,
             _ => {
                 warn!("unknown value for record type {}", value);
@@ -107,8 +138,11 @@ impl From<Type> for u16 {
             Type::PTR => 12,
             Type::MX => 15,
             Type::TXT => 16,
+            Type::SIG => 24,
+            Type::KEY => 25,
             Type::AAAA => 28,
             Type::SRV => 33,
+            Type::DNAME => 39,
             Type::OPT => 41,
             Type::DS => 43,
             Type::RRSIG => 46,
@@ -118,6 +152,9 @@ impl From<Type> for u16 {
             Type::NSEC3PARAM => 51,
             Type::SVCB => 64,
             Type::HTTPS => 65,
+            Type::TSIG => 250,
+            Type::AXFR => 252,
+            Type::ANY => 255,
             Type::CAA => 257,
             Type::Unknown(code) => code,
         }
@@ -134,17 +171,77 @@ impl From<&ResourceRecord<'_>> for Type {
     }
 }
 
-impl From<&Record<'_>> for Type {
-    fn from(value: &Record) -> Self {
-        match value {
-            Record::A { .. } => Self::A,
-            Record::NS { .. } => Self::NS,
-            Record::CNAME { .. } => Self::CNAME,
-            Record::SOA { .. } => Self::SOA,
-            Record::PTR { .. } => Self::PTR,
-            Record::MX { .. } => Self::MX,
-            Record::TXT { .. } => Self::TXT,
-            Record::AAAA { .. } => Self::AAAA,
+/// Mnemonic of the type as per [RFC 3597 Section 5](https://www.rfc-editor.org/rfc/rfc3597#section-5),
+/// types without one are written as `TYPE` followed by the code.
+impl Display for Type {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::A => f.write_str("A"),
+            Self::NS => f.write_str("NS"),
+            Self::CNAME => f.write_str("CNAME"),
+            Self::SOA => f.write_str("SOA"),
+            Self::PTR => f.write_str("PTR"),
+            Self::MX => f.write_str("MX"),
+            Self::TXT => f.write_str("TXT"),
+            Self::SIG => f.write_str("SIG"),
+            Self::KEY => f.write_str("KEY"),
+            Self::AAAA => f.write_str("AAAA"),
+            Self::SRV => f.write_str("SRV"),
+            Self::DNAME => f.write_str("DNAME"),
+            Self::OPT => f.write_str("OPT"),
+            Self::DS => f.write_str("DS"),
+            Self::RRSIG => f.write_str("RRSIG"),
+            Self::NSEC => f.write_str("NSEC"),
+            Self::DNSKEY => f.write_str("DNSKEY"),
+            Self::NSEC3 => f.write_str("NSEC3"),
+            Self::NSEC3PARAM => f.write_str("NSEC3PARAM"),
+            Self::SVCB => f.write_str("SVCB"),
+            Self::HTTPS => f.write_str("HTTPS"),
+            Self::TSIG => f.write_str("TSIG"),
+            Self::AXFR => f.write_str("AXFR"),
+            Self::ANY => f.write_str("ANY"),
+            Self::CAA => f.write_str("CAA"),
+            Self::Unknown(code) => write!(f, "TYPE{}", code),
+        }
+    }
+}
+
+/// Reads a mnemonic or the generic `TYPE` form, ignoring case.
+impl FromStr for Type {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "A" => Ok(Self::A),
+            "NS" => Ok(Self::NS),
+            "CNAME" => Ok(Self::CNAME),
+            "SOA" => Ok(Self::SOA),
+            "PTR" => Ok(Self::PTR),
+            "MX" => Ok(Self::MX),
+            "TXT" => Ok(Self::TXT),
+            "SIG" => Ok(Self::SIG),
+            "KEY" => Ok(Self::KEY),
+            "AAAA" => Ok(Self::AAAA),
+            "SRV" => Ok(Self::SRV),
+            "DNAME" => Ok(Self::DNAME),
+            "OPT" => Ok(Self::OPT),
+            "DS" => Ok(Self::DS),
+            "RRSIG" => Ok(Self::RRSIG),
+            "NSEC" => Ok(Self::NSEC),
+            "DNSKEY" => Ok(Self::DNSKEY),
+            "NSEC3" => Ok(Self::NSEC3),
+            "NSEC3PARAM" => Ok(Self::NSEC3PARAM),
+            "SVCB" => Ok(Self::SVCB),
+            "HTTPS" => Ok(Self::HTTPS),
+            "TSIG" => Ok(Self::TSIG),
+            "AXFR" => Ok(Self::AXFR),
+            "ANY" => Ok(Self::ANY),
+            "CAA" => Ok(Self::CAA),
+            other => other
+                .strip_prefix("TYPE")
+                .and_then(|code| code.parse::<u16>().ok())
+                .map(Self::from)
+                .ok_or(ParseError::new(ParseErrorKind::UnknownMnemonic, 0)),
         }
     }
 }
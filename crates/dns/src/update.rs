@@ -0,0 +1,627 @@
+use alloc::borrow::Cow;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{
+    DomainName,
+    class::Class,
+    header::{Header, OpCode, RCode},
+    packet::Packet,
+    question::Question,
+    rr::ResourceRecord,
+    r#type::Type,
+};
+
+/// DNS UPDATE message layout as per [RFC 2136 Section 2](https://www.rfc-editor.org/rfc/rfc2136#section-2)
+///
+/// ```text
+/// +---------------------+
+/// |        Header       |
+/// +---------------------+
+/// |         Zone        | specifies the zone to be updated
+/// +---------------------+
+/// |     Prerequisite    | RRs or RRsets which must (not) preexist
+/// +---------------------+
+/// |        Update       | RRs or RRsets to be added or deleted
+/// +---------------------+
+/// |   Additional Data   | additional data
+/// +---------------------+
+/// ```
+///
+/// The sections share their wire layout with [`Packet`], so an update is sent by
+/// converting it into a packet and interpreted by converting a received packet back.
+#[derive(Debug)]
+pub struct Update<'a> {
+    pub header: Header,
+    pub zone: Question<'a>,
+    pub prerequisites: Vec<ResourceRecord<'a>>,
+    pub updates: Vec<ResourceRecord<'a>>,
+    pub additionals: Vec<ResourceRecord<'a>>,
+}
+
+impl<'a> Update<'a> {
+    pub fn new(id: u16, zone: DomainName<'a>, class: Class) -> Self {
+        let mut header = Header {
+            id,
+            ..Default::default()
+        };
+        header.set_opcode(OpCode::Update);
+
+        Self {
+            header,
+            zone: Question {
+                name: zone,
+                r#type: Type::SOA,
+                class,
+            },
+            prerequisites: Vec::new(),
+            updates: Vec::new(),
+            additionals: Vec::new(),
+        }
+    }
+
+    pub fn add_prerequisite(&mut self, prerequisite: Prerequisite<'a>) {
+        let record = prerequisite.into_record(self.zone.class.clone());
+        self.prerequisites.push(record);
+    }
+
+    pub fn add_update(&mut self, operation: Operation<'a>) {
+        let record = operation.into_record(self.zone.class.clone());
+        self.updates.push(record);
+    }
+}
+
+impl<'a> From<Update<'a>> for Packet<'a> {
+    fn from(update: Update<'a>) -> Self {
+        let mut header = update.header;
+        header.qdcount = 1;
+        header.ancount = update.prerequisites.len() as u16;
+        header.nscount = update.updates.len() as u16;
+        header.arcount = update.additionals.len() as u16;
+
+        Packet {
+            header,
+            questions: vec![update.zone],
+            answers: update.prerequisites,
+            authorities: update.updates,
+            additionals: update.additionals,
+        }
+    }
+}
+
+impl<'a> TryFrom<Packet<'a>> for Update<'a> {
+    type Error = RCode;
+
+    /// Fails with [`RCode::FormatErr`] unless the packet is an update with exactly one
+    /// SOA zone entry, as per [RFC 2136 Section 3.1.1](https://www.rfc-editor.org/rfc/rfc2136#section-3.1.1).
+    fn try_from(packet: Packet<'a>) -> Result<Self, Self::Error> {
+        if packet.header.opcode() != OpCode::Update || packet.questions.len() != 1 {
+            return Err(RCode::FormatErr);
+        }
+
+        let zone = packet
+            .questions
+            .into_iter()
+            .next()
+            .ok_or(RCode::FormatErr)?;
+        if zone.r#type != Type::SOA {
+            return Err(RCode::FormatErr);
+        }
+
+        Ok(Self {
+            header: packet.header,
+            zone,
+            prerequisites: packet.answers,
+            updates: packet.authorities,
+            additionals: packet.additionals,
+        })
+    }
+}
+
+/// Prerequisite checks as per [RFC 2136 Section 2.4](https://www.rfc-editor.org/rfc/rfc2136#section-2.4)
+#[derive(Debug)]
+pub enum Prerequisite<'a> {
+    /// At least one RR of the given type exists at the name.
+    RRsetExists { name: DomainName<'a>, r#type: Type },
+
+    /// The RRset this record belongs to exists and contains exactly the records
+    /// given by all such prerequisites for the same name and type.
+    RRsetExistsValue(ResourceRecord<'a>),
+
+    /// No RR of the given type exists at the name.
+    RRsetDoesNotExist { name: DomainName<'a>, r#type: Type },
+
+    /// At least one RR of any type exists at the name.
+    NameInUse { name: DomainName<'a> },
+
+    /// No RR of any type exists at the name.
+    NameNotInUse { name: DomainName<'a> },
+}
+
+impl<'a> Prerequisite<'a> {
+    /// Interprets a record of the prerequisite section of an update for `zone`, as per
+    /// [RFC 2136 Section 3.2](https://www.rfc-editor.org/rfc/rfc2136#section-3.2).
+    pub fn from_record(record: ResourceRecord<'a>, zone: &Question) -> Result<Self, RCode> {
+        let fields = Fields::of(&record, zone)?;
+        if fields.ttl != 0 {
+            return Err(RCode::FormatErr);
+        }
+
+        match (fields.class, fields.empty, record) {
+            (Class::ANY, true, ResourceRecord::Unknown { name, r#type, .. }) => Ok(match r#type {
+                Type::ANY => Self::NameInUse { name },
+                r#type => Self::RRsetExists { name, r#type },
+            }),
+            (Class::NONE, true, ResourceRecord::Unknown { name, r#type, .. }) => Ok(match r#type {
+                Type::ANY => Self::NameNotInUse { name },
+                r#type => Self::RRsetDoesNotExist { name, r#type },
+            }),
+            (class, _, record) if class == zone.class => Ok(Self::RRsetExistsValue(record)),
+            _ => Err(RCode::FormatErr),
+        }
+    }
+
+    pub fn into_record(self, zone_class: Class) -> ResourceRecord<'a> {
+        match self {
+            Self::RRsetExists { name, r#type } => empty(name, r#type, Class::ANY),
+            Self::RRsetExistsValue(record) => with_class(record, zone_class, Some(0)),
+            Self::RRsetDoesNotExist { name, r#type } => empty(name, r#type, Class::NONE),
+            Self::NameInUse { name } => empty(name, Type::ANY, Class::ANY),
+            Self::NameNotInUse { name } => empty(name, Type::ANY, Class::NONE),
+        }
+    }
+}
+
+/// Update operations as per [RFC 2136 Section 2.5](https://www.rfc-editor.org/rfc/rfc2136#section-2.5)
+#[derive(Debug)]
+pub enum Operation<'a> {
+    /// Add the record to its RRset.
+    Add(ResourceRecord<'a>),
+
+    /// Delete the RRset of the given type at the name.
+    DeleteRRset { name: DomainName<'a>, r#type: Type },
+
+    /// Delete all RRsets at the name.
+    DeleteAll { name: DomainName<'a> },
+
+    /// Delete the record from its RRset.
+    DeleteRR(ResourceRecord<'a>),
+}
+
+impl<'a> Operation<'a> {
+    /// Interprets a record of the update section of an update for `zone`, as per
+    /// [RFC 2136 Section 3.4.1.3](https://www.rfc-editor.org/rfc/rfc2136#section-3.4.1.3).
+    pub fn from_record(record: ResourceRecord<'a>, zone: &Question) -> Result<Self, RCode> {
+        let fields = Fields::of(&record, zone)?;
+
+        match (fields.class, record) {
+            (class, record) if class == zone.class => {
+                if fields.r#type == Type::ANY {
+                    return Err(RCode::FormatErr);
+                }
+
+                Ok(Self::Add(record))
+            }
+            (Class::ANY, ResourceRecord::Unknown { name, r#type, .. }) => {
+                if fields.ttl != 0 || !fields.empty {
+                    return Err(RCode::FormatErr);
+                }
+
+                Ok(match r#type {
+                    Type::ANY => Self::DeleteAll { name },
+                    r#type => Self::DeleteRRset { name, r#type },
+                })
+            }
+            (Class::NONE, record) => {
+                if fields.ttl != 0 || fields.r#type == Type::ANY {
+                    return Err(RCode::FormatErr);
+                }
+
+                Ok(Self::DeleteRR(record))
+            }
+            _ => Err(RCode::FormatErr),
+        }
+    }
+
+    pub fn into_record(self, zone_class: Class) -> ResourceRecord<'a> {
+        match self {
+            Self::Add(record) => with_class(record, zone_class, None),
+            Self::DeleteRRset { name, r#type } => empty(name, r#type, Class::ANY),
+            Self::DeleteAll { name } => empty(name, Type::ANY, Class::ANY),
+            Self::DeleteRR(record) => with_class(record, Class::NONE, Some(0)),
+        }
+    }
+}
+
+/// The fields of a record that decide how it is interpreted within an update.
+struct Fields {
+    r#type: Type,
+    class: Class,
+    ttl: u32,
+    empty: bool,
+}
+
+impl Fields {
+    fn of(record: &ResourceRecord, zone: &Question) -> Result<Self, RCode> {
+        let (name, fields) = match record {
+            ResourceRecord::Record {
+                name,
+                class,
+                ttl,
+                data,
+            } => (
+                name,
+                Self {
+                    r#type: data.into(),
+                    class: class.clone(),
+                    ttl: *ttl,
+                    empty: false,
+                },
+            ),
+            ResourceRecord::Unknown {
+                name,
+                r#type,
+                class,
+                ttl,
+                data,
+            } => (
+                name,
+                Self {
+                    r#type: r#type.clone(),
+                    class: class.clone(),
+                    ttl: *ttl,
+                    empty: data.is_empty(),
+                },
+            ),
+            ResourceRecord::OPTRecord { .. } => return Err(RCode::FormatErr),
+        };
+
+        if !name.ends_with(&zone.name) {
+            return Err(RCode::NotZone);
+        }
+
+        Ok(fields)
+    }
+}
+
+fn empty<'a>(name: DomainName<'a>, r#type: Type, class: Class) -> ResourceRecord<'a> {
+    ResourceRecord::Unknown {
+        name,
+        r#type,
+        class,
+        ttl: 0,
+        data: Cow::Borrowed(&[]),
+    }
+}
+
+fn with_class<'a>(
+    mut record: ResourceRecord<'a>,
+    class: Class,
+    ttl: Option<u32>,
+) -> ResourceRecord<'a> {
+    if let ResourceRecord::Record {
+        class: c, ttl: t, ..
+    }
+    | ResourceRecord::Unknown {
+        class: c, ttl: t, ..
+    } = &mut record
+    {
+        *c = class;
+        if let Some(ttl) = ttl {
+            *t = ttl;
+        }
+    }
+
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::{Parse, Parser, Serialize, Serializer};
+
+    use super::*;
+
+    const IN: u16 = 1;
+    const CH: u16 = 3;
+    const NONE: u16 = 254;
+    const ANY: u16 = 255;
+
+    const A: u16 = 1;
+    const TYPE_ANY: u16 = 255;
+
+    fn zone() -> Question<'static> {
+        Question {
+            name: "example.com".parse().unwrap(),
+            r#type: Type::SOA,
+            class: Class::IN,
+        }
+    }
+
+    /// Parses a record of `name` below example.com, with an address as rdata unless it
+    /// is `empty`.
+    fn record(
+        name: &str,
+        r#type: u16,
+        class: u16,
+        ttl: u32,
+        empty: bool,
+    ) -> ResourceRecord<'static> {
+        let mut buf = Vec::new();
+        for label in name.split('.') {
+            buf.push(label.len() as u8);
+            buf.extend_from_slice(label.as_bytes());
+        }
+        buf.push(0);
+        buf.extend_from_slice(&r#type.to_be_bytes());
+        buf.extend_from_slice(&class.to_be_bytes());
+        buf.extend_from_slice(&ttl.to_be_bytes());
+        match empty {
+            true => buf.extend_from_slice(&[0, 0]),
+            false => buf.extend_from_slice(&[0, 4, 192, 0, 2, 1]),
+        }
+
+        ResourceRecord::parse(&mut Parser::new(&buf))
+            .unwrap()
+            .into_owned()
+    }
+
+    fn prerequisite(
+        r#type: u16,
+        class: u16,
+        ttl: u32,
+        empty: bool,
+    ) -> Result<Prerequisite<'static>, RCode> {
+        Prerequisite::from_record(
+            record("www.example.com", r#type, class, ttl, empty),
+            &zone(),
+        )
+    }
+
+    fn operation(
+        r#type: u16,
+        class: u16,
+        ttl: u32,
+        empty: bool,
+    ) -> Result<Operation<'static>, RCode> {
+        Operation::from_record(
+            record("www.example.com", r#type, class, ttl, empty),
+            &zone(),
+        )
+    }
+
+    #[test]
+    fn empty_rdata_with_any_or_none_parses_as_unknown() {
+        for class in [ANY, NONE] {
+            assert!(matches!(
+                record("www.example.com", A, class, 0, true),
+                ResourceRecord::Unknown { ref data, .. } if data.is_empty()
+            ));
+        }
+        assert!(matches!(
+            record("www.example.com", A, NONE, 0, false),
+            ResourceRecord::Record { .. }
+        ));
+    }
+
+    #[test]
+    fn prerequisites() {
+        assert!(matches!(
+            prerequisite(TYPE_ANY, ANY, 0, true),
+            Ok(Prerequisite::NameInUse { .. })
+        ));
+        assert!(matches!(
+            prerequisite(A, ANY, 0, true),
+            Ok(Prerequisite::RRsetExists {
+                r#type: Type::A,
+                ..
+            })
+        ));
+        assert!(matches!(
+            prerequisite(TYPE_ANY, NONE, 0, true),
+            Ok(Prerequisite::NameNotInUse { .. })
+        ));
+        assert!(matches!(
+            prerequisite(A, NONE, 0, true),
+            Ok(Prerequisite::RRsetDoesNotExist {
+                r#type: Type::A,
+                ..
+            })
+        ));
+        assert!(matches!(
+            prerequisite(A, IN, 0, false),
+            Ok(Prerequisite::RRsetExistsValue(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_prerequisites() {
+        // a TTL other than zero
+        assert_eq!(
+            prerequisite(A, ANY, 300, true).unwrap_err(),
+            RCode::FormatErr
+        );
+        assert_eq!(
+            prerequisite(A, IN, 300, false).unwrap_err(),
+            RCode::FormatErr
+        );
+
+        // rdata with ANY or NONE
+        assert_eq!(
+            prerequisite(A, ANY, 0, false).unwrap_err(),
+            RCode::FormatErr
+        );
+        assert_eq!(
+            prerequisite(A, NONE, 0, false).unwrap_err(),
+            RCode::FormatErr
+        );
+
+        // another class than the zone's
+        assert_eq!(prerequisite(A, CH, 0, false).unwrap_err(), RCode::FormatErr);
+
+        let outside = record("www.example.org", A, ANY, 0, true);
+        assert_eq!(
+            Prerequisite::from_record(outside, &zone()).unwrap_err(),
+            RCode::NotZone
+        );
+    }
+
+    #[test]
+    fn operations() {
+        assert!(matches!(
+            operation(A, IN, 300, false),
+            Ok(Operation::Add(_))
+        ));
+        assert!(matches!(
+            operation(A, ANY, 0, true),
+            Ok(Operation::DeleteRRset {
+                r#type: Type::A,
+                ..
+            })
+        ));
+        assert!(matches!(
+            operation(TYPE_ANY, ANY, 0, true),
+            Ok(Operation::DeleteAll { .. })
+        ));
+        assert!(matches!(
+            operation(A, NONE, 0, false),
+            Ok(Operation::DeleteRR(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_operations() {
+        // adding to every type
+        assert_eq!(
+            operation(TYPE_ANY, IN, 300, false).unwrap_err(),
+            RCode::FormatErr
+        );
+
+        // deleting rrsets with rdata or a TTL
+        assert_eq!(operation(A, ANY, 0, false).unwrap_err(), RCode::FormatErr);
+        assert_eq!(operation(A, ANY, 300, true).unwrap_err(), RCode::FormatErr);
+
+        // deleting a record with a TTL or of every type
+        assert_eq!(
+            operation(A, NONE, 300, false).unwrap_err(),
+            RCode::FormatErr
+        );
+        assert_eq!(
+            operation(TYPE_ANY, NONE, 0, true).unwrap_err(),
+            RCode::FormatErr
+        );
+
+        assert_eq!(operation(A, CH, 300, false).unwrap_err(), RCode::FormatErr);
+
+        let outside = record("www.example.org", A, IN, 300, false);
+        assert_eq!(
+            Operation::from_record(outside, &zone()).unwrap_err(),
+            RCode::NotZone
+        );
+    }
+
+    #[test]
+    fn update_round_trips() {
+        let zone = zone();
+        let name = || DomainName::from(vec!["www", "example", "com"]);
+
+        let mut update = Update::new(0x1234, zone.name.clone(), Class::IN);
+        update.add_prerequisite(Prerequisite::NameInUse { name: name() });
+        update.add_prerequisite(Prerequisite::RRsetDoesNotExist {
+            name: name(),
+            r#type: Type::AAAA,
+        });
+        update.add_prerequisite(Prerequisite::RRsetExistsValue(record(
+            "www.example.com",
+            A,
+            CH,
+            300,
+            false,
+        )));
+        update.add_update(Operation::DeleteRRset {
+            name: name(),
+            r#type: Type::A,
+        });
+        update.add_update(Operation::DeleteAll { name: name() });
+        update.add_update(Operation::Add(record("www.example.com", A, CH, 300, false)));
+        update.add_update(Operation::DeleteRR(record(
+            "www.example.com",
+            A,
+            IN,
+            300,
+            false,
+        )));
+
+        let mut buf = vec![0; 512];
+        let len = Packet::from(update)
+            .serialize(&mut Serializer::new(&mut buf))
+            .unwrap();
+        let packet = Packet::parse(&mut Parser::new(&buf[..len])).unwrap();
+        let update = Update::try_from(packet).unwrap();
+        assert_eq!(update.header.id, 0x1234);
+
+        let prerequisites: Vec<_> = update
+            .prerequisites
+            .into_iter()
+            .map(|record| Prerequisite::from_record(record, &zone).unwrap())
+            .collect();
+        assert!(matches!(
+            prerequisites.as_slice(),
+            [
+                Prerequisite::NameInUse { .. },
+                Prerequisite::RRsetDoesNotExist {
+                    r#type: Type::AAAA,
+                    ..
+                },
+                Prerequisite::RRsetExistsValue(ResourceRecord::Record {
+                    class: Class::IN,
+                    ttl: 0,
+                    ..
+                }),
+            ]
+        ));
+
+        let operations: Vec<_> = update
+            .updates
+            .into_iter()
+            .map(|record| Operation::from_record(record, &zone).unwrap())
+            .collect();
+        assert!(matches!(
+            operations.as_slice(),
+            [
+                Operation::DeleteRRset {
+                    r#type: Type::A,
+                    ..
+                },
+                Operation::DeleteAll { .. },
+                Operation::Add(ResourceRecord::Record {
+                    class: Class::IN,
+                    ttl: 300,
+                    ..
+                }),
+                Operation::DeleteRR(ResourceRecord::Record {
+                    class: Class::NONE,
+                    ttl: 0,
+                    ..
+                }),
+            ]
+        ));
+    }
+
+    #[test]
+    fn packets_that_arent_updates() {
+        let update = || Packet::from(Update::new(1, zone().name, Class::IN));
+        assert!(Update::try_from(update()).is_ok());
+
+        let mut query = update();
+        query.header.set_opcode(OpCode::Query);
+        assert_eq!(Update::try_from(query).unwrap_err(), RCode::FormatErr);
+
+        let mut not_soa = update();
+        not_soa.questions[0].r#type = Type::A;
+        assert_eq!(Update::try_from(not_soa).unwrap_err(), RCode::FormatErr);
+
+        let mut two_zones = update();
+        two_zones.questions.push(zone());
+        assert_eq!(Update::try_from(two_zones).unwrap_err(), RCode::FormatErr);
+    }
+}
@@ -0,0 +1,348 @@
+use std::cmp::Ordering;
+use std::sync::{Arc, RwLock};
+
+use dns::{DomainName, Packet};
+use log::{error, info, warn};
+
+use crate::config::{PrimaryZone, domain_name};
+use crate::handler;
+use crate::zone::Zone;
+
+/// The zones this server answers authoritatively.
+#[derive(Default)]
+pub struct Authority {
+    zones: RwLock<Vec<Arc<Zone>>>,
+}
+
+impl Authority {
+    /// Loads the configured zones, leaving out the ones that fail to load.
+    pub fn load(zones: &[PrimaryZone]) -> Arc<Self> {
+        let authority = Arc::new(Self::default());
+        for zone in zones {
+            authority.reload(zone);
+        }
+        authority
+    }
+
+    /// Reads the zone from its file again, replacing the previous version. Returns
+    /// whether a version with a higher serial is served now, that secondaries should
+    /// be notified of.
+    ///
+    /// The previous version keeps being served if the file is invalid, or if its serial
+    /// went backwards as per [RFC 1982](https://www.rfc-editor.org/rfc/rfc1982), as
+    /// secondaries would never transfer it. Changes without a new serial get one from
+    /// the serial policy of the zone, without a policy they are served, but not
+    /// announced.
+    pub fn reload(&self, zone: &PrimaryZone) -> bool {
+        let origin = domain_name(&zone.name).into_owned();
+
+        let mut loaded = match Zone::load(&zone.file, origin) {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                error!("failed to load zone {}: {}", zone.name, err);
+                return false;
+            }
+        };
+
+        if let Some(policy) = zone.serial_policy
+            && let Some(current) = self.get(&loaded.origin)
+            && loaded.serial().partial_cmp(&current.serial()) != Some(Ordering::Greater)
+        {
+            // the file keeps its serial, which the served version is already ahead of
+            if loaded.same_records(&current) {
+                return false;
+            }
+
+            let serial = policy.next(current.serial(), handler::unix_time());
+            info!(
+                "zone {} changed without a new serial, advancing it from {} to {}",
+                zone.name,
+                current.serial(),
+                serial
+            );
+            loaded.set_serial(serial);
+        }
+
+        let serial = loaded.serial();
+        let current = self.get(&loaded.origin).map(|current| current.serial());
+        match current {
+            Some(current) if serial == current => {
+                warn!(
+                    "zone {} changed without a new serial {}, secondaries won't pick it up",
+                    zone.name, serial
+                );
+                self.install(loaded);
+                return false;
+            }
+            Some(current) if serial < current || serial.partial_cmp(&current).is_none() => {
+                error!(
+                    "serial of zone {} went from {} to {}, keeping the previous version",
+                    zone.name, current, serial
+                );
+                return false;
+            }
+            _ => {}
+        }
+
+        info!("loaded zone {} at serial {}", zone.name, serial);
+
+        self.install(loaded);
+        true
+    }
+
+    /// Serves `zone`, replacing the previous version of it.
+    pub fn install(&self, zone: Zone) {
+        let mut zones = self.zones.write().unwrap();
+        zones.retain(|existing| existing.origin != zone.origin);
+        zones.push(Arc::new(zone));
+    }
+
+    /// The zone with the apex `origin`.
+    pub fn get(&self, origin: &DomainName) -> Option<Arc<Zone>> {
+        self.zones
+            .read()
+            .unwrap()
+            .iter()
+            .find(|zone| zone.origin == *origin)
+            .cloned()
+    }
+
+    /// The most specific zone `name` is in.
+    pub fn find(&self, name: &DomainName) -> Option<Arc<Zone>> {
+        self.zones
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|zone| name.ends_with(&zone.origin))
+            .max_by_key(|zone| zone.origin.labels.len())
+            .cloned()
+    }
+}
+
+/// Answers a query with a single question from `zone`, which the question is in.
+pub fn answer<'a>(zone: &Zone, request: &Packet<'a>) -> Packet<'a> {
+    let question = &request.questions[0];
+    let lookup = zone.lookup(&question.name, &question.r#type);
+
+    let mut response: Packet<'a> =
+        handler::error(request.header.id, request.header.opcode(), lookup.rcode);
+    response.header.set_authoritative(lookup.authoritative);
+    response
+        .header
+        .set_recursion_desired(request.header.recursion_desired());
+    response
+        .header
+        .set_checking_disabled(request.header.checking_disabled());
+    response.questions = request.questions.clone();
+    response.answers = lookup.answers;
+    response.authorities = lookup.authorities;
+    response.additionals = lookup.additionals;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use dns::{Class, Header, Question, RCode, Record, ResourceRecord, Serial, SerialPolicy, Type};
+
+    use super::*;
+
+    /// A zone file of its own for each test, as they run in parallel.
+    fn file(test: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("gravitas-{}-{}.zone", test, std::process::id()))
+    }
+
+    fn write(path: &Path, serial: u32, address: &str) {
+        let zone = format!(
+            "$TTL 1h\n\
+             @ SOA ns1 hostmaster {} 2h 15m 1w 300\n\
+             @ NS ns1\n\
+             ns1 A 192.0.2.1\n\
+             www A {}\n\
+             child NS ns.child\n\
+             ns.child A 192.0.2.6\n",
+            serial, address
+        );
+        std::fs::write(path, zone).unwrap();
+    }
+
+    fn primary(path: &Path) -> PrimaryZone {
+        PrimaryZone {
+            name: "example.com.".into(),
+            file: path.to_path_buf(),
+            notify: Vec::new(),
+            key: None,
+            serial_policy: None,
+        }
+    }
+
+    fn query(name: &str, r#type: Type) -> Packet<'static> {
+        Packet {
+            header: Header {
+                id: 1,
+                qdcount: 1,
+                ..Default::default()
+            },
+            questions: vec![Question {
+                name: name.parse().unwrap(),
+                r#type,
+                class: Class::IN,
+            }],
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        }
+    }
+
+    fn ask<'a>(authority: &Authority, request: &Packet<'a>) -> Option<Packet<'a>> {
+        let zone = authority.find(&request.questions[0].name)?;
+        Some(answer(&zone, request))
+    }
+
+    fn www(authority: &Authority) -> Record<'static> {
+        let response = ask(authority, &query("www.example.com", Type::A)).unwrap();
+        match &response.answers[0] {
+            ResourceRecord::Record { data, .. } => data.clone(),
+            _ => panic!("no address"),
+        }
+    }
+
+    fn serial(authority: &Authority) -> Serial {
+        authority
+            .get(&domain_name("example.com."))
+            .unwrap()
+            .serial()
+    }
+
+    #[test]
+    fn reload_with_a_higher_serial() {
+        let path = file("higher");
+        write(&path, 1, "192.0.2.2");
+        let authority = Authority::load(&[primary(&path)]);
+
+        write(&path, 2, "192.0.2.3");
+        assert!(authority.reload(&primary(&path)));
+        assert_eq!(serial(&authority), Serial(2));
+        assert!(matches!(
+            www(&authority),
+            Record::A {
+                address: [192, 0, 2, 3]
+            }
+        ));
+
+        // serials wrap around as per RFC 1982
+        for next in [0x7fff_ffff, 0xffff_fff0, 3] {
+            write(&path, next, "192.0.2.4");
+            assert!(authority.reload(&primary(&path)));
+            assert_eq!(serial(&authority), Serial(next));
+        }
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn reload_with_a_lower_serial_keeps_the_previous_version() {
+        let path = file("lower");
+        write(&path, 10, "192.0.2.2");
+        let authority = Authority::load(&[primary(&path)]);
+
+        write(&path, 9, "192.0.2.3");
+        assert!(!authority.reload(&primary(&path)));
+        assert_eq!(serial(&authority), Serial(10));
+        assert!(matches!(
+            www(&authority),
+            Record::A {
+                address: [192, 0, 2, 2]
+            }
+        ));
+
+        // an increment of exactly half the serial space is undefined
+        write(&path, 10 + 0x8000_0000, "192.0.2.3");
+        assert!(!authority.reload(&primary(&path)));
+        assert_eq!(serial(&authority), Serial(10));
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn reload_with_the_same_serial_isnt_announced() {
+        let path = file("same");
+        write(&path, 5, "192.0.2.2");
+        let authority = Authority::load(&[primary(&path)]);
+
+        write(&path, 5, "192.0.2.3");
+        assert!(!authority.reload(&primary(&path)));
+        assert!(matches!(
+            www(&authority),
+            Record::A {
+                address: [192, 0, 2, 3]
+            }
+        ));
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn reload_with_the_same_serial_advances_it_by_policy() {
+        let path = file("policy");
+        let zone = PrimaryZone {
+            serial_policy: Some(SerialPolicy::Increment),
+            ..primary(&path)
+        };
+        write(&path, 5, "192.0.2.2");
+        let authority = Authority::load(std::slice::from_ref(&zone));
+        assert_eq!(serial(&authority), Serial(5));
+
+        write(&path, 5, "192.0.2.3");
+        assert!(authority.reload(&zone));
+        assert_eq!(serial(&authority), Serial(6));
+        assert!(matches!(
+            www(&authority),
+            Record::A {
+                address: [192, 0, 2, 3]
+            }
+        ));
+
+        // the file is unchanged, but behind the served serial now
+        assert!(!authority.reload(&zone));
+        assert_eq!(serial(&authority), Serial(6));
+
+        write(&path, 5, "192.0.2.4");
+        assert!(authority.reload(&zone));
+        assert_eq!(serial(&authority), Serial(7));
+
+        // a serial in the file ahead of the served one is taken as is
+        write(&path, 100, "192.0.2.4");
+        assert!(authority.reload(&zone));
+        assert_eq!(serial(&authority), Serial(100));
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn authoritative_answers() {
+        let path = file("aa");
+        write(&path, 1, "192.0.2.2");
+        let authority = Authority::load(&[primary(&path)]);
+
+        let response = ask(&authority, &query("www.example.com", Type::A)).unwrap();
+        assert!(response.header.authoritative());
+        assert_eq!(response.header.rcode(), RCode::NoError);
+
+        let response = ask(&authority, &query("nope.example.com", Type::A)).unwrap();
+        assert!(response.header.authoritative());
+        assert_eq!(response.header.rcode(), RCode::NXDomain);
+
+        // referrals aren't authoritative, the data belongs to the child
+        let response = ask(&authority, &query("www.child.example.com", Type::A)).unwrap();
+        assert!(!response.header.authoritative());
+        assert_eq!(response.header.rcode(), RCode::NoError);
+        assert_eq!(response.authorities.len(), 1);
+        assert_eq!(response.additionals.len(), 1);
+
+        assert!(ask(&authority, &query("example.org", Type::A)).is_none());
+
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,1145 @@
+mod snapshot;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dns::{Class, DomainName, Question, RCode, Record, ResourceRecord, Type};
+use log::{debug, info, warn};
+
+use crate::config;
+use crate::resolver::Resolution;
+use crate::zone;
+
+/// Length of a CNAME chain followed within the cache.
+const MAX_CHAIN: usize = 8;
+
+/// TTL of expired records served while they can't be refreshed, as recommended by
+/// [RFC 8767 Section 4](https://www.rfc-editor.org/rfc/rfc8767#section-4).
+pub const STALE_TTL: u32 = 30;
+
+/// How long expired records are served right away after they couldn't be refreshed,
+/// the failure recheck timer of
+/// [RFC 8767 Section 4](https://www.rfc-editor.org/rfc/rfc8767#section-4).
+const FAILURE_RECHECK: Duration = Duration::from_secs(30);
+
+/// Uses of an entry after which it is refreshed before it expires.
+const PREFETCH_HITS: u32 = 2;
+
+/// Fraction of its TTL an entry has left when it is refreshed ahead of time.
+const PREFETCH_DIVISOR: u32 = 10;
+
+/// Refreshes that may run at the same time.
+const MAX_PREFETCHES: usize = 16;
+
+/// How often the statistics of the cache are logged.
+const REPORT_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Bytes an entry takes up besides its name and records, roughly.
+const ENTRY_OVERHEAD: usize = 128;
+
+/// Resolved answers, kept for as long as their TTLs allow.
+///
+/// Each RRset is cached on its own, keyed by owner, type and class, so that the
+/// links of a CNAME chain are shared between the queries that pass through them.
+/// Negative answers are cached for the TTL of the SOA that comes with them, capped
+/// by its minimum as per
+/// [RFC 2308 Section 5](https://www.rfc-editor.org/rfc/rfc2308#section-5).
+///
+/// Expired entries are kept for a while longer, to be served when they can't be
+/// refreshed as per [RFC 8767](https://www.rfc-editor.org/rfc/rfc8767).
+pub struct Cache {
+    entries: Mutex<Entries>,
+
+    max_size: usize,
+    min_ttl: u32,
+    max_ttl: u32,
+    max_negative_ttl: u32,
+    max_stale: Duration,
+    prefetch: bool,
+
+    /// Refreshes running at the moment.
+    prefetches: AtomicUsize,
+
+    hits: AtomicU64,
+    misses: AtomicU64,
+    stale: AtomicU64,
+    prefetched: AtomicU64,
+    evictions: AtomicU64,
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<Key, Entry>,
+
+    /// Keys by when they were last used, the least recently used first.
+    recency: BTreeMap<u64, Key>,
+
+    /// Advanced on every use, orders `recency`.
+    clock: u64,
+
+    /// Bytes taken up by all entries, roughly.
+    size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    /// The lowercased owner name.
+    name: String,
+
+    /// Unset for names that don't exist, which covers every type.
+    r#type: Option<u16>,
+
+    class: u16,
+}
+
+struct Entry {
+    data: Data,
+    expires: Instant,
+
+    /// The TTL the entry was cached with.
+    ttl: u32,
+
+    size: usize,
+
+    /// Position in `Entries::recency`.
+    used: u64,
+
+    /// Times the entry answered a query.
+    hits: u32,
+
+    /// Set once a refresh of the entry has started.
+    prefetching: bool,
+
+    /// Set while the expired entry is being refreshed.
+    refreshing: bool,
+
+    /// Until when the expired entry is served without being refreshed, after a
+    /// refresh failed.
+    recheck: Option<Instant>,
+}
+
+enum Data {
+    RRset(Vec<ResourceRecord<'static>>),
+
+    /// There is no data, as stated by the SOA the negative answer came with.
+    Negative(ResourceRecord<'static>),
+}
+
+/// What a lookup is for.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Answering a query, which counts as a use of the entries and may have them
+    /// refreshed.
+    Answer,
+
+    /// Finding out whether a query can be answered.
+    Probe,
+
+    /// Answering a query that couldn't be resolved, from expired entries too.
+    Stale,
+
+    /// Answering a query from expired entries too, which may have them refreshed.
+    Refresh,
+}
+
+/// An answer from the cache.
+pub struct Hit {
+    pub resolution: Resolution,
+
+    /// Set when the answer is about to expire and asked for often enough to be
+    /// refreshed ahead of time, which is then up to the caller.
+    pub prefetch: bool,
+}
+
+/// An expired answer from the cache.
+pub enum Stale {
+    /// The answer is to be served right away, as it is being refreshed already, or
+    /// a refresh failed less than [`FAILURE_RECHECK`] ago, or too many refreshes are
+    /// running.
+    Serve(Resolution),
+
+    /// The answer is to be refreshed, which is up to the caller, and handed to
+    /// [`Cache::refreshed`] once that is done.
+    Refresh(Refresh),
+}
+
+/// A refresh of expired entries, which no other query starts meanwhile.
+pub struct Refresh(Vec<Key>);
+
+/// Counters of how well the cache is doing.
+pub struct Stats {
+    pub hits: u64,
+    pub misses: u64,
+
+    /// Expired answers served because they couldn't be refreshed.
+    pub stale: u64,
+
+    pub prefetched: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub size: usize,
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} entries in {} bytes, {} hits, {} misses, {} stale, {} prefetched, {} evictions",
+            self.entries,
+            self.size,
+            self.hits,
+            self.misses,
+            self.stale,
+            self.prefetched,
+            self.evictions
+        )
+    }
+}
+
+impl Cache {
+    pub fn new(config: &config::Cache) -> Self {
+        Self {
+            entries: Mutex::default(),
+            max_size: config.max_size,
+            min_ttl: config.min_ttl,
+            max_ttl: config.max_ttl,
+            max_negative_ttl: config.max_negative_ttl,
+            max_stale: Duration::from_secs(u64::from(config.max_stale)),
+            prefetch: config.prefetch,
+            prefetches: AtomicUsize::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            stale: AtomicU64::new(0),
+            prefetched: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Answers `question` from the cache, with the TTLs of the records decremented by
+    /// the time they have been cached for.
+    pub fn get(&self, question: &Question) -> Option<Hit> {
+        let hit = self.lookup(question, Mode::Answer);
+
+        match hit {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+
+        hit.map(|(resolution, due)| Hit {
+            resolution,
+            prefetch: !due.is_empty(),
+        })
+    }
+
+    /// Whether `question` can be answered from the cache, without counting as a use.
+    pub fn contains(&self, question: &Question) -> bool {
+        self.lookup(question, Mode::Probe).is_some()
+    }
+
+    /// Answers `question` from the cache once the answer has expired, and decides
+    /// whether the query is to refresh it.
+    pub fn stale(&self, question: &Question) -> Option<Stale> {
+        let (resolution, due) = self.lookup(question, Mode::Refresh)?;
+        if due.is_empty() {
+            self.stale.fetch_add(1, Ordering::Relaxed);
+            return Some(Stale::Serve(resolution));
+        }
+
+        Some(Stale::Refresh(Refresh(due)))
+    }
+
+    /// Answers `question` from the cache even if the answer has expired, for when it
+    /// can't be resolved. Expired records get a TTL of [`STALE_TTL`].
+    pub fn get_stale(&self, question: &Question) -> Option<Resolution> {
+        let resolution = self
+            .lookup(question, Mode::Stale)
+            .map(|(resolution, _)| resolution);
+        if resolution.is_some() {
+            self.stale.fetch_add(1, Ordering::Relaxed);
+        }
+        resolution
+    }
+
+    /// The cached A and AAAA records of `name`, used as additional data for records
+    /// that refer to it. Unlike answers these don't count as hits and are never
+    /// refreshed ahead of time.
+    pub fn addresses(&self, name: &DomainName) -> Vec<ResourceRecord<'static>> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        let mut addresses = Vec::new();
+
+        for r#type in [Type::A, Type::AAAA] {
+            let key = Key {
+                name: zone::key(&name.labels),
+                r#type: Some(r#type.into()),
+                class: Class::IN.into(),
+            };
+
+            if let Some((entry, ttl)) = entries.get(&key, now, self.max_stale, Mode::Answer)
+                && let Data::RRset(records) = &entry.data
+            {
+                addresses.extend(with_ttl(records, ttl));
+            }
+        }
+
+        addresses
+    }
+
+    /// Looks `question` up, following CNAMEs. Returns the entries used that are due to
+    /// be refreshed, which a slot has been reserved for.
+    fn lookup(&self, question: &Question, mode: Mode) -> Option<(Resolution, Vec<Key>)> {
+        if question.r#type == Type::ANY {
+            return None;
+        }
+
+        let qtype = u16::from(question.r#type.clone());
+        let class = u16::from(question.class.clone());
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+
+        let mut name = zone::key(&question.name.labels);
+        let mut answers = Vec::new();
+
+        // entries used that are due to be refreshed, which a single resolution of
+        // the question does for all of them, unless one of them is refreshed or
+        // failed to be already
+        let mut due: Vec<Key> = Vec::new();
+        let mut blocked = false;
+        let mut used = |key: &Key, entry: &mut Entry, ttl: u32| match mode {
+            Mode::Answer if self.used(entry, ttl) => due.push(key.clone()),
+            Mode::Refresh if entry.expires <= now => {
+                match entry.refreshing || entry.recheck.is_some_and(|recheck| now < recheck) {
+                    true => blocked = true,
+                    false => due.push(key.clone()),
+                }
+            }
+            _ => {}
+        };
+
+        let resolution = 'lookup: {
+            for _ in 0..=MAX_CHAIN {
+                let key = |r#type| Key {
+                    name: name.clone(),
+                    r#type,
+                    class,
+                };
+
+                let positive = key(Some(qtype));
+                if let Some((entry, ttl)) = entries.get(&positive, now, self.max_stale, mode) {
+                    used(&positive, entry, ttl);
+                    let (answer, authorities) = match &entry.data {
+                        Data::RRset(records) => (with_ttl(records, ttl), Vec::new()),
+                        Data::Negative(soa) => {
+                            (Vec::new(), with_ttl(std::slice::from_ref(soa), ttl))
+                        }
+                    };
+                    answers.extend(answer);
+
+                    break 'lookup Resolution {
+                        rcode: RCode::NoError,
+                        answers,
+                        authorities,
+                    };
+                }
+
+                let negative = key(None);
+                if let Some((entry, ttl)) = entries.get(&negative, now, self.max_stale, mode)
+                    && let Data::Negative(soa) = &entry.data
+                {
+                    let authorities = with_ttl(std::slice::from_ref(soa), ttl);
+                    used(&negative, entry, ttl);
+                    break 'lookup Resolution {
+                        rcode: RCode::NXDomain,
+                        answers,
+                        authorities,
+                    };
+                }
+
+                if question.r#type == Type::CNAME {
+                    return None;
+                }
+
+                let alias = key(Some(u16::from(Type::CNAME)));
+                let (entry, ttl) = entries.get(&alias, now, self.max_stale, mode)?;
+                used(&alias, entry, ttl);
+                let Data::RRset(records) = &entry.data else {
+                    return None;
+                };
+                let target = records.iter().find_map(cname)?;
+
+                name = zone::key(&target.labels);
+                answers.extend(with_ttl(records, ttl));
+            }
+
+            return None;
+        };
+
+        if blocked || due.is_empty() || !self.reserve() {
+            return Some((resolution, Vec::new()));
+        }
+
+        for key in &due {
+            if let Some(entry) = entries.map.get_mut(key) {
+                match mode {
+                    Mode::Refresh => entry.refreshing = true,
+                    _ => entry.prefetching = true,
+                }
+            }
+        }
+
+        Some((resolution, due))
+    }
+
+    /// Counts a use of `entry`, which answered a query with `remaining` seconds left,
+    /// and returns whether it should be refreshed now.
+    fn used(&self, entry: &mut Entry, remaining: u32) -> bool {
+        entry.hits = entry.hits.saturating_add(1);
+
+        self.prefetch
+            && !entry.prefetching
+            && entry.hits >= PREFETCH_HITS
+            && remaining <= entry.ttl / PREFETCH_DIVISOR
+    }
+
+    /// Takes up a slot for a refresh if another one may run, which must be followed
+    /// by a call to [`Cache::prefetched`] or [`Cache::refreshed`].
+    fn reserve(&self) -> bool {
+        self.prefetches
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |running| {
+                (running < MAX_PREFETCHES).then_some(running + 1)
+            })
+            .is_ok()
+    }
+
+    /// Marks a refresh that was due as finished.
+    pub fn prefetched(&self) {
+        self.prefetches.fetch_sub(1, Ordering::Relaxed);
+        self.prefetched.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Marks a refresh of expired entries as finished. The entries of a failed one
+    /// are served without being refreshed for [`FAILURE_RECHECK`].
+    pub fn refreshed(&self, refresh: Refresh, succeeded: bool) {
+        self.prefetches.fetch_sub(1, Ordering::Relaxed);
+
+        let recheck = (!succeeded).then(|| Instant::now() + FAILURE_RECHECK);
+        let mut entries = self.entries.lock().unwrap();
+        for key in &refresh.0 {
+            // entries replaced by the refresh start out anew
+            if let Some(entry) = entries.map.get_mut(key)
+                && entry.refreshing
+            {
+                entry.refreshing = false;
+                entry.recheck = recheck;
+            }
+        }
+    }
+
+    /// Caches the RRsets of a resolution of `question`, and the absence of data when
+    /// the resolution is negative.
+    pub fn insert(&self, question: &Question, resolution: &Resolution) {
+        if question.r#type == Type::ANY
+            || !matches!(resolution.rcode, RCode::NoError | RCode::NXDomain)
+        {
+            return;
+        }
+
+        // records off the chain from the question's name weren't asked for, and
+        // could be planted by the server answering, RFC 2181 Section 5.4.1
+        let chain = chain(&question.name, &question.r#type, &resolution.answers);
+
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+
+        let mut rrsets: Vec<(Key, Vec<ResourceRecord<'static>>)> = Vec::new();
+        for record in &resolution.answers {
+            let Some(key) = Key::of(record) else {
+                continue;
+            };
+            if !is_on(&chain, &key, record) {
+                debug!(
+                    "not caching {} off the chain of {}",
+                    key.name, question.name
+                );
+                continue;
+            }
+
+            match rrsets.iter_mut().find(|(existing, _)| *existing == key) {
+                Some((_, rrset)) => rrset.push(record.clone()),
+                None => rrsets.push((key, vec![record.clone()])),
+            }
+        }
+
+        for (key, rrset) in rrsets {
+            // RFC 2181 Section 5.2, the records of an RRset should share their TTL
+            let ttl = rrset
+                .iter()
+                .filter_map(ttl)
+                .min()
+                .unwrap_or(0)
+                .max(self.min_ttl)
+                .min(self.max_ttl);
+
+            let expires = now + Duration::from_secs(u64::from(ttl));
+            self.store(&mut entries, key, Data::RRset(rrset), ttl, expires);
+        }
+
+        let name = chain[chain.len() - 1].clone();
+        let r#type = match resolution.rcode {
+            RCode::NXDomain => None,
+            _ if resolution.answers.iter().any(|record| {
+                Key::of(record).is_some_and(|key| {
+                    key.name == name && key.r#type == Some(u16::from(question.r#type.clone()))
+                })
+            }) =>
+            {
+                return;
+            }
+            _ => Some(u16::from(question.r#type.clone())),
+        };
+
+        // negative answers without an SOA are not cached, RFC 2308 Section 5
+        let Some((soa, ttl)) = resolution
+            .authorities
+            .iter()
+            .find_map(|record| match record {
+                ResourceRecord::Record {
+                    ttl,
+                    data: Record::SOA { minimum, .. },
+                    ..
+                } => Some((record, (*ttl).min(*minimum))),
+                _ => None,
+            })
+        else {
+            return;
+        };
+
+        let key = Key {
+            name,
+            r#type,
+            class: u16::from(question.class.clone()),
+        };
+        let ttl = ttl.min(self.max_negative_ttl);
+        let expires = now + Duration::from_secs(u64::from(ttl));
+        self.store(&mut entries, key, Data::Negative(soa.clone()), ttl, expires);
+    }
+
+    /// Adds an entry, evicting the least recently used ones beyond the size limit.
+    fn store(&self, entries: &mut Entries, key: Key, data: Data, ttl: u32, expires: Instant) {
+        if ttl == 0 {
+            return;
+        }
+
+        let size = ENTRY_OVERHEAD + key.name.len() + data.size();
+        if size > self.max_size {
+            return;
+        }
+
+        entries.remove(&key);
+
+        entries.clock += 1;
+        let used = entries.clock;
+        entries.recency.insert(used, key.clone());
+        entries.size += size;
+        entries.map.insert(
+            key,
+            Entry {
+                data,
+                expires,
+                ttl,
+                size,
+                used,
+                hits: 0,
+                prefetching: false,
+                refreshing: false,
+                recheck: None,
+            },
+        );
+
+        while entries.size > self.max_size {
+            let Some((_, key)) = entries.recency.pop_first() else {
+                break;
+            };
+            if let Some(entry) = entries.map.remove(&key) {
+                entries.size -= entry.size;
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Moves every entry and its recheck timer `elapsed` closer to expiring, as if it
+    /// was cached that much earlier.
+    #[cfg(test)]
+    pub fn age(&self, elapsed: Duration) {
+        for entry in self.entries.lock().unwrap().map.values_mut() {
+            entry.expires -= elapsed;
+            entry.recheck = entry.recheck.map(|recheck| recheck - elapsed);
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let entries = self.entries.lock().unwrap();
+
+        Stats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            stale: self.stale.load(Ordering::Relaxed),
+            prefetched: self.prefetched.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            entries: entries.map.len(),
+            size: entries.size,
+        }
+    }
+}
+
+/// Logs the statistics of `cache` every [`REPORT_INTERVAL`].
+pub fn report(cache: Arc<Cache>) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(REPORT_INTERVAL);
+            info!("cache: {}", cache.stats());
+        }
+    });
+}
+
+/// Saves `cache` to `path` every `interval`, unless it is zero, and once more when
+/// the server is shut down with SIGINT or SIGTERM.
+pub fn persist(cache: Arc<Cache>, path: PathBuf, interval: Duration) {
+    let save = move || match cache.save(&path) {
+        Ok(count) => debug!("saved {} cache entries to {}", count, path.display()),
+        Err(err) => warn!("couldn't save cache to {}: {}", path.display(), err),
+    };
+
+    if !interval.is_zero() {
+        let save = save.clone();
+        thread::spawn(move || {
+            loop {
+                thread::sleep(interval);
+                save();
+            }
+        });
+    }
+
+    let shutdown = ctrlc::set_handler(move || {
+        info!("shutting down");
+        save();
+        std::process::exit(0);
+    });
+    if let Err(err) = shutdown {
+        warn!("couldn't save cache on shutdown: {}", err);
+    }
+}
+
+impl Entries {
+    /// The entry for `key` with the seconds it has left, marking it as recently used
+    /// when answering.
+    ///
+    /// Expired entries are only returned for [`Mode::Stale`] and [`Mode::Refresh`], with a TTL of
+    /// [`STALE_TTL`]. Once they are expired for longer than `max_stale` they are
+    /// removed.
+    fn get(
+        &mut self,
+        key: &Key,
+        now: Instant,
+        max_stale: Duration,
+        mode: Mode,
+    ) -> Option<(&mut Entry, u32)> {
+        let expires = self.map.get(key)?.expires;
+        let ttl = match expires.checked_duration_since(now) {
+            Some(remaining) => remaining.as_secs() as u32,
+            None if now.duration_since(expires) >= max_stale => {
+                self.remove(key);
+                return None;
+            }
+            None if matches!(mode, Mode::Stale | Mode::Refresh) => STALE_TTL,
+            None => return None,
+        };
+
+        let entry = self.map.get_mut(key)?;
+        if mode == Mode::Answer {
+            self.clock += 1;
+            self.recency.remove(&entry.used);
+            self.recency.insert(self.clock, key.clone());
+            entry.used = self.clock;
+        }
+
+        Some((entry, ttl))
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.map.remove(key) {
+            self.recency.remove(&entry.used);
+            self.size -= entry.size;
+        }
+    }
+}
+
+impl Key {
+    /// The key of the RRset `record` belongs to.
+    fn of(record: &ResourceRecord) -> Option<Self> {
+        match record {
+            ResourceRecord::Record { name, class, .. }
+            | ResourceRecord::Unknown { name, class, .. } => Some(Self {
+                name: zone::key(&name.labels),
+                r#type: Some(u16::from(Type::from(record))),
+                class: u16::from(class.clone()),
+            }),
+            ResourceRecord::OPTRecord { .. } => None,
+        }
+    }
+}
+
+impl Data {
+    fn records(&self) -> &[ResourceRecord<'static>] {
+        match self {
+            Self::RRset(records) => records,
+            Self::Negative(soa) => std::slice::from_ref(soa),
+        }
+    }
+
+    /// Bytes taken up by the records, roughly.
+    fn size(&self) -> usize {
+        self.records()
+            .iter()
+            .map(|record| size_of::<ResourceRecord>() + record.size())
+            .sum()
+    }
+}
+
+fn ttl(record: &ResourceRecord) -> Option<u32> {
+    match record {
+        ResourceRecord::Record { ttl, .. } | ResourceRecord::Unknown { ttl, .. } => Some(*ttl),
+        ResourceRecord::OPTRecord { .. } => None,
+    }
+}
+
+fn cname<'a>(record: &ResourceRecord<'a>) -> Option<DomainName<'a>> {
+    match record {
+        ResourceRecord::Record {
+            data: Record::CNAME { cname },
+            ..
+        } => Some(cname.clone()),
+        _ => None,
+    }
+}
+
+/// Copies of `records` with their TTL set to what is left of it.
+fn with_ttl(records: &[ResourceRecord<'static>], remaining: u32) -> Vec<ResourceRecord<'static>> {
+    records
+        .iter()
+        .cloned()
+        .map(|mut record| {
+            if let ResourceRecord::Record { ttl, .. } | ResourceRecord::Unknown { ttl, .. } =
+                &mut record
+            {
+                *ttl = remaining;
+            }
+            record
+        })
+        .collect()
+}
+
+/// The lowercased names on the CNAME chain in `answers` that starts at `name`, in
+/// order. The last one is the one a negative answer is about.
+fn chain(name: &DomainName, r#type: &Type, answers: &[ResourceRecord]) -> Vec<String> {
+    let mut chain = vec![zone::key(&name.labels)];
+    if *r#type == Type::CNAME {
+        return chain;
+    }
+
+    for _ in 0..MAX_CHAIN {
+        let end = chain.last().unwrap();
+        let target = answers
+            .iter()
+            .filter(|record| Key::of(record).is_some_and(|key| key.name == *end))
+            .find_map(cname);
+
+        match target.map(|target| zone::key(&target.labels)) {
+            Some(target) if !chain.contains(&target) => chain.push(target),
+            _ => break,
+        }
+    }
+
+    chain
+}
+
+/// Whether `record` belongs to the answer along `chain`: it is owned by one of its
+/// names, or is a DNAME that one of them was substituted below.
+fn is_on(chain: &[String], key: &Key, record: &ResourceRecord) -> bool {
+    if chain.contains(&key.name) {
+        return true;
+    }
+
+    let dname = matches!(
+        record,
+        ResourceRecord::Record {
+            data: Record::DNAME { .. },
+            ..
+        }
+    );
+    dname
+        && chain.iter().any(|name| match key.name.as_str() {
+            "." => name != ".",
+            owner => name.ends_with(owner) && name[..name.len() - owner.len()].ends_with('.'),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use dns::Serial;
+
+    use super::*;
+
+    fn cache() -> Cache {
+        Cache::new(&config::Cache::default())
+    }
+
+    fn name(name: &str) -> DomainName<'static> {
+        name.parse().unwrap()
+    }
+
+    fn question(qname: &str, r#type: Type) -> Question<'static> {
+        Question {
+            name: name(qname),
+            r#type,
+            class: Class::IN,
+        }
+    }
+
+    fn address(owner: &str, address: &str) -> ResourceRecord<'static> {
+        ResourceRecord::address(name(owner), 300, address.parse::<IpAddr>().unwrap())
+    }
+
+    fn answer(answers: Vec<ResourceRecord<'static>>) -> Resolution {
+        Resolution {
+            rcode: RCode::NoError,
+            answers,
+            authorities: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn addresses() {
+        let cache = cache();
+        cache.insert(
+            &question("mail.example.net", Type::A),
+            &answer(vec![address("mail.example.net", "192.0.2.1")]),
+        );
+        cache.insert(
+            &question("mail.example.net", Type::AAAA),
+            &answer(vec![address("mail.example.net", "2001:db8::1")]),
+        );
+
+        let addresses = cache.addresses(&name("MAIL.example.net"));
+        let types: Vec<_> = addresses.iter().map(Type::from).collect();
+        assert_eq!(types, [Type::A, Type::AAAA]);
+        assert!(cache.addresses(&name("www.example.net")).is_empty());
+
+        // looking up additional data isn't a hit
+        assert_eq!(cache.stats().hits, 0);
+    }
+
+    fn record(owner: &str, data: Record<'static>) -> ResourceRecord<'static> {
+        ResourceRecord::Record {
+            name: name(owner),
+            class: Class::IN,
+            ttl: 300,
+            data,
+        }
+    }
+
+    #[test]
+    fn only_the_chain_is_cached() {
+        let cache = cache();
+        let cname = Record::CNAME {
+            cname: name("www.example.net"),
+        };
+        cache.insert(
+            &question("www.example.com", Type::A),
+            &answer(vec![
+                record("www.example.com", cname),
+                address("www.example.net", "192.0.2.1"),
+                address("www.example.org", "192.0.2.66"),
+            ]),
+        );
+
+        let hit = cache.get(&question("www.example.com", Type::A)).unwrap();
+        assert_eq!(hit.resolution.answers.len(), 2);
+        assert_eq!(cache.addresses(&name("www.example.net")).len(), 1);
+        assert!(cache.addresses(&name("www.example.org")).is_empty());
+    }
+
+    #[test]
+    fn dname_above_the_chain_is_cached() {
+        let cache = cache();
+        let dname = Record::DNAME {
+            target: name("example.net"),
+        };
+        let cname = Record::CNAME {
+            cname: name("www.example.net"),
+        };
+        let unrelated = Record::DNAME {
+            target: name("example.net"),
+        };
+        cache.insert(
+            &question("www.example.org", Type::A),
+            &answer(vec![
+                record("example.org", dname),
+                record("www.example.org", cname),
+                address("www.example.net", "192.0.2.1"),
+                record("ample.org", unrelated),
+            ]),
+        );
+
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 3);
+    }
+
+    fn prefetching() -> Cache {
+        Cache::new(&config::Cache {
+            prefetch: true,
+            ..Default::default()
+        })
+    }
+
+    /// Brings every entry close enough to expiring to be refreshed.
+    fn expire_soon(cache: &Cache) {
+        let soon = Instant::now() + Duration::from_secs(1);
+        for entry in cache.entries.lock().unwrap().map.values_mut() {
+            entry.expires = soon;
+        }
+    }
+
+    fn prefetches(cache: &Cache) -> usize {
+        cache.prefetches.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn prefetch_is_due_once_per_hit() {
+        let cache = prefetching();
+        let question = question("www.example.com", Type::A);
+        let cname = Record::CNAME {
+            cname: name("web.example.com"),
+        };
+        cache.insert(
+            &question,
+            &answer(vec![
+                record("www.example.com", cname),
+                address("web.example.com", "192.0.2.1"),
+            ]),
+        );
+        for _ in 0..PREFETCH_HITS {
+            assert!(!cache.get(&question).unwrap().prefetch);
+        }
+        expire_soon(&cache);
+
+        // both entries are due, the answer is refreshed as a whole
+        assert!(cache.get(&question).unwrap().prefetch);
+        assert_eq!(prefetches(&cache), 1);
+        assert!(!cache.get(&question).unwrap().prefetch);
+        assert_eq!(prefetches(&cache), 1);
+
+        cache.prefetched();
+        assert_eq!(prefetches(&cache), 0);
+    }
+
+    #[test]
+    fn prefetch_is_only_due_for_hits() {
+        let cache = prefetching();
+        let question = question("www.example.com", Type::A);
+        let cname = Record::CNAME {
+            cname: name("web.example.com"),
+        };
+        cache.insert(&question, &answer(vec![record("www.example.com", cname)]));
+        for _ in 0..PREFETCH_HITS {
+            cache.contains(&question);
+            cache.get_stale(&question);
+        }
+        expire_soon(&cache);
+
+        // the target of the CNAME isn't cached
+        for _ in 0..=PREFETCH_HITS {
+            assert!(cache.get(&question).is_none());
+        }
+        assert!(!cache.contains(&question));
+        assert!(cache.get_stale(&question).is_none());
+        assert_eq!(prefetches(&cache), 0);
+    }
+
+    fn ttls(records: &[ResourceRecord]) -> Vec<u32> {
+        records.iter().filter_map(ttl).collect()
+    }
+
+    #[test]
+    fn ttls_are_decremented_on_hits() {
+        let cache = cache();
+        let question = question("www.example.com", Type::A);
+        cache.insert(
+            &question,
+            &answer(vec![address("www.example.com", "192.0.2.1")]),
+        );
+        cache.age(Duration::from_secs(100));
+
+        let hit = cache.get(&question).unwrap();
+        let ttl = ttls(&hit.resolution.answers)[0];
+        assert!((199..=200).contains(&ttl), "{ttl}");
+        assert_eq!(cache.stats().hits, 1);
+    }
+
+    #[test]
+    fn ttls_are_capped() {
+        let question = question("www.example.com", Type::A);
+        let cached = |min_ttl, max_ttl| {
+            let cache = Cache::new(&config::Cache {
+                min_ttl,
+                max_ttl,
+                ..Default::default()
+            });
+            cache.insert(
+                &question,
+                &answer(vec![address("www.example.com", "192.0.2.1")]),
+            );
+            cache
+                .entries
+                .lock()
+                .unwrap()
+                .map
+                .values()
+                .next()
+                .unwrap()
+                .ttl
+        };
+
+        assert_eq!(cached(0, 86400), 300);
+        assert_eq!(cached(600, 86400), 600);
+        assert_eq!(cached(0, 60), 60);
+    }
+
+    fn negative(rcode: RCode) -> Resolution {
+        let soa = Record::SOA {
+            mname: name("ns.example.com"),
+            rname: name("hostmaster.example.com"),
+            serial: Serial(1),
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 900,
+        };
+        Resolution {
+            rcode,
+            answers: Vec::new(),
+            authorities: vec![ResourceRecord::Record {
+                name: name("example.com"),
+                class: Class::IN,
+                ttl: 3600,
+                data: soa,
+            }],
+        }
+    }
+
+    #[test]
+    fn names_that_dont_exist_are_cached_for_the_soa_minimum() {
+        let cache = cache();
+        cache.insert(
+            &question("nx.example.com", Type::A),
+            &negative(RCode::NXDomain),
+        );
+
+        // the name doesn't exist for any type
+        for r#type in [Type::A, Type::MX] {
+            let hit = cache.get(&question("nx.example.com", r#type)).unwrap();
+            assert_eq!(hit.resolution.rcode, RCode::NXDomain);
+            assert!(hit.resolution.answers.is_empty());
+            let ttl = ttls(&hit.resolution.authorities)[0];
+            assert!((899..=900).contains(&ttl), "{ttl}");
+        }
+    }
+
+    #[test]
+    fn missing_types_are_cached_for_the_soa_minimum() {
+        let cache = Cache::new(&config::Cache {
+            max_negative_ttl: 600,
+            ..Default::default()
+        });
+        cache.insert(
+            &question("www.example.com", Type::AAAA),
+            &negative(RCode::NoError),
+        );
+
+        let hit = cache.get(&question("www.example.com", Type::AAAA)).unwrap();
+        assert_eq!(hit.resolution.rcode, RCode::NoError);
+        assert!(hit.resolution.answers.is_empty());
+        let ttl = ttls(&hit.resolution.authorities)[0];
+        assert!((599..=600).contains(&ttl), "{ttl}");
+
+        // other types of the name may exist
+        assert!(cache.get(&question("www.example.com", Type::A)).is_none());
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_evicted() {
+        let insert = |cache: &Cache, owner| {
+            cache.insert(
+                &question(owner, Type::A),
+                &answer(vec![address(owner, "192.0.2.1")]),
+            );
+        };
+        let one = {
+            let cache = cache();
+            insert(&cache, "a.example.com");
+            cache.stats().size
+        };
+        let cache = Cache::new(&config::Cache {
+            max_size: 2 * one + one / 2,
+            ..Default::default()
+        });
+
+        insert(&cache, "a.example.com");
+        insert(&cache, "b.example.com");
+        assert!(cache.get(&question("a.example.com", Type::A)).is_some());
+        // only answering counts as a use
+        assert!(cache.contains(&question("b.example.com", Type::A)));
+        insert(&cache, "c.example.com");
+
+        let stats = cache.stats();
+        assert_eq!(
+            (stats.entries, stats.size, stats.evictions),
+            (2, 2 * one, 1)
+        );
+        assert!(cache.contains(&question("a.example.com", Type::A)));
+        assert!(!cache.contains(&question("b.example.com", Type::A)));
+        assert!(cache.contains(&question("c.example.com", Type::A)));
+    }
+
+    #[test]
+    fn expired_answers_are_refreshed_once() {
+        let cache = cache();
+        let question = question("www.example.com", Type::A);
+        cache.insert(
+            &question,
+            &answer(vec![address("www.example.com", "192.0.2.1")]),
+        );
+        assert!(matches!(cache.stale(&question), Some(Stale::Serve(_))));
+        cache.age(Duration::from_secs(300));
+
+        let Some(Stale::Refresh(refresh)) = cache.stale(&question) else {
+            panic!("expired answer isn't refreshed");
+        };
+        assert!(matches!(cache.stale(&question), Some(Stale::Serve(_))));
+        assert_eq!(prefetches(&cache), 1);
+
+        // after a failure the answer isn't refreshed until the recheck timer expires
+        cache.refreshed(refresh, false);
+        assert_eq!(prefetches(&cache), 0);
+        assert!(matches!(cache.stale(&question), Some(Stale::Serve(_))));
+        cache.age(FAILURE_RECHECK);
+        assert!(matches!(cache.stale(&question), Some(Stale::Refresh(_))));
+    }
+}
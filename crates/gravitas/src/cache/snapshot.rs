@@ -0,0 +1,389 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use dns::{
+    ResourceRecord,
+    proto::{Parse, ParseError, Parser, Serialize, Serializer},
+};
+
+use super::{Cache, Data, Key};
+use crate::handler::unix_time;
+
+/// Marks a file as a snapshot of the cache, followed by the version of the format.
+const MAGIC: &[u8; 4] = b"GRVC";
+const VERSION: u8 = 1;
+
+/// Flags of an entry in a snapshot.
+const NEGATIVE: u8 = 1;
+const HAS_TYPE: u8 = 2;
+
+/// An entry copied out of the cache to be saved.
+struct Saved {
+    key: Key,
+
+    /// Seconds since the Unix epoch the entry expires at.
+    expires: u64,
+
+    ttl: u32,
+    negative: bool,
+    records: Vec<ResourceRecord<'static>>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(std::io::Error),
+    Invalid(String),
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "couldn't access snapshot: {}", err),
+            Self::Invalid(message) => write!(f, "invalid snapshot: {}", message),
+        }
+    }
+}
+
+impl From<ParseError> for SnapshotError {
+    fn from(err: ParseError) -> Self {
+        Self::Invalid(err.to_string())
+    }
+}
+
+impl Cache {
+    /// Writes all entries to `path`, the least recently used first, and returns how
+    /// many there were. The file is replaced at once, so that a crash never leaves a
+    /// snapshot half written.
+    ///
+    /// Entries are stored with the time they expire by the wall clock, as instants
+    /// don't carry over to another process.
+    pub fn save(&self, path: &Path) -> Result<usize, SnapshotError> {
+        self.save_at(path, unix_time())
+    }
+
+    /// Saves the entries as of `wall` seconds since the Unix epoch.
+    fn save_at(&self, path: &Path, wall: u64) -> Result<usize, SnapshotError> {
+        let now = Instant::now();
+
+        // copied out first, so that lookups aren't held up while the snapshot is
+        // serialized and written
+        let entries = self.entries.lock().unwrap();
+        let saved: Vec<Saved> = entries
+            .recency
+            .values()
+            .filter_map(|key| {
+                let entry = entries.map.get(key)?;
+                let expires = match entry.expires.checked_duration_since(now) {
+                    Some(remaining) => wall.saturating_add(remaining.as_secs()),
+                    None => wall.saturating_sub(now.duration_since(entry.expires).as_secs()),
+                };
+
+                Some(Saved {
+                    key: key.clone(),
+                    expires,
+                    ttl: entry.ttl,
+                    negative: matches!(entry.data, Data::Negative(_)),
+                    records: entry.data.records().to_vec(),
+                })
+            })
+            .collect();
+        drop(entries);
+
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+
+        let mut buf = vec![0; usize::from(u16::MAX)];
+        for Saved {
+            key,
+            expires,
+            ttl,
+            negative,
+            records,
+        } in &saved
+        {
+            let mut flags = 0;
+            if *negative {
+                flags |= NEGATIVE;
+            }
+            if key.r#type.is_some() {
+                flags |= HAS_TYPE;
+            }
+
+            out.push(flags);
+            out.extend_from_slice(&key.r#type.unwrap_or(0).to_be_bytes());
+            out.extend_from_slice(&key.class.to_be_bytes());
+            out.extend_from_slice(&(key.name.len() as u16).to_be_bytes());
+            out.extend_from_slice(key.name.as_bytes());
+            out.extend_from_slice(&expires.to_be_bytes());
+            out.extend_from_slice(&ttl.to_be_bytes());
+
+            out.extend_from_slice(&(records.len() as u16).to_be_bytes());
+            for record in records {
+                let mut serializer = Serializer::new(&mut buf);
+                record.clone().serialize(&mut serializer).map_err(|err| {
+                    SnapshotError::Invalid(format!("couldn't serialize {}: {:?}", key.name, err))
+                })?;
+
+                let wire = serializer.written();
+                out.extend_from_slice(&(wire.len() as u16).to_be_bytes());
+                out.extend_from_slice(wire);
+            }
+        }
+
+        write(path, &out).map_err(SnapshotError::Io)?;
+
+        Ok(saved.len())
+    }
+
+    /// Adds the entries of the snapshot at `path`, if there is one, and returns how
+    /// many were restored. The time since the snapshot was taken counts against their
+    /// TTLs, entries that expired for longer than the stale window are left out.
+    pub fn restore(&self, path: &Path) -> Result<usize, SnapshotError> {
+        self.restore_at(path, unix_time())
+    }
+
+    /// Restores the entries as of `wall` seconds since the Unix epoch.
+    fn restore_at(&self, path: &Path, wall: u64) -> Result<usize, SnapshotError> {
+        let content = match std::fs::read(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(SnapshotError::Io(err)),
+        };
+
+        let mut parser = Parser::new(&content);
+        if parser.consume_bytes(MAGIC.len())? != MAGIC || parser.consume_u8()? != VERSION {
+            return Err(SnapshotError::Invalid("unknown format".to_string()));
+        }
+
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        let mut restored = 0;
+
+        while parser.remaining() > 0 {
+            let flags = parser.consume_u8()?;
+            let r#type = parser.consume_u16()?;
+            let class = parser.consume_u16()?;
+            let len = parser.consume_u16()?;
+            let name = std::str::from_utf8(parser.consume_bytes(usize::from(len))?)
+                .map_err(|err| SnapshotError::Invalid(err.to_string()))?
+                .to_string();
+            let expires = u64::from_be_bytes(
+                parser
+                    .consume_bytes(size_of::<u64>())?
+                    .try_into()
+                    .expect("consumed the size of a u64"),
+            );
+            let ttl = parser.consume_u32()?;
+
+            let count = parser.consume_u16()?;
+            let mut records = Vec::with_capacity(usize::from(count));
+            for _ in 0..count {
+                let len = parser.consume_u16()?;
+                let wire = parser.consume_bytes(usize::from(len))?;
+                records.push(ResourceRecord::parse(&mut Parser::new(wire))?.into_owned());
+            }
+
+            let data = match flags & NEGATIVE {
+                0 => Data::RRset(records),
+                _ => Data::Negative(records.into_iter().next().ok_or_else(|| {
+                    SnapshotError::Invalid(format!("negative entry for {} without soa", name))
+                })?),
+            };
+
+            // an instant can only be made relative to now
+            let expires = match expires.checked_sub(wall) {
+                Some(remaining) => now.checked_add(Duration::from_secs(remaining)),
+                None => Some(Duration::from_secs(wall - expires))
+                    .filter(|expired| *expired < self.max_stale)
+                    .and_then(|expired| now.checked_sub(expired)),
+            };
+            let Some(expires) = expires else {
+                continue;
+            };
+
+            let key = Key {
+                name,
+                r#type: (flags & HAS_TYPE != 0).then_some(r#type),
+                class,
+            };
+            self.store(&mut entries, key, data, ttl, expires);
+            restored += 1;
+        }
+
+        Ok(restored)
+    }
+}
+
+/// Replaces the file at `path` with `content` through a temporary file, which is
+/// flushed to disk before it is renamed, as is the directory after, so that the
+/// snapshot is either the old or the new one after a crash.
+fn write(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    drop(file);
+
+    std::fs::rename(&tmp, path)?;
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use dns::{Class, Question, RCode, Record, Serial, Type};
+
+    use super::*;
+    use crate::cache::STALE_TTL;
+    use crate::config;
+    use crate::resolver::Resolution;
+
+    /// When the snapshots are taken.
+    const SAVED: u64 = 1_700_000_000;
+
+    fn question(r#type: Type) -> Question<'static> {
+        Question {
+            name: "www.example.com".parse().unwrap(),
+            r#type,
+            class: Class::IN,
+        }
+    }
+
+    fn address() -> Resolution {
+        let address = "192.0.2.1".parse::<std::net::IpAddr>().unwrap();
+        Resolution {
+            rcode: RCode::NoError,
+            answers: vec![ResourceRecord::address(
+                question(Type::A).name,
+                300,
+                address,
+            )],
+            authorities: Vec::new(),
+        }
+    }
+
+    /// A path of its own for each test, as they run at the same time.
+    fn path(test: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("gravitas-{}-{}.snapshot", std::process::id(), test))
+    }
+
+    /// A copy of `cache` made through a snapshot restored `elapsed` seconds after it
+    /// was saved, with how many entries were restored.
+    fn through_snapshot(cache: &Cache, test: &str, elapsed: u64) -> (Cache, usize) {
+        let path = path(test);
+        cache.save_at(&path, SAVED).unwrap();
+
+        let restored = Cache::new(&config::Cache {
+            max_stale: 60,
+            ..Default::default()
+        });
+        let count = restored.restore_at(&path, SAVED + elapsed).unwrap();
+        std::fs::remove_file(path).ok();
+
+        (restored, count)
+    }
+
+    fn ttls(records: &[ResourceRecord]) -> Vec<u32> {
+        records
+            .iter()
+            .filter_map(|record| match record {
+                ResourceRecord::Record { ttl, .. } => Some(*ttl),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn save_and_restore() {
+        let question = question(Type::A);
+        let resolution = address();
+
+        let cache = Cache::new(&config::Cache::default());
+        cache.insert(&question, &resolution);
+
+        let path = path("save_and_restore");
+        assert_eq!(cache.save(&path).unwrap(), 1);
+
+        let restored = Cache::new(&config::Cache::default());
+        assert_eq!(restored.restore(&path).unwrap(), 1);
+        let hit = restored.get(&question).unwrap();
+        assert_eq!(hit.resolution.answers.len(), 1);
+        assert_eq!(
+            hit.resolution.answers[0].size(),
+            resolution.answers[0].size()
+        );
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn time_since_saving_counts_against_ttls() {
+        let cache = Cache::new(&config::Cache::default());
+        cache.insert(&question(Type::A), &address());
+
+        let (restored, count) = through_snapshot(&cache, "ttls", 100);
+        assert_eq!(count, 1);
+        let hit = restored.get(&question(Type::A)).unwrap();
+        let ttl = ttls(&hit.resolution.answers)[0];
+        assert!((198..=200).contains(&ttl), "{ttl}");
+    }
+
+    #[test]
+    fn expired_entries_are_restored_while_they_may_be_served() {
+        let cache = Cache::new(&config::Cache::default());
+        cache.insert(&question(Type::A), &address());
+
+        let (restored, count) = through_snapshot(&cache, "stale", 330);
+        assert_eq!(count, 1);
+        assert!(restored.get(&question(Type::A)).is_none());
+        let resolution = restored.get_stale(&question(Type::A)).unwrap();
+        assert_eq!(ttls(&resolution.answers), [STALE_TTL]);
+
+        let (restored, count) = through_snapshot(&cache, "expired", 360);
+        assert_eq!(count, 0);
+        assert!(restored.get_stale(&question(Type::A)).is_none());
+    }
+
+    #[test]
+    fn negative_entries() {
+        let soa = Record::SOA {
+            mname: "ns.example.com".parse().unwrap(),
+            rname: "hostmaster.example.com".parse().unwrap(),
+            serial: Serial(1),
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 900,
+        };
+        let resolution = Resolution {
+            rcode: RCode::NXDomain,
+            answers: Vec::new(),
+            authorities: vec![ResourceRecord::Record {
+                name: "example.com".parse().unwrap(),
+                class: Class::IN,
+                ttl: 3600,
+                data: soa,
+            }],
+        };
+        let cache = Cache::new(&config::Cache::default());
+        cache.insert(&question(Type::A), &resolution);
+
+        let (restored, count) = through_snapshot(&cache, "negative", 100);
+        assert_eq!(count, 1);
+        let hit = restored.get(&question(Type::MX)).unwrap();
+        assert_eq!(hit.resolution.rcode, RCode::NXDomain);
+        assert!(hit.resolution.answers.is_empty());
+        let ttl = ttls(&hit.resolution.authorities)[0];
+        assert!((798..=800).contains(&ttl), "{ttl}");
+    }
+}
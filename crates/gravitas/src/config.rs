@@ -0,0 +1,419 @@
+use std::fmt::Display;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use dns::{DomainName, SerialPolicy, base64, tsig};
+use serde::Deserialize;
+
+/// Server configuration, read from a TOML file.
+///
+/// ```toml
+/// listen = "0.0.0.0:5300"
+/// minimal_responses = false
+///
+/// [tcp]
+/// idle_timeout = 10
+/// max_connections = 128
+/// workers = 16
+///
+/// [recursion]
+/// root_hints = ["198.41.0.4"]
+/// timeout = 1500
+///
+/// [forward]
+/// upstreams = ["192.0.2.53:53", "198.51.100.53:53"]
+/// strategy = "lowest-latency"
+/// timeout = 1500
+///
+/// [cache]
+/// max_size = 33554432
+/// min_ttl = 0
+/// max_ttl = 86400
+/// max_negative_ttl = 10800
+/// max_stale = 86400
+/// prefetch = true
+/// snapshot = "cache.snapshot"
+/// snapshot_interval = 300
+///
+/// [[route]]
+/// suffix = "corp.example."
+/// upstreams = ["10.0.0.53:53"]
+///
+/// [[route]]
+/// suffix = "10.in-addr.arpa."
+///
+/// [[key]]
+/// name = "transfer.example.com."
+/// algorithm = "hmac-sha256"
+/// secret = "c2VjcmV0IHNoYXJlZCB3aXRoIHRoZSBwcmltYXJ5"
+///
+/// [[primary]]
+/// name = "example.com."
+/// file = "zones/example.com.zone"
+/// notify = ["192.0.2.2:53"]
+/// key = "transfer.example.com."
+/// serial_policy = "date"
+///
+/// [[secondary]]
+/// name = "example.org."
+/// primaries = ["192.0.2.1:53"]
+/// key = "transfer.example.com."
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default = "default_listen")]
+    pub listen: SocketAddr,
+
+    #[serde(default)]
+    pub primary: Vec<PrimaryZone>,
+
+    #[serde(default)]
+    pub secondary: Vec<SecondaryZone>,
+
+    #[serde(default)]
+    pub tcp: Tcp,
+
+    /// Leaves the addresses of MX, NS and SRV targets out of the additional section,
+    /// keeping responses small.
+    #[serde(default)]
+    pub minimal_responses: bool,
+
+    /// Resolves names outside of the served zones for clients that desire recursion,
+    /// disabled unless the section is present.
+    #[serde(default)]
+    pub recursion: Option<Recursion>,
+
+    /// Forwards queries outside of the served zones to upstream resolvers instead,
+    /// taking precedence over recursion.
+    #[serde(default)]
+    pub forward: Option<Forward>,
+
+    /// Routes for queries below certain names, overriding forwarding and recursion.
+    #[serde(default)]
+    pub route: Vec<Route>,
+
+    #[serde(default)]
+    pub cache: Cache,
+
+    /// TSIG keys that requests may be signed with, and that zones refer to by name.
+    #[serde(default)]
+    pub key: Vec<Key>,
+}
+
+/// Limits of the TCP listener, which shares the address with the UDP one.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Tcp {
+    /// Seconds a connection may stay idle before it is closed, as per
+    /// [RFC 7766 Section 6.2.3](https://www.rfc-editor.org/rfc/rfc7766#section-6.2.3).
+    pub idle_timeout: u64,
+
+    /// Connections accepted at the same time, further clients are turned away.
+    pub max_connections: usize,
+
+    /// Threads answering the requests of all connections.
+    pub workers: usize,
+}
+
+impl Default for Tcp {
+    fn default() -> Self {
+        Self {
+            idle_timeout: 10,
+            max_connections: 128,
+            workers: 16,
+        }
+    }
+}
+
+/// Iterative resolution starting from the root name servers.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Recursion {
+    /// Addresses of the root name servers, the ones published by IANA if empty.
+    pub root_hints: Vec<IpAddr>,
+
+    /// Milliseconds to wait for a name server before asking another one.
+    pub timeout: u64,
+}
+
+impl Default for Recursion {
+    fn default() -> Self {
+        Self {
+            root_hints: Vec::new(),
+            timeout: 1500,
+        }
+    }
+}
+
+/// Forwarding of queries to upstream resolvers.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Forward {
+    pub upstreams: Vec<SocketAddr>,
+
+    #[serde(default)]
+    pub strategy: Strategy,
+
+    /// Milliseconds to wait for an upstream before failing over to the next one.
+    #[serde(default = "default_forward_timeout")]
+    pub timeout: u64,
+}
+
+/// The order upstreams are tried in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Each query starts with the next upstream in turn.
+    RoundRobin,
+
+    /// The upstream with the lowest smoothed round trip time first.
+    #[default]
+    LowestLatency,
+
+    /// Always in the configured order, later upstreams are only fallbacks.
+    StrictOrder,
+}
+
+/// Where queries for names below `suffix` go, the longest matching suffix wins.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Route {
+    pub suffix: String,
+
+    /// Upstreams the queries are forwarded to. Without any they are only answered
+    /// from the served zones and never leave the server.
+    #[serde(default)]
+    pub upstreams: Vec<SocketAddr>,
+
+    #[serde(default)]
+    pub strategy: Strategy,
+
+    #[serde(default = "default_forward_timeout")]
+    pub timeout: u64,
+}
+
+impl Route {
+    /// The forwarding to the upstreams of the route.
+    pub fn forward(&self) -> Forward {
+        Forward {
+            upstreams: self.upstreams.clone(),
+            strategy: self.strategy,
+            timeout: self.timeout,
+        }
+    }
+}
+
+fn default_forward_timeout() -> u64 {
+    1500
+}
+
+/// The cache of resolved answers.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Cache {
+    /// Bytes the cached records may take up, roughly. The least recently used ones
+    /// are evicted beyond it.
+    pub max_size: usize,
+
+    /// Seconds records are cached for at least, even if their TTL is lower.
+    pub min_ttl: u32,
+
+    /// Seconds records are cached for at most, even if their TTL is higher.
+    pub max_ttl: u32,
+
+    /// Seconds the absence of a name or of data is cached for at most.
+    pub max_negative_ttl: u32,
+
+    /// Seconds records are kept past their expiry, to be served while they can't be
+    /// refreshed. Zero disables serving stale records.
+    pub max_stale: u32,
+
+    /// Refreshes records that are asked for often in the background, shortly before
+    /// they expire.
+    pub prefetch: bool,
+
+    /// File the cache is saved to on shutdown and periodically, and restored from on
+    /// startup. The cache is lost on restarts without it.
+    pub snapshot: Option<PathBuf>,
+
+    /// Seconds between saves of the snapshot, zero only saves on shutdown.
+    pub snapshot_interval: u64,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self {
+            max_size: 32 * 1024 * 1024,
+            min_ttl: 0,
+            max_ttl: 86400,
+            max_negative_ttl: 10800,
+            max_stale: 86400,
+            prefetch: true,
+            snapshot: None,
+            snapshot_interval: 300,
+        }
+    }
+}
+
+/// A zone this server is the primary for.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrimaryZone {
+    pub name: String,
+    pub file: PathBuf,
+
+    /// Secondaries that are sent a NOTIFY whenever the zone changes.
+    #[serde(default)]
+    pub notify: Vec<SocketAddr>,
+
+    /// Name of the TSIG key the NOTIFY messages are signed with.
+    #[serde(default)]
+    pub key: Option<String>,
+
+    /// How the serial advances when the file changes without a new one. Without a
+    /// policy such changes are served, but secondaries aren't told about them.
+    ///
+    /// The advanced serial isn't written back to the file, after a restart the serial
+    /// of the file is served again until the next change. Only the `date` and
+    /// `unix-time` policies advance past what secondaries have seen before that.
+    #[serde(default)]
+    pub serial_policy: Option<SerialPolicy>,
+}
+
+/// A zone this server is a secondary for.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SecondaryZone {
+    pub name: String,
+
+    /// Primaries the zone is transferred from, only they may send a NOTIFY for it.
+    pub primaries: Vec<SocketAddr>,
+
+    /// Name of the TSIG key that messages exchanged with the primaries are signed
+    /// with. A NOTIFY without it is refused.
+    #[serde(default)]
+    pub key: Option<String>,
+}
+
+/// A TSIG key shared with other servers, as per
+/// [RFC 8945](https://www.rfc-editor.org/rfc/rfc8945).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Key {
+    pub name: String,
+
+    /// One of `hmac-sha256`, `hmac-sha384` and `hmac-sha512`.
+    #[serde(default = "default_algorithm")]
+    pub algorithm: String,
+
+    /// The shared secret, base64 encoded.
+    pub secret: String,
+}
+
+fn default_algorithm() -> String {
+    "hmac-sha256".to_string()
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    Invalid(String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "couldn't read config: {}", err),
+            Self::Toml(err) => write!(f, "invalid config: {}", err),
+            Self::Invalid(message) => write!(f, "invalid config: {}", message),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        toml::from_str(&content).map_err(ConfigError::Toml)
+    }
+
+    /// The configured TSIG keys, checking that the zones only refer to those.
+    pub fn keyring(&self) -> Result<Vec<tsig::Key>, ConfigError> {
+        let keys = self
+            .key
+            .iter()
+            .map(Key::tsig)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let references = self
+            .primary
+            .iter()
+            .filter_map(|zone| zone.key.as_ref())
+            .chain(self.secondary.iter().filter_map(|zone| zone.key.as_ref()));
+        for name in references {
+            if find_key(&keys, name).is_none() {
+                return Err(ConfigError::Invalid(format!("unknown key {}", name)));
+            }
+        }
+
+        Ok(keys)
+    }
+}
+
+impl Key {
+    fn tsig(&self) -> Result<tsig::Key, ConfigError> {
+        let algorithm =
+            tsig::Algorithm::from_name(&domain_name(&self.algorithm)).ok_or_else(|| {
+                ConfigError::Invalid(format!(
+                    "unsupported algorithm {} of key {}",
+                    self.algorithm, self.name
+                ))
+            })?;
+        let secret = base64::decode(self.secret.trim()).ok_or_else(|| {
+            ConfigError::Invalid(format!("secret of key {} isn't base64", self.name))
+        })?;
+
+        Ok(tsig::Key {
+            name: self.name.clone(),
+            algorithm,
+            secret,
+        })
+    }
+}
+
+/// The key of the keyring called `name`.
+pub fn find_key<'k>(keys: &'k [tsig::Key], name: &str) -> Option<&'k tsig::Key> {
+    keys.iter()
+        .find(|key| domain_name(&key.name) == domain_name(name))
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: default_listen(),
+            primary: Vec::new(),
+            secondary: Vec::new(),
+            tcp: Tcp::default(),
+            minimal_responses: false,
+            recursion: None,
+            forward: None,
+            route: Vec::new(),
+            cache: Cache::default(),
+            key: Vec::new(),
+        }
+    }
+}
+
+fn default_listen() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 5300))
+}
+
+/// The domain name spelled out by a configured zone name, with or without the
+/// trailing root label.
+pub fn domain_name(name: &str) -> DomainName<'_> {
+    name.split('.')
+        .filter(|label| !label.is_empty())
+        .collect::<Vec<_>>()
+        .into()
+}
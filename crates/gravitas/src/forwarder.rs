@@ -0,0 +1,265 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use dns::{Question, RCode};
+use log::{debug, info, warn};
+
+use crate::config::{Forward, Strategy};
+use crate::resolver::{Resolution, Resolve, ResolveError};
+use crate::upstream::{self, Exchange, Rtt};
+
+/// Consecutive failures after which an upstream is considered down.
+const MAX_FAILURES: u32 = 3;
+
+/// How long an upstream that is down is skipped at first, doubled while it keeps
+/// failing.
+const BACKOFF: Duration = Duration::from_secs(5);
+
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Forwards queries to upstream resolvers, failing over to the next one when an
+/// upstream times out or fails.
+pub struct Forwarder {
+    exchange: Arc<dyn Exchange>,
+    upstreams: Vec<Upstream>,
+    strategy: Strategy,
+    timeout: Duration,
+    rtt: Rtt,
+
+    /// The upstream the next query starts with when taking turns.
+    next: AtomicUsize,
+}
+
+struct Upstream {
+    address: SocketAddr,
+    health: Mutex<Health>,
+}
+
+#[derive(Default)]
+struct Health {
+    failures: u32,
+
+    /// Until when the upstream is skipped, while it is down.
+    down_until: Option<Instant>,
+}
+
+impl Forwarder {
+    pub fn new(config: &Forward, exchange: Arc<dyn Exchange>) -> Self {
+        Self {
+            exchange,
+            upstreams: config
+                .upstreams
+                .iter()
+                .map(|&address| Upstream {
+                    address,
+                    health: Mutex::default(),
+                })
+                .collect(),
+            strategy: config.strategy,
+            timeout: Duration::from_millis(config.timeout),
+            rtt: Rtt::default(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// The upstreams in the order they are tried for the next query. The ones that
+    /// are down are skipped until their backoff expires, unless all of them are down,
+    /// then only the one that is due first is probed.
+    fn order(&self) -> Vec<&Upstream> {
+        let mut order: Vec<&Upstream> = self.upstreams.iter().collect();
+
+        match self.strategy {
+            Strategy::RoundRobin if !order.is_empty() => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed) % order.len();
+                order.rotate_left(start);
+            }
+            Strategy::LowestLatency => {
+                order.sort_by_cached_key(|upstream| self.rtt.estimate(upstream.address.ip()))
+            }
+            _ => {}
+        }
+
+        let now = Instant::now();
+        let (healthy, down): (Vec<_>, Vec<_>) = order
+            .into_iter()
+            .partition(|upstream| upstream.down_until(now).is_none());
+        if !healthy.is_empty() {
+            return healthy;
+        }
+
+        down.into_iter()
+            .min_by_key(|upstream| upstream.down_until(now))
+            .into_iter()
+            .collect()
+    }
+}
+
+impl Resolve for Forwarder {
+    fn resolve(&self, question: &Question) -> Result<Resolution, ResolveError> {
+        let question = question.clone().into_owned();
+
+        for upstream in self.order() {
+            // a fresh id for every attempt, so late responses to earlier ones don't match
+            let query = upstream::query(question.clone(), true);
+
+            let response = match upstream::ask(
+                self.exchange.as_ref(),
+                &query,
+                upstream.address,
+                self.timeout,
+                &self.rtt,
+            ) {
+                Ok(response) => response,
+                Err(err) => {
+                    debug!(
+                        "upstream {} failed for {}: {}",
+                        upstream.address, question.name, err
+                    );
+                    upstream.failure();
+                    continue;
+                }
+            };
+
+            match response.header.rcode() {
+                rcode @ (RCode::NoError | RCode::NXDomain) => {
+                    upstream.success();
+                    return Ok(Resolution {
+                        rcode,
+                        answers: response.answers,
+                        authorities: response.authorities,
+                    });
+                }
+                rcode => {
+                    debug!(
+                        "upstream {} answered {} with {:?}",
+                        upstream.address, question.name, rcode
+                    );
+                    upstream.failure();
+                }
+            }
+        }
+
+        Err(ResolveError::Upstreams)
+    }
+}
+
+impl Upstream {
+    /// Until when the upstream is skipped, if it is down and its backoff hasn't
+    /// expired by `now`.
+    fn down_until(&self, now: Instant) -> Option<Instant> {
+        self.health
+            .lock()
+            .unwrap()
+            .down_until
+            .filter(|&until| now < until)
+    }
+
+    fn success(&self) {
+        let mut health = self.health.lock().unwrap();
+        if health.failures >= MAX_FAILURES {
+            info!("upstream {} is back up", self.address);
+        }
+        *health = Health::default();
+    }
+
+    fn failure(&self) {
+        let mut health = self.health.lock().unwrap();
+        health.failures += 1;
+
+        if health.failures >= MAX_FAILURES {
+            let backoff = BACKOFF
+                .saturating_mul(1 << (health.failures - MAX_FAILURES).min(16))
+                .min(MAX_BACKOFF);
+            if health.failures == MAX_FAILURES {
+                warn!("upstream {} is down", self.address);
+            }
+            health.down_until = Some(Instant::now() + backoff);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::handler::Transport;
+
+    /// Upstreams that never answer.
+    struct Unreachable;
+
+    impl Exchange for Unreachable {
+        fn exchange(
+            &self,
+            _query: &[u8],
+            _server: SocketAddr,
+            _transport: Transport,
+            _timeout: Duration,
+        ) -> io::Result<Vec<u8>> {
+            Err(io::ErrorKind::TimedOut.into())
+        }
+    }
+
+    fn forwarder(upstreams: &[&str]) -> Forwarder {
+        let config = Forward {
+            upstreams: upstreams.iter().map(|u| u.parse().unwrap()).collect(),
+            strategy: Strategy::StrictOrder,
+            timeout: 100,
+        };
+        Forwarder::new(&config, Arc::new(Unreachable))
+    }
+
+    fn order(forwarder: &Forwarder) -> Vec<String> {
+        forwarder
+            .order()
+            .iter()
+            .map(|upstream| upstream.address.to_string())
+            .collect()
+    }
+
+    fn fail(upstream: &Upstream) {
+        for _ in 0..MAX_FAILURES {
+            upstream.failure();
+        }
+    }
+
+    #[test]
+    fn down_upstreams_are_skipped() {
+        let forwarder = forwarder(&["192.0.2.1:53", "192.0.2.2:53", "192.0.2.3:53"]);
+        assert_eq!(
+            order(&forwarder),
+            ["192.0.2.1:53", "192.0.2.2:53", "192.0.2.3:53"]
+        );
+
+        fail(&forwarder.upstreams[0]);
+        assert_eq!(order(&forwarder), ["192.0.2.2:53", "192.0.2.3:53"]);
+
+        forwarder.upstreams[0].success();
+        assert_eq!(
+            order(&forwarder),
+            ["192.0.2.1:53", "192.0.2.2:53", "192.0.2.3:53"]
+        );
+    }
+
+    #[test]
+    fn down_upstream_is_probed_when_its_backoff_expires() {
+        let forwarder = forwarder(&["192.0.2.1:53", "192.0.2.2:53"]);
+        fail(&forwarder.upstreams[0]);
+        forwarder.upstreams[0].health.lock().unwrap().down_until = Some(Instant::now());
+
+        assert_eq!(order(&forwarder), ["192.0.2.1:53", "192.0.2.2:53"]);
+    }
+
+    #[test]
+    fn one_upstream_is_probed_when_all_are_down() {
+        let forwarder = forwarder(&["192.0.2.1:53", "192.0.2.2:53"]);
+        fail(&forwarder.upstreams[1]);
+        fail(&forwarder.upstreams[0]);
+        forwarder.upstreams[0].failure();
+
+        // the one whose backoff is shorter is due first
+        assert_eq!(order(&forwarder), ["192.0.2.2:53"]);
+    }
+}
@@ -0,0 +1,725 @@
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use dns::{
+    DomainName, Flags, Header, OpCode, Packet, QR, Question, RCode, Record, ResourceRecord,
+    proto::{Parse, ParseMode, Parser, Serialize, Serializer},
+    sig0,
+    tsig::{self, TsigError},
+};
+use log::{debug, error, warn};
+
+/// Error of a [`Handler`] that couldn't produce a response, answered with SERVFAIL.
+pub type HandlerError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Produces responses to requests, independent of the transport they came in over.
+pub trait Handler: Send + Sync {
+    /// Answers `request`, received from `source`. `signer` is who signed the request,
+    /// the signature has been verified already.
+    ///
+    /// The id, the QR bit and the section counts of the response are filled in by the
+    /// caller, as well as the signature.
+    fn handle<'a>(
+        &self,
+        request: Packet<'a>,
+        source: SocketAddr,
+        signer: Option<&Signer>,
+    ) -> Result<Packet<'a>, HandlerError>;
+
+    /// TSIG keys that requests may be signed with.
+    fn keyring(&self) -> &[tsig::Key] {
+        &[]
+    }
+
+    /// The KEY records of `name`, that requests signed with SIG(0) by `name` are
+    /// verified against.
+    fn keys(&self, _name: &DomainName) -> Vec<ResourceRecord<'static>> {
+        Vec::new()
+    }
+
+    /// Whether answering `request` may take long, as it has to be resolved first.
+    /// Transports hand such requests off, so that they don't hold up the others.
+    fn is_slow(&self, _request: &Packet) -> bool {
+        false
+    }
+}
+
+/// Whether the request in `buf` may take long to answer, see [`Handler::is_slow`].
+pub fn is_slow(handler: &dyn Handler, buf: &[u8]) -> bool {
+    Packet::parse(&mut Parser::new(buf))
+        .is_ok_and(|request| !request.header.is_response() && handler.is_slow(&request))
+}
+
+/// Who signed a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Signer {
+    /// The name of the TSIG key.
+    Tsig(String),
+
+    /// The owner of the KEY record the SIG(0) signature was made with.
+    Sig0(DomainName<'static>),
+}
+
+/// Largest UDP response to clients that don't advertise a size with EDNS, as per
+/// [RFC 1035 Section 4.2.1](https://www.rfc-editor.org/rfc/rfc1035#section-4.2.1).
+const UDP_SIZE: usize = 512;
+
+/// Largest UDP response, whatever size the client advertises.
+const MAX_UDP_SIZE: usize = 4096;
+
+/// Transport a request came in over, which limits the size of the response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+
+    /// Messages are prefixed with their length as per
+    /// [RFC 1035 Section 4.2.2](https://www.rfc-editor.org/rfc/rfc1035#section-4.2.2),
+    /// so responses may take up to 65535 bytes.
+    Tcp,
+}
+
+/// Handles the request in `buf` received from `source`, writing the response into
+/// `response`. Returns the length of the response, or `None` if there is nothing to
+/// send back.
+///
+/// Requests that can't be parsed are answered with FORMERR and requests the handler
+/// fails on with SERVFAIL. Responses that don't fit the size the client accepts lose
+/// their additional data first, otherwise they are truncated to the question with the
+/// TC bit set.
+///
+/// Responses to requests with EDNS carry an OPT record advertising the size the
+/// server accepts, as per
+/// [RFC 6891 Section 7](https://www.rfc-editor.org/rfc/rfc6891#section-7). Requests
+/// with an EDNS version other than 0 are answered with BADVERS.
+///
+/// Signed requests are verified before they are handled. Responses to requests signed
+/// with TSIG are signed with the same key, as per
+/// [RFC 8945 Section 5](https://www.rfc-editor.org/rfc/rfc8945#section-5), requests
+/// signed with SIG(0) are verified against the KEY records of the signer as per
+/// [RFC 2931 Section 3.2](https://www.rfc-editor.org/rfc/rfc2931#section-3.2).
+pub fn respond(
+    handler: &dyn Handler,
+    buf: &[u8],
+    source: SocketAddr,
+    transport: Transport,
+    response: &mut [u8],
+) -> Option<usize> {
+    let mut parser = Parser::with_mode(buf, ParseMode::Strict).preserve_compression(true);
+
+    let mut request = match Packet::parse(&mut parser) {
+        Ok(request) => request,
+        Err(err) => {
+            warn!("failed to parse request from {}: {}", source, err);
+
+            // only requests with an intact header can be answered
+            let header = Header::parse(&mut Parser::new(buf)).ok()?;
+            if header.is_response() {
+                return None;
+            }
+
+            return serialize(
+                error(header.id, header.opcode(), RCode::FormatErr),
+                response,
+            );
+        }
+    };
+
+    debug!("{:?}", request);
+
+    if request.header.is_response() {
+        debug!("dropping response from {}", source);
+        return None;
+    }
+
+    let id = request.header.id;
+    let flags = request.header.flags;
+    let questions = request.questions.clone();
+    let version = edns_version(&request);
+    let edns = version.is_some();
+    let mut limit = match transport {
+        Transport::Udp => udp_size(&request),
+        Transport::Tcp => usize::from(u16::MAX),
+    }
+    .min(response.len());
+
+    let verified = match request.additionals.iter().any(is_tsig) {
+        false => None,
+        true => match tsig::verify(buf, handler.keyring(), None, unix_time()) {
+            Ok(verified) => Some(verified),
+            Err(err) => {
+                warn!("signature of request {} from {}: {}", id, source, err);
+                return reject(handler, buf, &request, err, response);
+            }
+        },
+    };
+
+    let signer = match (&verified, sig0_signer(&request)) {
+        (Some(verified), _) => Some(Signer::Tsig(verified.key.name.clone())),
+        (None, Some(name)) => {
+            let keys = handler.keys(&name);
+            match sig0::verify(buf, &keys, unix_time() as u32) {
+                Ok(_) => Some(Signer::Sig0(name)),
+                Err(err) => {
+                    warn!("sig(0) of request {} from {}: {}", id, source, err);
+
+                    let mut packet = error(id, flags.opcode, err.rcode());
+                    packet.questions = questions;
+                    if edns {
+                        packet.additionals.push(opt(RCode::NoError));
+                    }
+                    return serialize(packet, response);
+                }
+            }
+        }
+        (None, None) => None,
+    };
+    request
+        .additionals
+        .retain(|record| !is_tsig(record) && !is_sig0(record));
+
+    // room for the signature of the response
+    if let Some(verified) = &verified {
+        limit = limit.saturating_sub(tsig_size(verified.key));
+    }
+
+    let mut packet = match version {
+        Some(version @ 1..) => {
+            debug!("unsupported edns version {} from {}", version, source);
+
+            let mut packet = error(id, flags.opcode, RCode::BADVERS);
+            packet.questions = questions.clone();
+            packet
+        }
+        _ => match handler.handle(request, source, signer.as_ref()) {
+            Ok(packet) => packet,
+            Err(err) => {
+                error!("failed to handle request {} from {}: {}", id, source, err);
+
+                let mut packet = error(id, flags.opcode, RCode::ServFail);
+                packet.header.set_recursion_desired(flags.rd);
+                packet.questions = questions.clone();
+                packet
+            }
+        },
+    };
+
+    // the upper bits of an extended rcode go into the OPT record, as per RFC 6891
+    // Section 6.1.3
+    packet.additionals.retain(|record| !is_opt(record));
+    if edns {
+        let rcode = packet.header.rcode();
+        packet.header.set_rcode(RCode::extended(rcode.low(), 0));
+        packet.additionals.push(opt(rcode));
+    }
+
+    let len = fit(packet, id, flags, questions, source, limit, response)?;
+
+    let Some(verified) = verified else {
+        return Some(len);
+    };
+
+    let mut message = response[..len].to_vec();
+    if let Err(err) = tsig::sign(
+        &mut message,
+        verified.key,
+        Some(&verified.mac),
+        unix_time(),
+        tsig::DEFAULT_FUDGE,
+    ) {
+        error!("failed to sign response to {}: {}", source, err);
+        return None;
+    }
+
+    copy(&message, response)
+}
+
+/// Serializes `packet` into `response` within `limit` bytes, dropping optional data or
+/// truncating it if it doesn't fit.
+fn fit(
+    mut packet: Packet,
+    id: u16,
+    flags: Flags,
+    questions: Vec<Question>,
+    source: SocketAddr,
+    limit: usize,
+    response: &mut [u8],
+) -> Option<usize> {
+    packet.header.id = id;
+    packet.header.set_qr(QR::Response);
+
+    // additional data of an answer is optional and dropped before truncating, as per
+    // RFC 2181 Section 9, while the glue of a referral is needed to follow it
+    while !packet.answers.is_empty() && optional_additionals(&packet) > 0 {
+        match serialize(packet.clone(), response) {
+            Some(len) if len <= limit => return Some(len),
+            _ => {
+                let last = packet
+                    .additionals
+                    .iter()
+                    .rposition(|record| !is_opt(record));
+                if let Some(last) = last {
+                    packet.additionals.remove(last);
+                }
+            }
+        }
+    }
+
+    // a truncated response keeps the OPT record, as per RFC 6891 Section 7
+    let opt: Vec<_> = packet
+        .additionals
+        .iter()
+        .filter(|record| is_opt(record))
+        .cloned()
+        .collect();
+
+    // as is the rcode, whose upper bits are in the OPT record
+    let rcode = packet.header.rcode();
+
+    match serialize(packet, response) {
+        Some(len) if len <= limit => Some(len),
+        _ => {
+            debug!("response to {} exceeds {} bytes, truncating", source, limit);
+
+            let mut packet = error(id, flags.opcode, rcode);
+            packet.header.set_truncated(true);
+            packet.header.set_recursion_desired(flags.rd);
+            packet.questions = questions;
+            packet.additionals = opt;
+            serialize(packet, response)
+        }
+    }
+}
+
+/// Answers a request whose signature failed verification, with a signed error if the
+/// key is known as per
+/// [RFC 8945 Section 5.2](https://www.rfc-editor.org/rfc/rfc8945#section-5.2).
+fn reject(
+    handler: &dyn Handler,
+    buf: &[u8],
+    request: &Packet,
+    err: TsigError,
+    response: &mut [u8],
+) -> Option<usize> {
+    // a TSIG record that isn't the last record is malformed
+    let rcode = match err {
+        TsigError::Unsigned => RCode::FormatErr,
+        _ => err.rcode(),
+    };
+
+    let mut packet = error(request.header.id, request.header.opcode(), rcode);
+    packet.questions = request.questions.clone();
+    let len = serialize(packet, response)?;
+    if rcode == RCode::FormatErr {
+        return Some(len);
+    }
+
+    let mut message = response[..len].to_vec();
+    tsig::sign_error(&mut message, buf, handler.keyring(), &err, unix_time()).ok()?;
+    copy(&message, response)
+}
+
+fn is_opt(record: &ResourceRecord) -> bool {
+    matches!(record, ResourceRecord::OPTRecord { .. })
+}
+
+/// The OPT record of responses, advertising the largest UDP payload the server
+/// accepts and carrying the upper bits of `rcode`. DNSSEC isn't supported, so the DO
+/// bit is never set.
+fn opt(rcode: RCode) -> ResourceRecord<'static> {
+    ResourceRecord::OPTRecord {
+        size: MAX_UDP_SIZE as u16,
+        flags: u32::from(rcode.high()) << 24,
+        options: Vec::new(),
+    }
+}
+
+/// The EDNS version of the request, if it has an OPT record, as per
+/// [RFC 6891 Section 6.1.3](https://www.rfc-editor.org/rfc/rfc6891#section-6.1.3).
+fn edns_version(request: &Packet) -> Option<u8> {
+    request.additionals.iter().find_map(|record| match record {
+        ResourceRecord::OPTRecord { flags, .. } => Some((flags >> 16) as u8),
+        _ => None,
+    })
+}
+
+fn is_tsig(record: &ResourceRecord) -> bool {
+    matches!(
+        record,
+        ResourceRecord::Record {
+            data: Record::TSIG { .. },
+            ..
+        }
+    )
+}
+
+/// SIG(0) records are owned by the root, as per
+/// [RFC 2931 Section 3](https://www.rfc-editor.org/rfc/rfc2931#section-3).
+fn is_sig0(record: &ResourceRecord) -> bool {
+    matches!(
+        record,
+        ResourceRecord::Record {
+            name,
+            data: Record::SIG { .. },
+            ..
+        } if name.labels.is_empty()
+    )
+}
+
+/// The signer of the SIG(0) record of `request`, if it has one.
+fn sig0_signer(request: &Packet) -> Option<DomainName<'static>> {
+    match request.additionals.last()? {
+        record @ ResourceRecord::Record {
+            data: Record::SIG { signer, .. },
+            ..
+        } if is_sig0(record) => Some(signer.clone().into_owned()),
+        _ => None,
+    }
+}
+
+/// Bytes the TSIG record signing a response with `key` takes up.
+fn tsig_size(key: &tsig::Key) -> usize {
+    let name = |name: &str| name.trim_end_matches('.').len() + 2;
+
+    // type, class, ttl and rdata length
+    let fields = size_of::<u16>() + size_of::<u16>() + size_of::<u32>() + size_of::<u16>();
+
+    // time signed, fudge, mac size, original id, error and other length
+    let rdata = size_of::<u16>() + size_of::<u32>() + 5 * size_of::<u16>();
+
+    name(&key.name) + fields + name(key.algorithm.name()) + rdata + key.algorithm.output_len()
+}
+
+/// Copies a message built outside of `response` into it.
+fn copy(message: &[u8], response: &mut [u8]) -> Option<usize> {
+    response.get_mut(..message.len())?.copy_from_slice(message);
+    Some(message.len())
+}
+
+/// Seconds since the Unix epoch, the time TSIG signatures are made at and serials
+/// advance to.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+/// The payload size the client accepts over UDP, as per
+/// [RFC 6891 Section 6.2.5](https://www.rfc-editor.org/rfc/rfc6891#section-6.2.5).
+fn udp_size(request: &Packet) -> usize {
+    request
+        .additionals
+        .iter()
+        .find_map(|record| match record {
+            ResourceRecord::OPTRecord { size, .. } => Some(usize::from(*size)),
+            _ => None,
+        })
+        .map_or(UDP_SIZE, |size| size.clamp(UDP_SIZE, MAX_UDP_SIZE))
+}
+
+/// Additional records that may be left out to make the response fit.
+fn optional_additionals(packet: &Packet) -> usize {
+    packet
+        .additionals
+        .iter()
+        .filter(|record| !is_opt(record))
+        .count()
+}
+
+/// Fills in the section counts and serializes `packet` into `buf`.
+fn serialize(mut packet: Packet, buf: &mut [u8]) -> Option<usize> {
+    packet.header.qdcount = packet.questions.len() as u16;
+    packet.header.ancount = packet.answers.len() as u16;
+    packet.header.nscount = packet.authorities.len() as u16;
+    packet.header.arcount = packet.additionals.len() as u16;
+
+    match packet.serialize(&mut Serializer::new(buf)) {
+        Ok(len) => Some(len),
+        Err(err) => {
+            error!("failed to serialize response {:?}", err);
+            None
+        }
+    }
+}
+
+/// A response with just `rcode` for a request that couldn't be interpreted.
+pub fn error(id: u16, opcode: OpCode, rcode: RCode) -> Packet<'static> {
+    let mut header = Header {
+        id,
+        ..Default::default()
+    };
+    header.set_qr(QR::Response);
+    header.set_opcode(opcode);
+    header.set_rcode(rcode);
+
+    Packet {
+        header,
+        questions: Vec::new(),
+        answers: Vec::new(),
+        authorities: Vec::new(),
+        additionals: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    use dns::{Class, Type};
+
+    use super::*;
+
+    const SOURCE: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5353));
+
+    /// Answers with `answers` and `additionals` addresses of the question, or fails.
+    struct Addresses {
+        answers: usize,
+        additionals: usize,
+        fail: bool,
+        keys: Vec<tsig::Key>,
+    }
+
+    impl Addresses {
+        fn new(answers: usize, additionals: usize) -> Self {
+            Self {
+                answers,
+                additionals,
+                fail: false,
+                keys: Vec::new(),
+            }
+        }
+    }
+
+    impl Handler for Addresses {
+        fn handle<'a>(
+            &self,
+            request: Packet<'a>,
+            _source: SocketAddr,
+            _signer: Option<&Signer>,
+        ) -> Result<Packet<'a>, HandlerError> {
+            if self.fail {
+                return Err("no answer".into());
+            }
+
+            let address = |i: usize| ResourceRecord::Record {
+                name: request.questions[0].name.clone(),
+                class: Class::IN,
+                ttl: 300,
+                data: Record::A {
+                    address: [192, 0, 2, i as u8],
+                },
+            };
+
+            let mut response = error(0, OpCode::Query, RCode::NoError);
+            response.questions = request.questions.clone();
+            response.answers = (0..self.answers).map(address).collect();
+            response.additionals = (0..self.additionals).map(address).collect();
+            // replaced by the OPT record of the server
+            response.additionals.push(opt(RCode::NoError));
+            Ok(response)
+        }
+
+        fn keyring(&self) -> &[tsig::Key] {
+            &self.keys
+        }
+    }
+
+    /// A query for the addresses of www.example.com, with an OPT record of `version`
+    /// advertising `size` if given.
+    fn query(edns: Option<(u16, u8)>) -> Vec<u8> {
+        let mut packet = error(0x1234, OpCode::Query, RCode::NoError);
+        packet.header.set_qr(QR::Query);
+        packet.header.set_recursion_desired(true);
+        packet.questions.push(Question {
+            name: "www.example.com".parse().unwrap(),
+            r#type: Type::A,
+            class: Class::IN,
+        });
+        if let Some((size, version)) = edns {
+            packet.additionals.push(ResourceRecord::OPTRecord {
+                size,
+                flags: u32::from(version) << 16,
+                options: Vec::new(),
+            });
+        }
+
+        let mut buf = vec![0; 512];
+        let len = serialize(packet, &mut buf).unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    fn ask(handler: &dyn Handler, request: &[u8], transport: Transport) -> Option<Vec<u8>> {
+        let mut response = vec![0; usize::from(u16::MAX)];
+        let len = respond(handler, request, SOURCE, transport, &mut response)?;
+        response.truncate(len);
+        Some(response)
+    }
+
+    fn parse(response: &[u8]) -> Packet<'static> {
+        Packet::parse(&mut Parser::new(response))
+            .unwrap()
+            .into_owned()
+    }
+
+    fn opts(response: &Packet) -> Vec<u32> {
+        response
+            .additionals
+            .iter()
+            .filter_map(|record| match record {
+                ResourceRecord::OPTRecord { size, flags, .. } => {
+                    assert_eq!(usize::from(*size), MAX_UDP_SIZE);
+                    Some(*flags)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn malformed_requests_are_answered_with_formerr() {
+        let handler = Addresses::new(1, 0);
+
+        // a second question that isn't there
+        let mut request = query(None);
+        request[5] = 2;
+        let response = parse(&ask(&handler, &request, Transport::Udp).unwrap());
+        assert_eq!(response.header.id, 0x1234);
+        assert!(response.header.is_response());
+        assert_eq!(response.header.rcode(), RCode::FormatErr);
+
+        // trailing bytes are malformed in strict mode
+        let mut request = query(None);
+        request.push(0);
+        let response = parse(&ask(&handler, &request, Transport::Udp).unwrap());
+        assert_eq!(response.header.rcode(), RCode::FormatErr);
+
+        // nothing to answer without a header, or to a response
+        assert!(ask(&handler, &request[..11], Transport::Udp).is_none());
+        request[2] |= 0x80;
+        assert!(ask(&handler, &request, Transport::Udp).is_none());
+    }
+
+    #[test]
+    fn failed_requests_are_answered_with_servfail() {
+        let handler = Addresses {
+            fail: true,
+            ..Addresses::new(1, 0)
+        };
+
+        let response = parse(&ask(&handler, &query(None), Transport::Udp).unwrap());
+        assert_eq!(response.header.id, 0x1234);
+        assert_eq!(response.header.rcode(), RCode::ServFail);
+        assert!(response.header.recursion_desired());
+        assert_eq!(response.questions.len(), 1);
+        assert!(response.answers.is_empty());
+    }
+
+    #[test]
+    fn edns_requests_get_an_opt_record() {
+        let handler = Addresses::new(1, 1);
+
+        let response = parse(&ask(&handler, &query(None), Transport::Udp).unwrap());
+        assert_eq!(response.header.rcode(), RCode::NoError);
+        assert!(opts(&response).is_empty());
+
+        let response = parse(&ask(&handler, &query(Some((1232, 0))), Transport::Udp).unwrap());
+        assert_eq!(opts(&response), [0]);
+        assert_eq!(response.additionals.len(), 2);
+    }
+
+    #[test]
+    fn other_edns_versions_are_answered_with_badvers() {
+        // the handler isn't asked, it would fail
+        let handler = Addresses {
+            fail: true,
+            ..Addresses::new(1, 0)
+        };
+
+        let response = parse(&ask(&handler, &query(Some((1232, 1))), Transport::Udp).unwrap());
+        assert_eq!(response.header.id, 0x1234);
+        assert_eq!(response.questions.len(), 1);
+
+        // the upper bits of BADVERS are in the OPT record, which has version 0
+        let [flags] = opts(&response)[..] else {
+            panic!("not a single opt record");
+        };
+        assert_eq!(flags, 1 << 24);
+        // BADVERS shares its value with BADSIG, which it is read back as
+        let rcode = RCode::extended(response.header.rcode().low(), (flags >> 24) as u8);
+        assert_eq!(u16::from(rcode), u16::from(RCode::BADVERS));
+    }
+
+    #[test]
+    fn oversized_responses_lose_additionals_first() {
+        // 30 addresses don't fit into 512 bytes
+        let handler = Addresses::new(5, 30);
+
+        let response = ask(&handler, &query(None), Transport::Udp).unwrap();
+        assert!(response.len() <= UDP_SIZE);
+        let response = parse(&response);
+        assert!(!response.header.truncated());
+        assert_eq!(response.answers.len(), 5);
+        assert!(!response.additionals.is_empty() && response.additionals.len() < 30);
+
+        // while TCP takes them all
+        let response = parse(&ask(&handler, &query(None), Transport::Tcp).unwrap());
+        assert_eq!(response.additionals.len(), 30);
+    }
+
+    #[test]
+    fn oversized_answers_are_truncated() {
+        let handler = Addresses::new(30, 1);
+
+        let response = ask(&handler, &query(None), Transport::Udp).unwrap();
+        assert!(response.len() <= UDP_SIZE);
+        let response = parse(&response);
+        assert!(response.header.truncated());
+        assert!(response.header.recursion_desired());
+        assert_eq!(response.questions.len(), 1);
+        assert!(response.answers.is_empty() && response.additionals.is_empty());
+
+        // within the size advertised with EDNS they fit
+        let request = query(Some((1232, 0)));
+        let response = parse(&ask(&handler, &request, Transport::Udp).unwrap());
+        assert!(!response.header.truncated());
+        assert_eq!(response.answers.len(), 30);
+
+        // beyond it the truncated response keeps the OPT record
+        let handler = Addresses::new(60, 0);
+        let response = ask(&handler, &request, Transport::Udp).unwrap();
+        assert!(response.len() <= 1232);
+        let response = parse(&response);
+        assert!(response.header.truncated());
+        assert_eq!(opts(&response), [0]);
+    }
+
+    #[test]
+    fn signed_responses_fit_with_their_signature() {
+        let key = tsig::Key {
+            name: "key.example.com.".into(),
+            algorithm: tsig::Algorithm::HmacSha256,
+            secret: b"a secret shared with the client".to_vec(),
+        };
+
+        let mut truncated = false;
+        for answers in 10..20 {
+            let handler = Addresses {
+                keys: vec![key.clone()],
+                ..Addresses::new(answers, 0)
+            };
+
+            let mut request = query(None);
+            let mac = tsig::sign(&mut request, &key, None, unix_time(), 300).unwrap();
+            let response = ask(&handler, &request, Transport::Udp).unwrap();
+            assert!(response.len() <= UDP_SIZE, "{} answers", answers);
+
+            let verified = tsig::verify(&response, handler.keyring(), Some(&mac), unix_time());
+            assert!(verified.is_ok(), "{} answers", answers);
+            truncated |= parse(&response).header.truncated();
+        }
+
+        // some response would fit without the signature, but not with it
+        assert!(truncated);
+    }
+}
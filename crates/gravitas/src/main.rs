@@ -1,22 +1,135 @@
-use std::net::UdpSocket;
-use std::time::Instant;
+mod authority;
+mod cache;
+mod config;
+mod forwarder;
+mod handler;
+mod notify;
+mod resolver;
+mod router;
+mod server;
+mod tcp;
+mod transfer;
+mod upstream;
+mod zone;
+
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError, sync_channel};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use dns::{
-    Packet,
-    proto::{Parse, Parser, Serialize, Serializer},
-};
 use log::{debug, error, info, warn};
 
-const LISTEN_ADDR: &str = "0.0.0.0:5300";
+use crate::authority::Authority;
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::handler::Transport;
+use crate::notify::Secondaries;
+use crate::router::Router;
+use crate::server::Server;
+use crate::upstream::Network;
 
-fn main() {
+/// Threads receiving UDP requests and answering the ones that don't need resolving.
+const UDP_WORKERS: usize = 16;
+
+/// Threads answering UDP requests that need resolving, so that these don't hold up
+/// the ones answered from the zones or the cache.
+const RESOLUTION_WORKERS: usize = 64;
+
+/// UDP requests waiting for a resolution worker, further ones are dropped.
+const RESOLUTION_QUEUE: usize = 1024;
+
+/// A request that needs resolving and the client it came from.
+type Job = (Vec<u8>, SocketAddr);
+
+fn main() -> ExitCode {
     env_logger::init();
 
-    let socket = UdpSocket::bind(LISTEN_ADDR).expect("couldn't bind to address");
+    let config = match std::env::args().nth(1) {
+        Some(path) => match Config::load(Path::new(&path)) {
+            Ok(config) => config,
+            Err(err) => {
+                error!("{}", err);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Config::default(),
+    };
+
+    let keys: Arc<[_]> = match config.keyring() {
+        Ok(keys) => keys.into(),
+        Err(err) => {
+            error!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let socket = UdpSocket::bind(config.listen).expect("couldn't bind to address");
 
-    let mut buf = [0; 4096];
+    let authority = Authority::load(&config.primary);
+    let router = Router::new(&config, Arc::new(Network));
+    let cache = Arc::new(Cache::new(&config.cache));
+    if router.resolves() {
+        cache::report(Arc::clone(&cache));
+    }
+    if let Some(path) = config.cache.snapshot {
+        match cache.restore(&path) {
+            Ok(count) => info!("restored {} cache entries from {}", count, path.display()),
+            Err(err) => warn!("couldn't restore cache from {}: {}", path.display(), err),
+        }
+        cache::persist(
+            Arc::clone(&cache),
+            path,
+            Duration::from_secs(config.cache.snapshot_interval),
+        );
+    }
+
+    let secondaries = Secondaries::new(config.secondary, Arc::clone(&keys), Arc::clone(&authority));
+    secondaries.refresh();
+
+    let server = Arc::new(Server {
+        authority: Arc::clone(&authority),
+        router,
+        cache,
+        minimal_responses: config.minimal_responses,
+        secondaries,
+        keys: Arc::clone(&keys),
+    });
+    notify::watch(config.primary, authority, keys);
+
+    if let Err(err) = tcp::listen(config.listen, server.clone(), config.tcp) {
+        error!("couldn't listen on {} over tcp: {}", config.listen, err);
+        return ExitCode::FAILURE;
+    }
+
+    info!("listening on {}", config.listen);
 
-    info!("listening on {}", LISTEN_ADDR);
+    let (resolutions, queue) = sync_channel::<Job>(RESOLUTION_QUEUE);
+    let queue = Arc::new(Mutex::new(queue));
+    for _ in 0..RESOLUTION_WORKERS {
+        let socket = socket.try_clone().expect("couldn't clone socket");
+        let server = Arc::clone(&server);
+        let queue = Arc::clone(&queue);
+        thread::spawn(move || resolve_udp(&socket, &server, &queue));
+    }
+
+    for _ in 1..UDP_WORKERS {
+        let socket = socket.try_clone().expect("couldn't clone socket");
+        let server = Arc::clone(&server);
+        let resolutions = resolutions.clone();
+        thread::spawn(move || serve_udp(&socket, &server, &resolutions));
+    }
+
+    serve_udp(&socket, &server, &resolutions)
+}
+
+/// Answers requests received on `socket` one after the other, handing the ones that
+/// need resolving to the resolution workers.
+fn serve_udp(socket: &UdpSocket, server: &Server, resolutions: &SyncSender<Job>) -> ! {
+    let mut buf = [0; 4096];
+    let mut response = [0; 4096];
 
     loop {
         let (len, addr) = match socket.recv_from(&mut buf) {
@@ -29,41 +142,52 @@ fn main() {
 
         debug!("received {} bytes from {}", len, addr.ip());
 
-        let start = Instant::now();
-
-        let mut parser = Parser::new(&buf[..len]);
-
-        let packet = match Packet::parse(&mut parser) {
-            Err(err) => {
-                error!("failed to parse packet {:?}", err);
-                continue;
+        if handler::is_slow(server, &buf[..len]) {
+            if let Err(TrySendError::Full(_)) = resolutions.try_send((buf[..len].to_vec(), addr)) {
+                warn!(
+                    "too many resolutions pending, dropping request from {}",
+                    addr
+                );
             }
-            Ok(packet) => packet,
-        };
-
-        if parser.remaining() > 0 {
-            warn!("{} bytes left in buffer", parser.remaining());
+            continue;
         }
 
-        debug!("packet parsed in {:?}", start.elapsed());
-
-        debug!("{:?}", packet);
+        answer_udp(socket, server, &buf[..len], addr, &mut response);
+    }
+}
 
-        let mut serialize_buf = [0; 4096];
+/// Answers requests that need resolving, taken from `queue`.
+fn resolve_udp(socket: &UdpSocket, server: &Server, queue: &Mutex<Receiver<Job>>) {
+    let mut response = [0; 4096];
 
-        let serialize_len = match packet.serialize(&mut Serializer::new(&mut serialize_buf)) {
-            Err(err) => {
-                error!("failed to serialize packet {:?}", err);
-                continue;
-            }
-            Ok(len) => len,
+    loop {
+        let (request, addr) = match queue.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
         };
 
-        if buf[..len] != serialize_buf[..serialize_len] {
-            error!("original:   {:?}", &buf[..len]);
-            error!("serialized: {:?}", &serialize_buf[..serialize_len]);
-        } else {
-            debug!("serialization successful: buffers match");
-        }
+        answer_udp(socket, server, &request, addr, &mut response);
+    }
+}
+
+/// Answers a single request, sending the response back to `addr`.
+fn answer_udp(
+    socket: &UdpSocket,
+    server: &Server,
+    request: &[u8],
+    addr: SocketAddr,
+    response: &mut [u8],
+) {
+    let start = Instant::now();
+
+    let Some(response_len) = handler::respond(server, request, addr, Transport::Udp, response)
+    else {
+        return;
+    };
+
+    debug!("request handled in {:?}", start.elapsed());
+
+    if let Err(err) = socket.send_to(&response[..response_len], addr) {
+        error!("failed to send response to {}: {}", addr, err);
     }
 }
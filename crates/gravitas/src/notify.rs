@@ -0,0 +1,416 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use dns::{
+    Class, Header, Notify, OpCode, Packet, Question, RCode, Record, ResourceRecord, Type,
+    proto::{Parse, Parser, Serialize, Serializer},
+    tsig,
+};
+use log::{debug, error, info, warn};
+
+use crate::authority::Authority;
+use crate::config::{PrimaryZone, SecondaryZone, domain_name, find_key};
+use crate::handler::{Signer, unix_time};
+use crate::transfer;
+
+/// How often primary zone files are checked for modifications.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Initial timeout of an outgoing request, doubled on each retransmission.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Transmissions of an outgoing request before giving up, as per
+/// [RFC 1996 Section 3.6](https://www.rfc-editor.org/rfc/rfc1996#section-3.6).
+const ATTEMPTS: u32 = 5;
+
+/// How long to wait before trying again when a secondary zone couldn't be
+/// transferred yet, and there is no SOA to take the retry interval from.
+const INITIAL_RETRY: Duration = Duration::from_secs(60);
+
+/// Shortest interval between checks of a secondary zone, whatever its SOA says.
+const MIN_REFRESH: Duration = Duration::from_secs(5);
+
+/// Watches the files of primary zones, reloading them into `authority` and notifying
+/// their secondaries whenever one is modified.
+pub fn watch(zones: Vec<PrimaryZone>, authority: Arc<Authority>, keys: Arc<[tsig::Key]>) {
+    if zones.is_empty() {
+        return;
+    }
+
+    thread::spawn(move || {
+        let mut modified: HashMap<PathBuf, SystemTime> = HashMap::new();
+
+        loop {
+            for zone in &zones {
+                let time = match std::fs::metadata(&zone.file).and_then(|m| m.modified()) {
+                    Ok(time) => time,
+                    Err(err) => {
+                        warn!("couldn't stat zone file {}: {}", zone.file.display(), err);
+                        continue;
+                    }
+                };
+
+                if let Some(previous) = modified.insert(zone.file.clone(), time)
+                    && previous != time
+                {
+                    info!("zone {} changed", zone.name);
+                    if authority.reload(zone) {
+                        notify_secondaries(zone, &keys);
+                    }
+                }
+            }
+
+            thread::sleep(WATCH_INTERVAL);
+        }
+    });
+}
+
+/// Sends a NOTIFY for the zone to each of its secondaries, signed with the key of the
+/// zone if it has one.
+pub fn notify_secondaries(zone: &PrimaryZone, keys: &[tsig::Key]) {
+    let key = zone
+        .key
+        .as_deref()
+        .and_then(|name| find_key(keys, name))
+        .cloned();
+
+    for &secondary in &zone.notify {
+        let name = zone.name.clone();
+        let key = key.clone();
+
+        thread::spawn(move || {
+            let request = Notify::new(rand::random(), domain_name(&name), Class::IN);
+
+            let mut buf = [0; 4096];
+            let Some(len) = exchange(request.into(), secondary, key.as_ref(), &mut buf) else {
+                error!(
+                    "secondary {} didn't acknowledge notify for {}",
+                    secondary, name
+                );
+                return;
+            };
+
+            match Packet::parse(&mut Parser::new(&buf[..len])) {
+                Ok(response) if response.header.opcode() == OpCode::Notify => {
+                    match response.header.rcode() {
+                        RCode::NoError => debug!("{} acknowledged notify for {}", secondary, name),
+                        rcode => warn!("{} refused notify for {}: {:?}", secondary, name, rcode),
+                    }
+                }
+                Ok(_) => warn!("unexpected response to notify from {}", secondary),
+                Err(err) => warn!("invalid response to notify from {}: {}", secondary, err),
+            }
+        });
+    }
+}
+
+/// Secondary zones, kept up to date with their primaries and served by `authority`.
+pub struct Secondaries {
+    zones: Vec<SecondaryZone>,
+    keys: Arc<[tsig::Key]>,
+    authority: Arc<Authority>,
+
+    /// Zones being checked or transferred right now.
+    refreshing: Mutex<HashSet<String>>,
+}
+
+impl Secondaries {
+    pub fn new(
+        zones: Vec<SecondaryZone>,
+        keys: Arc<[tsig::Key]>,
+        authority: Arc<Authority>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            zones,
+            keys,
+            authority,
+            refreshing: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// Transfers each zone at startup and checks its primaries again at the refresh
+    /// interval of its SOA, or at the retry interval after a failure, as per
+    /// [RFC 1034 Section 4.3.5](https://www.rfc-editor.org/rfc/rfc1034#section-4.3.5).
+    pub fn refresh(self: &Arc<Self>) {
+        for index in 0..self.zones.len() {
+            let secondaries = Arc::clone(self);
+
+            thread::spawn(move || {
+                loop {
+                    let zone = &secondaries.zones[index];
+                    let refreshed = zone
+                        .primaries
+                        .iter()
+                        .any(|&primary| secondaries.check_serial(&zone.name, primary));
+
+                    let (refresh, retry) = secondaries.timers(&zone.name);
+                    thread::sleep(match refreshed {
+                        true => refresh,
+                        false => retry,
+                    });
+                }
+            });
+        }
+    }
+
+    /// The refresh and retry intervals of the zone, from the SOA of the version
+    /// served.
+    fn timers(&self, name: &str) -> (Duration, Duration) {
+        let Some(zone) = self.authority.get(&domain_name(name)) else {
+            return (INITIAL_RETRY, INITIAL_RETRY);
+        };
+
+        match zone.soa() {
+            ResourceRecord::Record {
+                data: Record::SOA { refresh, retry, .. },
+                ..
+            } => (
+                Duration::from_secs(u64::from(*refresh)).max(MIN_REFRESH),
+                Duration::from_secs(u64::from(*retry)).max(MIN_REFRESH),
+            ),
+            _ => (INITIAL_RETRY, INITIAL_RETRY),
+        }
+    }
+
+    /// Handles a NOTIFY received from `source` and signed with the key named `signer`,
+    /// as per [RFC 1996 Section 3](https://www.rfc-editor.org/rfc/rfc1996#section-3).
+    ///
+    /// Only the primaries of a zone may notify for it, with the key of the zone if it
+    /// has one. An accepted notify is acknowledged right away and the serial is checked
+    /// with the primary in the background.
+    pub fn handle<'a>(
+        self: &Arc<Self>,
+        notify: Notify<'a>,
+        source: SocketAddr,
+        signer: Option<&Signer>,
+    ) -> Packet<'a> {
+        let mut response = notify.acknowledge();
+
+        let Some(zone) = self
+            .zones
+            .iter()
+            .find(|zone| domain_name(&zone.name) == notify.zone.name)
+        else {
+            warn!(
+                "notify for unknown zone {} from {}",
+                notify.zone.name, source
+            );
+            response.header.set_rcode(RCode::NotAuth);
+            return response;
+        };
+
+        let Some(&primary) = zone
+            .primaries
+            .iter()
+            .find(|primary| primary.ip() == source.ip())
+        else {
+            warn!("notify for {} from {} refused", zone.name, source);
+            response.header.set_rcode(RCode::Refused);
+            return response;
+        };
+
+        if let Some(key) = &zone.key
+            && !matches!(signer, Some(Signer::Tsig(signer)) if domain_name(signer) == domain_name(key))
+        {
+            warn!(
+                "notify for {} from {} not signed with key {}",
+                zone.name, source, key
+            );
+            response.header.set_rcode(RCode::NotAuth);
+            return response;
+        }
+
+        info!(
+            "notify for {} from {}, serial {:?}",
+            zone.name,
+            source,
+            notify.serial()
+        );
+
+        let secondaries = Arc::clone(self);
+        let name = zone.name.clone();
+        thread::spawn(move || secondaries.check_serial(&name, primary));
+
+        response
+    }
+
+    /// Queries the SOA of the zone on its primary and transfers the zone if its serial
+    /// is newer than the one served, as per
+    /// [RFC 1996 Section 3.11](https://www.rfc-editor.org/rfc/rfc1996#section-3.11).
+    /// Returns whether the zone is up to date with the primary.
+    fn check_serial(&self, name: &str, primary: SocketAddr) -> bool {
+        if !self.refreshing.lock().unwrap().insert(name.to_string()) {
+            debug!("zone {} is being refreshed already", name);
+            return true;
+        }
+
+        let refreshed = self.refresh_from(name, primary);
+        self.refreshing.lock().unwrap().remove(name);
+        refreshed
+    }
+
+    fn refresh_from(&self, name: &str, primary: SocketAddr) -> bool {
+        let zone = domain_name(name).into_owned();
+
+        let header = Header {
+            id: rand::random(),
+            qdcount: 1,
+            ..Default::default()
+        };
+        let query = Packet {
+            header,
+            questions: vec![Question {
+                name: zone.clone(),
+                r#type: Type::SOA,
+                class: Class::IN,
+            }],
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        };
+
+        let key = self
+            .zones
+            .iter()
+            .find(|secondary| secondary.name == name)
+            .and_then(|secondary| secondary.key.as_deref())
+            .and_then(|key| find_key(&self.keys, key));
+
+        let mut buf = [0; 4096];
+        let Some(len) = exchange(query, primary, key, &mut buf) else {
+            error!("primary {} didn't answer soa query for {}", primary, name);
+            return false;
+        };
+
+        let response = match Packet::parse(&mut Parser::new(&buf[..len])) {
+            Ok(response) => response,
+            Err(err) => {
+                warn!("invalid soa response from {}: {}", primary, err);
+                return false;
+            }
+        };
+
+        let Some(serial) = response.answers.iter().find_map(|record| match record {
+            ResourceRecord::Record {
+                name,
+                data: Record::SOA { serial, .. },
+                ..
+            } if *name == zone => Some(*serial),
+            _ => None,
+        }) else {
+            warn!("primary {} has no soa for {}", primary, name);
+            return false;
+        };
+
+        let current = self.authority.get(&zone).map(|zone| zone.serial());
+        if current.is_some_and(|current| serial <= current) {
+            debug!("zone {} is up to date at serial {:?}", name, current);
+            return true;
+        }
+
+        info!(
+            "zone {} changed on {} from serial {:?} to {}, transferring",
+            name, primary, current, serial
+        );
+        match transfer::transfer(&zone, primary, key) {
+            Ok(transferred) => {
+                info!(
+                    "transferred zone {} at serial {} from {}",
+                    name,
+                    transferred.serial(),
+                    primary
+                );
+                self.authority.install(transferred);
+                true
+            }
+            Err(err) => {
+                error!("failed to transfer {} from {}: {}", name, primary, err);
+                false
+            }
+        }
+    }
+}
+
+/// Sends `request` to `server` over UDP, retransmitting with a doubling timeout until a
+/// response with the same id arrives in `buf`. Returns the length of the response.
+///
+/// With a `key` the request is signed and only a response signed with the same key is
+/// accepted.
+fn exchange(
+    request: Packet,
+    server: SocketAddr,
+    key: Option<&tsig::Key>,
+    buf: &mut [u8; 4096],
+) -> Option<usize> {
+    let id = request.header.id;
+
+    let mut request_buf = [0; 4096];
+    let len = match request.serialize(&mut Serializer::new(&mut request_buf)) {
+        Ok(len) => len,
+        Err(err) => {
+            error!("failed to serialize request {:?}", err);
+            return None;
+        }
+    };
+    let mut request = request_buf[..len].to_vec();
+
+    let mac = match key {
+        Some(key) => match tsig::sign(&mut request, key, None, unix_time(), tsig::DEFAULT_FUDGE) {
+            Ok(mac) => Some(mac),
+            Err(err) => {
+                error!("failed to sign request: {}", err);
+                return None;
+            }
+        },
+        None => None,
+    };
+
+    let local: IpAddr = match server {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = match UdpSocket::bind((local, 0)) {
+        Ok(socket) => socket,
+        Err(err) => {
+            error!("couldn't bind socket: {}", err);
+            return None;
+        }
+    };
+
+    let mut timeout = TIMEOUT;
+    for _ in 0..ATTEMPTS {
+        if let Err(err) = socket.send_to(&request, server) {
+            error!("failed to send to {}: {}", server, err);
+            return None;
+        }
+
+        socket.set_read_timeout(Some(timeout)).ok()?;
+        while let Ok((len, from)) = socket.recv_from(buf) {
+            if from != server || len < 2 || u16::from_be_bytes([buf[0], buf[1]]) != id {
+                continue;
+            }
+
+            if let Some(key) = key
+                && let Err(err) = tsig::verify(
+                    &buf[..len],
+                    std::slice::from_ref(key),
+                    mac.as_deref(),
+                    unix_time(),
+                )
+            {
+                warn!("signature of response from {}: {}", server, err);
+                return None;
+            }
+
+            return Some(len);
+        }
+
+        timeout *= 2;
+    }
+
+    None
+}
@@ -0,0 +1,1143 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use dns::{Class, DomainName, Packet, Question, RCode, Record, ResourceRecord, Type};
+use log::debug;
+
+use crate::config::{Recursion, domain_name};
+use crate::upstream::{self, Exchange, Rtt};
+use crate::zone;
+
+/// Addresses of the root name servers as published by IANA, used unless the
+/// configuration names others.
+const ROOT_HINTS: [(&str, [u8; 4]); 13] = [
+    ("a.root-servers.net.", [198, 41, 0, 4]),
+    ("b.root-servers.net.", [170, 247, 170, 2]),
+    ("c.root-servers.net.", [192, 33, 4, 12]),
+    ("d.root-servers.net.", [199, 7, 91, 13]),
+    ("e.root-servers.net.", [192, 203, 230, 10]),
+    ("f.root-servers.net.", [192, 5, 5, 241]),
+    ("g.root-servers.net.", [192, 112, 36, 4]),
+    ("h.root-servers.net.", [198, 97, 190, 53]),
+    ("i.root-servers.net.", [192, 36, 148, 17]),
+    ("j.root-servers.net.", [192, 58, 128, 30]),
+    ("k.root-servers.net.", [193, 0, 14, 129]),
+    ("l.root-servers.net.", [199, 7, 83, 42]),
+    ("m.root-servers.net.", [202, 12, 27, 33]),
+];
+
+const PORT: u16 = 53;
+
+/// Queries sent for one resolution, including the ones for name server addresses.
+const MAX_QUERIES: usize = 64;
+
+/// Time one resolution may take, including the resolutions of name server addresses.
+const MAX_TIME: Duration = Duration::from_secs(10);
+
+/// Delegations remembered from referrals, further ones are dropped until some expire.
+const MAX_DELEGATIONS: usize = 4096;
+
+/// Seconds a delegation is remembered for at most, whatever the TTL of its NS records.
+const MAX_DELEGATION_TTL: u32 = 86400;
+
+/// Referrals followed for one name before giving up.
+const MAX_REFERRALS: usize = 16;
+
+/// Length of a CNAME or DNAME chain that is followed.
+const MAX_CHAIN: usize = 8;
+
+/// Nesting of resolutions of name server addresses, which may need others in turn.
+const MAX_DEPTH: usize = 4;
+
+/// Times each server of a zone is asked before giving up on the zone.
+const ATTEMPTS: usize = 2;
+
+/// The answer to a question, with the chain of aliases leading to it.
+pub struct Resolution {
+    pub rcode: RCode,
+    pub answers: Vec<ResourceRecord<'static>>,
+
+    /// The SOA of negative answers.
+    pub authorities: Vec<ResourceRecord<'static>>,
+}
+
+#[derive(Debug)]
+pub enum ResolveError {
+    /// None of the name servers of the zone gave a usable response.
+    Unreachable(String),
+
+    /// Resolution took more queries, referrals, aliases or time than allowed.
+    Limit(String),
+
+    /// None of the upstreams queries are forwarded to gave a usable response.
+    Upstreams,
+}
+
+impl Display for ResolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unreachable(zone) => write!(f, "no name server of {} answered", zone),
+            Self::Limit(name) => write!(f, "resolution of {} exceeded its limits", name),
+            Self::Upstreams => write!(f, "no upstream answered"),
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+/// Answers questions about names outside of the served zones.
+pub trait Resolve: Send + Sync {
+    fn resolve(&self, question: &Question) -> Result<Resolution, ResolveError>;
+}
+
+/// Resolves questions iteratively, starting from the root as described in
+/// [RFC 1034 Section 5.3.3](https://www.rfc-editor.org/rfc/rfc1034#section-5.3.3).
+///
+/// Delegations learned from referrals are remembered for the TTL of their NS records,
+/// so that later resolutions start at the closest known zone cut instead.
+pub struct Resolver {
+    exchange: Arc<dyn Exchange>,
+    rtt: Rtt,
+    root_hints: Vec<(DomainName<'static>, IpAddr)>,
+    timeout: Duration,
+    max_time: Duration,
+
+    /// Delegations by lowercased zone name.
+    delegations: Mutex<HashMap<String, Delegation>>,
+}
+
+/// The zone a query is sent to, with the name servers it was delegated to.
+#[derive(Clone)]
+struct Delegation {
+    zone: DomainName<'static>,
+    servers: Vec<NameServer>,
+
+    /// When the NS records of the delegation expire.
+    expires: Instant,
+}
+
+/// What is left to spend on a resolution, shared with the resolutions of name server
+/// addresses it needs.
+struct Budget {
+    queries: usize,
+    deadline: Instant,
+}
+
+impl Budget {
+    /// Takes a query from the budget and returns the time left for it, or fails if
+    /// the resolution of `name` ran out of either.
+    fn spend(&mut self, name: &DomainName) -> Result<Duration, ResolveError> {
+        let remaining = self
+            .deadline
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero());
+
+        match remaining {
+            Some(remaining) if self.queries > 0 => {
+                self.queries -= 1;
+                Ok(remaining)
+            }
+            _ => Err(ResolveError::Limit(name.to_string())),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct NameServer {
+    name: DomainName<'static>,
+
+    /// `None` until the addresses are looked up, for name servers without glue.
+    addresses: Option<Vec<IpAddr>>,
+}
+
+/// What a response says about the name that was asked for.
+enum Outcome {
+    Answer(Vec<ResourceRecord<'static>>),
+
+    /// Aliases leading to a name that has to be resolved again, from the closest zone
+    /// cut known.
+    Alias(Vec<ResourceRecord<'static>>, DomainName<'static>),
+
+    /// NXDOMAIN or NODATA, possibly at the end of a chain of aliases.
+    Negative {
+        rcode: RCode,
+        aliases: Vec<ResourceRecord<'static>>,
+        authorities: Vec<ResourceRecord<'static>>,
+    },
+
+    Referral(Delegation),
+
+    /// The server isn't authoritative for the zone or failed, another one is asked.
+    Lame,
+}
+
+impl Resolver {
+    pub fn new(config: &Recursion, exchange: Arc<dyn Exchange>) -> Self {
+        let root_hints = match config.root_hints.is_empty() {
+            true => ROOT_HINTS
+                .iter()
+                .map(|(name, address)| (domain_name(name), Ipv4Addr::from(*address).into()))
+                .collect(),
+            false => config
+                .root_hints
+                .iter()
+                .map(|&address| (DomainName::from(Vec::new()), address))
+                .collect(),
+        };
+
+        Self {
+            exchange,
+            rtt: Rtt::default(),
+            root_hints,
+            timeout: Duration::from_millis(config.timeout),
+            max_time: MAX_TIME,
+            delegations: Mutex::default(),
+        }
+    }
+
+    fn resolve_name(
+        &self,
+        qname: DomainName<'static>,
+        qtype: &Type,
+        budget: &mut Budget,
+        depth: usize,
+    ) -> Result<Resolution, ResolveError> {
+        let mut resolution = Resolution {
+            rcode: RCode::NoError,
+            answers: Vec::new(),
+            authorities: Vec::new(),
+        };
+
+        let mut visited = vec![qname];
+        'chain: while visited.len() <= MAX_CHAIN {
+            let name = visited[visited.len() - 1].clone();
+            let mut delegation = self.closest(&name);
+            let mut remembered = !delegation.zone.labels.is_empty();
+
+            for _ in 0..MAX_REFERRALS {
+                let outcome = match self.query(&mut delegation, &name, qtype, budget, depth) {
+                    // the servers may have changed since, start over from the root
+                    Err(ResolveError::Unreachable(zone)) if remembered => {
+                        debug!("remembered delegation of {} unreachable", zone);
+                        self.forget(&delegation.zone);
+                        delegation = self.root();
+                        remembered = false;
+                        continue;
+                    }
+                    outcome => outcome?,
+                };
+                // addresses of name servers without glue may have been looked up
+                self.remember(&delegation);
+
+                match outcome {
+                    Outcome::Answer(records) => {
+                        resolution.answers.extend(records);
+                        return Ok(resolution);
+                    }
+                    Outcome::Alias(records, target) => {
+                        resolution.answers.extend(records);
+                        if visited.contains(&target) {
+                            break 'chain;
+                        }
+                        visited.push(target);
+                        continue 'chain;
+                    }
+                    Outcome::Negative {
+                        rcode,
+                        aliases,
+                        authorities,
+                    } => {
+                        resolution.rcode = rcode;
+                        resolution.answers.extend(aliases);
+                        resolution.authorities = authorities;
+                        return Ok(resolution);
+                    }
+                    Outcome::Referral(referral) => {
+                        self.remember(&referral);
+                        delegation = referral;
+                        remembered = false;
+                    }
+                    Outcome::Lame => unreachable!("lame responses are retried"),
+                }
+            }
+
+            return Err(ResolveError::Limit(name.to_string()));
+        }
+
+        Err(ResolveError::Limit(visited[0].to_string()))
+    }
+
+    fn root(&self) -> Delegation {
+        Delegation {
+            zone: DomainName::from(Vec::new()),
+            servers: self
+                .root_hints
+                .iter()
+                .map(|(name, address)| NameServer {
+                    name: name.clone(),
+                    addresses: Some(vec![*address]),
+                })
+                .collect(),
+            expires: Instant::now(),
+        }
+    }
+
+    /// The remembered delegation of the zone closest to `name`, or the root.
+    fn closest(&self, name: &DomainName) -> Delegation {
+        let now = Instant::now();
+        let mut delegations = self.delegations.lock().unwrap();
+
+        for skip in 0..name.labels.len() {
+            let key = zone::key(&name.labels[skip..]);
+            match delegations.get(&key) {
+                Some(delegation) if delegation.expires > now => return delegation.clone(),
+                Some(_) => {
+                    delegations.remove(&key);
+                }
+                None => {}
+            }
+        }
+
+        drop(delegations);
+        self.root()
+    }
+
+    /// Remembers the delegation of a zone below the root, replacing an earlier one.
+    fn remember(&self, delegation: &Delegation) {
+        if delegation.zone.labels.is_empty() {
+            return;
+        }
+
+        let mut delegations = self.delegations.lock().unwrap();
+        if delegations.len() >= MAX_DELEGATIONS {
+            let now = Instant::now();
+            delegations.retain(|_, delegation| delegation.expires > now);
+        }
+
+        let key = zone::key(&delegation.zone.labels);
+        if delegations.len() < MAX_DELEGATIONS || delegations.contains_key(&key) {
+            delegations.insert(key, delegation.clone());
+        }
+    }
+
+    fn forget(&self, zone: &DomainName) {
+        self.delegations
+            .lock()
+            .unwrap()
+            .remove(&zone::key(&zone.labels));
+    }
+
+    /// Asks the name servers of `delegation` about `name`, fastest first, until one
+    /// gives a usable response. Addresses of name servers without glue are looked up
+    /// when the ones known run out.
+    fn query(
+        &self,
+        delegation: &mut Delegation,
+        name: &DomainName<'static>,
+        qtype: &Type,
+        budget: &mut Budget,
+        depth: usize,
+    ) -> Result<Outcome, ResolveError> {
+        let question = Question {
+            name: name.clone(),
+            r#type: qtype.clone(),
+            class: Class::IN,
+        };
+        let mut attempts: HashMap<SocketAddr, usize> = HashMap::new();
+
+        loop {
+            let mut candidates: Vec<SocketAddr> = delegation
+                .servers
+                .iter()
+                .flat_map(|server| server.addresses.iter().flatten())
+                .map(|&address| SocketAddr::new(address, PORT))
+                .filter(|server| attempts.get(server).copied().unwrap_or(0) < ATTEMPTS)
+                .collect();
+
+            if candidates.is_empty() {
+                if self.resolve_glueless(delegation, budget, depth)? {
+                    continue;
+                }
+                return Err(ResolveError::Unreachable(delegation.zone.to_string()));
+            }
+
+            self.rtt.sort(&mut candidates);
+            let server = candidates[0];
+            *attempts.entry(server).or_default() += 1;
+
+            let timeout = budget.spend(name)?.min(self.timeout);
+
+            let query = upstream::query(question.clone(), false);
+            let response =
+                match upstream::ask(self.exchange.as_ref(), &query, server, timeout, &self.rtt) {
+                    Ok(response) => response,
+                    Err(err) => {
+                        debug!("{} failed for {} {}: {}", server, name, qtype, err);
+                        continue;
+                    }
+                };
+
+            match classify(&delegation.zone, name, qtype, response) {
+                Outcome::Lame => {
+                    debug!("lame response from {} for {}", server, delegation.zone);
+                    // a lame server won't get better by asking again
+                    attempts.insert(server, ATTEMPTS);
+                }
+                outcome => return Ok(outcome),
+            }
+        }
+    }
+
+    /// Looks up the addresses of the next name server of `delegation` without glue.
+    /// Returns whether there was one to look up.
+    fn resolve_glueless(
+        &self,
+        delegation: &mut Delegation,
+        budget: &mut Budget,
+        depth: usize,
+    ) -> Result<bool, ResolveError> {
+        let Some(server) = delegation
+            .servers
+            .iter_mut()
+            .find(|server| server.addresses.is_none())
+        else {
+            return Ok(false);
+        };
+
+        let mut addresses = Vec::new();
+
+        // names within the zone need glue, asking the zone for them can't work
+        if depth < MAX_DEPTH && !server.name.ends_with(&delegation.zone) {
+            debug!("resolving address of name server {}", server.name);
+
+            match self.resolve_name(server.name.clone(), &Type::A, budget, depth + 1) {
+                Ok(resolution) => {
+                    addresses.extend(resolution.answers.iter().filter_map(|record| match record {
+                        ResourceRecord::Record { data, .. } => data.ip(),
+                        _ => None,
+                    }))
+                }
+                Err(ResolveError::Limit(name)) => return Err(ResolveError::Limit(name)),
+                Err(err) => debug!("name server {} unresolvable: {}", server.name, err),
+            }
+        }
+
+        server.addresses = Some(addresses);
+        Ok(true)
+    }
+}
+
+impl Resolve for Resolver {
+    fn resolve(&self, question: &Question) -> Result<Resolution, ResolveError> {
+        let mut budget = Budget {
+            queries: MAX_QUERIES,
+            deadline: Instant::now() + self.max_time,
+        };
+        self.resolve_name(
+            question.name.clone().into_owned(),
+            &question.r#type,
+            &mut budget,
+            0,
+        )
+    }
+}
+
+/// Classifies the response of a server of `zone` to a query for `name`, only trusting
+/// records within the zone.
+fn classify(
+    zone: &DomainName<'static>,
+    name: &DomainName<'static>,
+    qtype: &Type,
+    response: Packet<'static>,
+) -> Outcome {
+    let rcode = response.header.rcode();
+    if !matches!(rcode, RCode::NoError | RCode::NXDomain) {
+        return Outcome::Lame;
+    }
+
+    // follow aliases within the answer section, as far as the zone goes
+    let mut aliases = Vec::new();
+    let mut current = name.clone();
+    while aliases.len() < MAX_CHAIN * 2 && current.ends_with(zone) {
+        let answers: Vec<_> = response
+            .answers
+            .iter()
+            .filter(|record| {
+                owner(record) == Some(&current)
+                    && (*qtype == Type::ANY || Type::from(*record) == *qtype)
+            })
+            .cloned()
+            .collect();
+        if !answers.is_empty() {
+            aliases.extend(answers);
+            return Outcome::Answer(aliases);
+        }
+
+        match alias(&response.answers, &current) {
+            Some((records, target)) => {
+                aliases.extend(records);
+                current = target;
+            }
+            None => break,
+        }
+    }
+
+    let soa: Vec<_> = response
+        .authorities
+        .iter()
+        .filter(|record| {
+            Type::from(*record) == Type::SOA
+                && owner(record).is_some_and(|owner| owner.ends_with(zone))
+        })
+        .cloned()
+        .collect();
+
+    if rcode == RCode::NXDomain {
+        return Outcome::Negative {
+            rcode,
+            aliases,
+            authorities: soa,
+        };
+    }
+
+    if current != *name && (!current.ends_with(zone) || aliases.len() >= MAX_CHAIN * 2) {
+        return Outcome::Alias(aliases, current);
+    }
+
+    if let Some(referral) = referral(zone, &current, &response) {
+        return match current == *name {
+            true => Outcome::Referral(referral),
+            // the rest of the chain belongs to another zone
+            false => Outcome::Alias(aliases, current),
+        };
+    }
+
+    if response.header.authoritative() || !soa.is_empty() {
+        return Outcome::Negative {
+            rcode,
+            aliases,
+            authorities: soa,
+        };
+    }
+
+    Outcome::Lame
+}
+
+/// The CNAME of `name`, or the DNAME of one of its ancestors with the CNAME it
+/// implies as per [RFC 6672 Section 2.2](https://www.rfc-editor.org/rfc/rfc6672#section-2.2),
+/// along with the name they lead to.
+fn alias(
+    answers: &[ResourceRecord<'static>],
+    name: &DomainName<'static>,
+) -> Option<(Vec<ResourceRecord<'static>>, DomainName<'static>)> {
+    for record in answers {
+        let ResourceRecord::Record {
+            name: owner,
+            class,
+            ttl,
+            data,
+        } = record
+        else {
+            continue;
+        };
+
+        match data {
+            Record::CNAME { cname } if owner == name => {
+                return Some((vec![record.clone()], cname.clone()));
+            }
+            Record::DNAME { target }
+                if name.ends_with(owner) && name.labels.len() > owner.labels.len() =>
+            {
+                let prefix = &name.labels[..name.labels.len() - owner.labels.len()];
+                let mut cname = DomainName::from(Vec::new());
+                cname.labels.extend(prefix.iter().cloned());
+                cname.labels.extend(target.labels.iter().cloned());
+
+                // RFC 6672 Section 2.2, substitutions overflowing a name fail
+                if cname.size() > 255 {
+                    return None;
+                }
+
+                let synthesized = ResourceRecord::Record {
+                    name: name.clone(),
+                    class: class.clone(),
+                    ttl: *ttl,
+                    data: Record::CNAME {
+                        cname: cname.clone(),
+                    },
+                };
+                return Some((vec![record.clone(), synthesized], cname));
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// The delegation to a zone below `zone` that `name` is in, with glue from the
+/// additional section where it is within `zone`.
+fn referral(
+    zone: &DomainName<'static>,
+    name: &DomainName<'static>,
+    response: &Packet<'static>,
+) -> Option<Delegation> {
+    let mut child: Option<DomainName<'static>> = None;
+    let mut servers = Vec::new();
+    let mut ttl = MAX_DELEGATION_TTL;
+
+    for record in &response.authorities {
+        let ResourceRecord::Record {
+            name: owner,
+            ttl: ns_ttl,
+            data: Record::NS { nsdname },
+            ..
+        } = record
+        else {
+            continue;
+        };
+
+        if !name.ends_with(owner)
+            || !owner.ends_with(zone)
+            || owner.labels.len() <= zone.labels.len()
+            || child.as_ref().is_some_and(|child| child != owner)
+        {
+            continue;
+        }
+        child = Some(owner.clone());
+        ttl = ttl.min(*ns_ttl);
+
+        let glue: Vec<IpAddr> = response
+            .additionals
+            .iter()
+            .filter_map(|record| match record {
+                ResourceRecord::Record {
+                    name: owner, data, ..
+                } if owner == nsdname && owner.ends_with(zone) => data.ip(),
+                _ => None,
+            })
+            .collect();
+
+        servers.push(NameServer {
+            name: nsdname.clone(),
+            addresses: (!glue.is_empty()).then_some(glue),
+        });
+    }
+
+    Some(Delegation {
+        zone: child?,
+        servers,
+        expires: Instant::now() + Duration::from_secs(u64::from(ttl)),
+    })
+}
+
+fn owner<'r>(record: &'r ResourceRecord<'static>) -> Option<&'r DomainName<'static>> {
+    match record {
+        ResourceRecord::Record { name, .. } | ResourceRecord::Unknown { name, .. } => Some(name),
+        ResourceRecord::OPTRecord { .. } => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::thread;
+
+    use dns::{
+        OpCode,
+        proto::{Parse, Parser, Serialize, Serializer},
+    };
+
+    use super::*;
+    use crate::config;
+    use crate::handler::{self, Transport};
+    use crate::zone::Zone;
+
+    const ROOT: &str = "
+. 86400 SOA a.root.test. hostmaster.root.test. 1 1800 900 604800 86400
+. 86400 NS a.root.test.
+a.root.test. 86400 A 198.51.100.1
+com. 86400 NS a.gtld.test.
+net. 86400 NS a.gtld.test.
+org. 86400 NS a.gtld.test.
+test. 86400 NS a.root.test.
+a.gtld.test. 86400 A 198.51.100.2
+";
+
+    const COM: &str = "
+com. 900 SOA a.gtld.test. hostmaster.gtld.test. 1 1800 900 604800 900
+com. 86400 NS a.gtld.test.
+example.com. 3600 NS ns1.example.com.
+ns1.example.com. 3600 A 198.51.100.10
+glueless.com. 3600 NS ns.example.net.
+lame.com. 3600 NS ns1.lame.com.
+lame.com. 3600 NS ns2.lame.com.
+ns1.lame.com. 3600 A 198.51.100.20
+ns2.lame.com. 3600 A 198.51.100.21
+slow.com. 3600 NS ns1.slow.com.
+slow.com. 3600 NS ns2.slow.com.
+ns1.slow.com. 3600 A 198.51.100.30
+ns2.slow.com. 3600 A 198.51.100.31
+big.com. 3600 NS ns.big.com.
+ns.big.com. 3600 A 198.51.100.40
+pair.com. 3600 NS a.pair.com.
+pair.com. 3600 NS b.pair.com.
+a.pair.com. 3600 A 198.51.100.50
+b.pair.com. 3600 A 198.51.100.51
+dead.com. 3600 NS ns1.dead.com.
+dead.com. 3600 NS ns2.dead.com.
+ns1.dead.com. 3600 A 198.51.100.60
+ns2.dead.com. 3600 A 198.51.100.61
+";
+
+    const NET: &str = "
+net. 900 SOA a.gtld.test. hostmaster.gtld.test. 1 1800 900 604800 900
+net. 86400 NS a.gtld.test.
+example.net. 3600 NS ns.example.net.
+ns.example.net. 3600 A 198.51.100.11
+";
+
+    const ORG: &str = "
+org. 900 SOA a.gtld.test. hostmaster.gtld.test. 1 1800 900 604800 900
+org. 86400 NS a.gtld.test.
+example.org. 3600 NS ns.example.org.
+ns.example.org. 3600 A 198.51.100.12
+";
+
+    const EXAMPLE_COM: &str = "
+example.com. 3600 SOA ns1.example.com. hostmaster.example.com. 1 7200 900 604800 300
+example.com. 3600 NS ns1.example.com.
+ns1.example.com. 3600 A 198.51.100.10
+www.example.com. 3600 A 192.0.2.1
+mail.example.com. 3600 A 192.0.2.2
+alias.example.com. 3600 CNAME www.example.net.
+";
+
+    const EXAMPLE_NET: &str = "
+example.net. 3600 SOA ns.example.net. hostmaster.example.net. 1 7200 900 604800 300
+example.net. 3600 NS ns.example.net.
+ns.example.net. 3600 A 198.51.100.11
+www.example.net. 3600 A 192.0.2.3
+";
+
+    /// Serves the A record of `www` below `origin` only.
+    fn www(origin: &str, address: &str) -> Zone {
+        zone(
+            origin,
+            &format!(
+                "{origin} 3600 SOA ns.{origin} hostmaster.{origin} 1 7200 900 604800 300\n\
+                 {origin} 3600 NS ns.{origin}\n\
+                 www.{origin} 3600 A {address}\n"
+            ),
+        )
+    }
+
+    fn zone(origin: &str, records: &str) -> Zone {
+        let origin = domain_name(origin).into_owned();
+        let records = crate::zone::file::parse(records, &origin).unwrap();
+        Zone::new(origin, records).unwrap()
+    }
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    enum Behavior {
+        /// Answers authoritatively from the zones.
+        Zones(Vec<Zone>),
+
+        /// Answers with the records, whatever the question.
+        Fixed(Vec<ResourceRecord<'static>>),
+
+        /// Answers over TCP only, over UDP the response is truncated.
+        Truncating(Vec<Zone>),
+
+        Refused,
+
+        /// Never answers, taking the whole timeout.
+        Timeout,
+    }
+
+    /// Name servers that answer from zones without a network.
+    #[derive(Default)]
+    struct Fake {
+        servers: HashMap<IpAddr, Behavior>,
+
+        /// The queries received, in order.
+        log: Mutex<Vec<(IpAddr, String, Transport)>>,
+    }
+
+    impl Fake {
+        fn new() -> Self {
+            let mut fake = Self::default();
+            fake.serve("198.51.100.1", Behavior::Zones(vec![zone(".", ROOT)]));
+            fake.serve(
+                "198.51.100.2",
+                Behavior::Zones(vec![
+                    zone("com.", COM),
+                    zone("net.", NET),
+                    zone("org.", ORG),
+                ]),
+            );
+            fake.serve(
+                "198.51.100.10",
+                Behavior::Zones(vec![zone("example.com.", EXAMPLE_COM)]),
+            );
+            fake.serve(
+                "198.51.100.11",
+                Behavior::Zones(vec![
+                    zone("example.net.", EXAMPLE_NET),
+                    www("glueless.com.", "192.0.2.4"),
+                ]),
+            );
+
+            let dname = ResourceRecord::Record {
+                name: domain_name("example.org.").into_owned(),
+                class: Class::IN,
+                ttl: 3600,
+                data: Record::DNAME {
+                    target: domain_name("example.net.").into_owned(),
+                },
+            };
+            fake.serve("198.51.100.12", Behavior::Fixed(vec![dname]));
+
+            fake.serve("198.51.100.20", Behavior::Refused);
+            fake.serve(
+                "198.51.100.21",
+                Behavior::Zones(vec![www("lame.com.", "192.0.2.5")]),
+            );
+            fake.serve("198.51.100.30", Behavior::Timeout);
+            fake.serve(
+                "198.51.100.31",
+                Behavior::Zones(vec![www("slow.com.", "192.0.2.6")]),
+            );
+            fake.serve(
+                "198.51.100.40",
+                Behavior::Truncating(vec![www("big.com.", "192.0.2.7")]),
+            );
+            fake.serve(
+                "198.51.100.50",
+                Behavior::Zones(vec![www("pair.com.", "192.0.2.8")]),
+            );
+            fake.serve(
+                "198.51.100.51",
+                Behavior::Zones(vec![www("pair.com.", "192.0.2.8")]),
+            );
+            fake.serve("198.51.100.60", Behavior::Timeout);
+            fake.serve("198.51.100.61", Behavior::Timeout);
+            fake
+        }
+
+        fn serve(&mut self, address: &str, behavior: Behavior) {
+            self.servers.insert(ip(address), behavior);
+        }
+
+        /// The servers asked, in order.
+        fn asked(&self) -> Vec<IpAddr> {
+            self.log
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(ip, ..)| *ip)
+                .collect()
+        }
+
+        fn clear(&self) {
+            self.log.lock().unwrap().clear();
+        }
+    }
+
+    impl Exchange for Fake {
+        fn exchange(
+            &self,
+            query: &[u8],
+            server: SocketAddr,
+            transport: Transport,
+            timeout: Duration,
+        ) -> io::Result<Vec<u8>> {
+            let query = Packet::parse(&mut Parser::new(query)).unwrap();
+            let question = query.questions[0].clone().into_owned();
+            self.log.lock().unwrap().push((
+                server.ip(),
+                format!("{} {}", question.name, question.r#type),
+                transport,
+            ));
+
+            let mut response = handler::error(query.header.id, OpCode::Query, RCode::NoError);
+            response.questions = vec![question.clone()];
+
+            let zones = match self.servers.get(&server.ip()) {
+                None | Some(Behavior::Timeout) => {
+                    thread::sleep(timeout);
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+                Some(Behavior::Refused) => {
+                    response.header.set_rcode(RCode::Refused);
+                    return Ok(serialize(response));
+                }
+                Some(Behavior::Fixed(records)) => {
+                    response.header.set_authoritative(true);
+                    response.answers = records.clone();
+                    return Ok(serialize(response));
+                }
+                Some(Behavior::Truncating(_)) if transport == Transport::Udp => {
+                    response.header.set_truncated(true);
+                    return Ok(serialize(response));
+                }
+                Some(Behavior::Zones(zones) | Behavior::Truncating(zones)) => zones,
+            };
+
+            let Some(zone) = zones
+                .iter()
+                .filter(|zone| question.name.ends_with(&zone.origin))
+                .max_by_key(|zone| zone.origin.labels.len())
+            else {
+                response.header.set_rcode(RCode::Refused);
+                return Ok(serialize(response));
+            };
+
+            let lookup = zone.lookup(&question.name, &question.r#type);
+            response.header.set_rcode(lookup.rcode);
+            response.header.set_authoritative(lookup.authoritative);
+            response.answers = lookup.answers;
+            response.authorities = lookup.authorities;
+            response.additionals = lookup.additionals;
+            Ok(serialize(response))
+        }
+    }
+
+    fn serialize(mut packet: Packet) -> Vec<u8> {
+        packet.header.qdcount = packet.questions.len() as u16;
+        packet.header.ancount = packet.answers.len() as u16;
+        packet.header.nscount = packet.authorities.len() as u16;
+        packet.header.arcount = packet.additionals.len() as u16;
+
+        let mut buf = vec![0; usize::from(u16::MAX)];
+        let len = packet.serialize(&mut Serializer::new(&mut buf)).unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    fn resolver() -> (Resolver, Arc<Fake>) {
+        let fake = Arc::new(Fake::new());
+        let config = config::Recursion {
+            root_hints: vec![ip("198.51.100.1")],
+            timeout: 100,
+        };
+        (Resolver::new(&config, fake.clone()), fake)
+    }
+
+    fn resolve(resolver: &Resolver, name: &str) -> Result<Resolution, ResolveError> {
+        resolver.resolve(&Question {
+            name: domain_name(name),
+            r#type: Type::A,
+            class: Class::IN,
+        })
+    }
+
+    /// The answers as type and data, in order.
+    fn answers(resolution: &Resolution) -> Vec<String> {
+        resolution
+            .answers
+            .iter()
+            .map(|record| match record {
+                ResourceRecord::Record { data, .. } => match (data, data.ip()) {
+                    (_, Some(ip)) => format!("A {}", ip),
+                    (Record::CNAME { cname }, _) => format!("CNAME {}", cname),
+                    (Record::DNAME { target }, _) => format!("DNAME {}", target),
+                    _ => format!("{:?}", data),
+                },
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    fn ips(addresses: &[&str]) -> Vec<IpAddr> {
+        addresses.iter().map(|address| ip(address)).collect()
+    }
+
+    /// Makes `fast` look faster than `slow` to the resolver.
+    fn prefer(resolver: &Resolver, fast: &str, slow: &str) {
+        resolver.rtt.sample(ip(fast), Duration::ZERO);
+        resolver.rtt.sample(ip(slow), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn referral_with_glue() {
+        let (resolver, fake) = resolver();
+
+        let resolution = resolve(&resolver, "www.example.com.").unwrap();
+        assert_eq!(resolution.rcode, RCode::NoError);
+        assert_eq!(answers(&resolution), ["A 192.0.2.1"]);
+        assert_eq!(
+            fake.asked(),
+            ips(&["198.51.100.1", "198.51.100.2", "198.51.100.10"])
+        );
+    }
+
+    #[test]
+    fn glueless_out_of_bailiwick_name_server() {
+        let (resolver, fake) = resolver();
+
+        let resolution = resolve(&resolver, "www.glueless.com.").unwrap();
+        assert_eq!(answers(&resolution), ["A 192.0.2.4"]);
+
+        // ns.example.net is looked up from the net servers before asking it
+        let log = fake.log.lock().unwrap();
+        let lookups: Vec<_> = log
+            .iter()
+            .map(|(_, question, _)| question.as_str())
+            .collect();
+        assert!(lookups.contains(&"ns.example.net. A"));
+        assert_eq!(log.last().unwrap().0, ip("198.51.100.11"));
+    }
+
+    #[test]
+    fn nxdomain() {
+        let (resolver, _) = resolver();
+
+        let resolution = resolve(&resolver, "nope.example.com.").unwrap();
+        assert_eq!(resolution.rcode, RCode::NXDomain);
+        assert!(resolution.answers.is_empty());
+        assert_eq!(resolution.authorities.len(), 1);
+    }
+
+    #[test]
+    fn cname_across_zones() {
+        let (resolver, _) = resolver();
+
+        let resolution = resolve(&resolver, "alias.example.com.").unwrap();
+        assert_eq!(
+            answers(&resolution),
+            ["CNAME www.example.net.", "A 192.0.2.3"]
+        );
+    }
+
+    #[test]
+    fn dname_across_zones() {
+        let (resolver, _) = resolver();
+
+        let resolution = resolve(&resolver, "www.example.org.").unwrap();
+        assert_eq!(
+            answers(&resolution),
+            [
+                "DNAME example.net.",
+                "CNAME www.example.net.",
+                "A 192.0.2.3"
+            ]
+        );
+    }
+
+    #[test]
+    fn lame_server_is_skipped() {
+        let (resolver, fake) = resolver();
+        prefer(&resolver, "198.51.100.20", "198.51.100.21");
+
+        let resolution = resolve(&resolver, "www.lame.com.").unwrap();
+        assert_eq!(answers(&resolution), ["A 192.0.2.5"]);
+        assert_eq!(fake.asked()[2..], ips(&["198.51.100.20", "198.51.100.21"]));
+    }
+
+    #[test]
+    fn failover_on_timeout() {
+        let (resolver, fake) = resolver();
+        resolver.rtt.sample(ip("198.51.100.30"), Duration::ZERO);
+        resolver
+            .rtt
+            .sample(ip("198.51.100.31"), Duration::from_millis(10));
+
+        let resolution = resolve(&resolver, "www.slow.com.").unwrap();
+        assert_eq!(answers(&resolution), ["A 192.0.2.6"]);
+        assert_eq!(fake.asked()[2..], ips(&["198.51.100.30", "198.51.100.31"]));
+
+        // the server that timed out is avoided afterwards
+        assert!(
+            resolver.rtt.estimate(ip("198.51.100.30")) > resolver.rtt.estimate(ip("198.51.100.31"))
+        );
+    }
+
+    #[test]
+    fn truncated_response_is_repeated_over_tcp() {
+        let (resolver, fake) = resolver();
+
+        let resolution = resolve(&resolver, "www.big.com.").unwrap();
+        assert_eq!(answers(&resolution), ["A 192.0.2.7"]);
+
+        let log = fake.log.lock().unwrap();
+        let transports: Vec<_> = log[2..]
+            .iter()
+            .map(|(ip, _, transport)| (*ip, *transport))
+            .collect();
+        assert_eq!(
+            transports,
+            [
+                (ip("198.51.100.40"), Transport::Udp),
+                (ip("198.51.100.40"), Transport::Tcp)
+            ]
+        );
+    }
+
+    #[test]
+    fn fastest_server_first() {
+        for (fast, slow) in [
+            ("198.51.100.50", "198.51.100.51"),
+            ("198.51.100.51", "198.51.100.50"),
+        ] {
+            let (resolver, fake) = resolver();
+            prefer(&resolver, fast, slow);
+
+            resolve(&resolver, "www.pair.com.").unwrap();
+            assert_eq!(fake.asked()[2..], ips(&[fast]));
+        }
+    }
+
+    #[test]
+    fn resolution_starts_at_the_closest_delegation() {
+        let (resolver, fake) = resolver();
+
+        resolve(&resolver, "www.example.com.").unwrap();
+        fake.clear();
+
+        let resolution = resolve(&resolver, "mail.example.com.").unwrap();
+        assert_eq!(answers(&resolution), ["A 192.0.2.2"]);
+        assert_eq!(fake.asked(), ips(&["198.51.100.10"]));
+
+        // a sibling below a known top level domain skips the root
+        fake.clear();
+        resolve(&resolver, "www.pair.com.").unwrap();
+        assert_eq!(fake.asked()[0], ip("198.51.100.2"));
+    }
+
+    #[test]
+    fn unreachable_delegation_is_forgotten() {
+        let (resolver, fake) = resolver();
+        resolve(&resolver, "www.example.com.").unwrap();
+
+        // the servers of the zone changed
+        let mut delegation = resolver.closest(&domain_name("example.com."));
+        delegation.servers[0].addresses = Some(vec![ip("198.51.100.60")]);
+        resolver.remember(&delegation);
+        fake.clear();
+
+        let resolution = resolve(&resolver, "www.example.com.").unwrap();
+        assert_eq!(answers(&resolution), ["A 192.0.2.1"]);
+        assert_eq!(fake.asked()[0], ip("198.51.100.60"));
+        assert!(fake.asked().contains(&ip("198.51.100.1")));
+    }
+
+    #[test]
+    fn resolution_has_a_deadline() {
+        let (mut resolver, _) = resolver();
+        resolver.max_time = Duration::from_millis(250);
+
+        let start = Instant::now();
+        let result = resolve(&resolver, "www.dead.com.");
+        assert!(matches!(result, Err(ResolveError::Limit(_))));
+
+        // two servers asked twice would take 400ms
+        assert!(start.elapsed() < Duration::from_millis(350));
+    }
+}
@@ -0,0 +1,237 @@
+use std::sync::Arc;
+
+use dns::DomainName;
+use log::info;
+
+use crate::config::{Config, domain_name};
+use crate::forwarder::Forwarder;
+use crate::resolver::{Resolve, Resolver};
+use crate::upstream::Exchange;
+
+/// Picks where queries outside of the served zones go, by the longest configured
+/// suffix of the name they are for.
+pub struct Router {
+    routes: Vec<(DomainName<'static>, Option<Arc<dyn Resolve>>)>,
+
+    /// Forwarding or recursion for names without a route.
+    default: Option<Arc<dyn Resolve>>,
+}
+
+/// Where a query is sent.
+pub enum Route<'r> {
+    Resolve(&'r Arc<dyn Resolve>),
+
+    /// The name belongs to the served zones only, it doesn't exist if they don't
+    /// have it.
+    Local,
+
+    /// Nothing answers the query.
+    Refuse,
+}
+
+impl Router {
+    pub fn new(config: &Config, exchange: Arc<dyn Exchange>) -> Self {
+        let routes = config
+            .route
+            .iter()
+            .map(|route| {
+                let suffix = domain_name(&route.suffix).into_owned();
+                let resolver: Option<Arc<dyn Resolve>> = match route.upstreams.is_empty() {
+                    true => None,
+                    false => Some(Arc::new(Forwarder::new(
+                        &route.forward(),
+                        Arc::clone(&exchange),
+                    ))),
+                };
+
+                info!(
+                    "routing {} to {}",
+                    suffix,
+                    match resolver {
+                        Some(_) => format!("{:?}", route.upstreams),
+                        None => "served zones".to_string(),
+                    }
+                );
+                (suffix, resolver)
+            })
+            .collect();
+
+        let default: Option<Arc<dyn Resolve>> = match (&config.forward, &config.recursion) {
+            (Some(forward), _) => Some(Arc::new(Forwarder::new(forward, exchange))),
+            (None, Some(recursion)) => Some(Arc::new(Resolver::new(recursion, exchange))),
+            (None, None) => None,
+        };
+
+        Self { routes, default }
+    }
+
+    /// Whether any queries get resolved, for the RA bit.
+    pub fn resolves(&self) -> bool {
+        self.default.is_some() || self.routes.iter().any(|(_, route)| route.is_some())
+    }
+
+    pub fn route(&self, name: &DomainName) -> Route<'_> {
+        match self.matching(name) {
+            Some((_, Some(resolver))) => Route::Resolve(resolver),
+            Some((_, None)) => Route::Local,
+            None => match &self.default {
+                Some(resolver) => Route::Resolve(resolver),
+                None => Route::Refuse,
+            },
+        }
+    }
+
+    /// The longest configured suffix of `name`, if any route matches it.
+    pub fn suffix(&self, name: &DomainName) -> Option<&DomainName<'static>> {
+        self.matching(name).map(|(suffix, _)| suffix)
+    }
+
+    fn matching(
+        &self,
+        name: &DomainName,
+    ) -> Option<&(DomainName<'static>, Option<Arc<dyn Resolve>>)> {
+        self.routes
+            .iter()
+            .filter(|(suffix, _)| name.ends_with(suffix))
+            .max_by_key(|(suffix, _)| suffix.labels.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::upstream::Network;
+
+    use super::*;
+
+    /// A router for `config` in the format of the configuration file.
+    fn router(config: &str) -> Router {
+        let config: Config = toml::from_str(config).unwrap();
+        Router::new(&config, Arc::new(Network))
+    }
+
+    fn name(name: &str) -> DomainName<'static> {
+        name.parse().unwrap()
+    }
+
+    /// Whether `name` is resolved by `resolver`.
+    fn resolved_by(router: &Router, name: &str, resolver: &Arc<dyn Resolve>) -> bool {
+        matches!(
+            router.route(&self::name(name)),
+            Route::Resolve(route) if Arc::ptr_eq(route, resolver)
+        )
+    }
+
+    const ROUTES: &str = r#"
+        [forward]
+        upstreams = ["192.0.2.53:53"]
+
+        [[route]]
+        suffix = "example.com."
+        upstreams = ["192.0.2.1:53"]
+
+        [[route]]
+        suffix = "corp.example.com."
+        upstreams = ["192.0.2.2:53"]
+
+        [[route]]
+        suffix = "lab.corp.example.com"
+
+        [[route]]
+        suffix = "10.in-addr.arpa."
+    "#;
+
+    #[test]
+    fn longest_suffix_wins() {
+        let router = router(ROUTES);
+        let example = router.routes[0].1.as_ref().unwrap();
+        let corp = router.routes[1].1.as_ref().unwrap();
+
+        assert!(resolved_by(&router, "example.com", example));
+        assert!(resolved_by(&router, "www.example.com", example));
+        assert!(resolved_by(&router, "corp.example.com", corp));
+        assert!(resolved_by(&router, "host.corp.example.com", corp));
+        assert!(matches!(
+            router.route(&name("host.lab.corp.example.com")),
+            Route::Local
+        ));
+
+        assert_eq!(
+            router.suffix(&name("a.b.corp.example.com")),
+            Some(&name("corp.example.com"))
+        );
+        assert_eq!(router.suffix(&name("example.org")), None);
+
+        // suffixes match whole labels only
+        assert!(resolved_by(
+            &router,
+            "notexample.com",
+            router.default.as_ref().unwrap()
+        ));
+    }
+
+    #[test]
+    fn suffixes_match_regardless_of_case() {
+        let router = router(ROUTES);
+        let corp = router.routes[1].1.as_ref().unwrap();
+
+        assert!(resolved_by(&router, "Host.CORP.Example.com", corp));
+        assert!(matches!(
+            router.route(&name("1.2.3.10.IN-ADDR.ARPA")),
+            Route::Local
+        ));
+    }
+
+    #[test]
+    fn names_without_a_route_go_to_the_default() {
+        let router = router(ROUTES);
+        let default = router.default.as_ref().unwrap();
+
+        assert!(resolved_by(&router, "example.org", default));
+        assert!(resolved_by(&router, "com", default));
+        assert!(resolved_by(&router, "1.168.192.in-addr.arpa", default));
+        assert!(router.resolves());
+
+        // recursion when nothing is forwarded
+        let recursion = self::router("[recursion]\n");
+        assert!(resolved_by(
+            &recursion,
+            "example.org",
+            recursion.default.as_ref().unwrap()
+        ));
+    }
+
+    #[test]
+    fn local_routes_are_answered_from_the_zones_only() {
+        let router = router(
+            r#"
+            [[route]]
+            suffix = "home.arpa."
+            "#,
+        );
+
+        assert!(matches!(router.route(&name("home.arpa")), Route::Local));
+        assert!(matches!(router.route(&name("nas.home.arpa")), Route::Local));
+        assert!(!router.resolves());
+    }
+
+    #[test]
+    fn names_without_a_route_or_default_are_refused() {
+        let router = router(
+            r#"
+            [[route]]
+            suffix = "corp.example.com."
+            upstreams = ["192.0.2.2:53"]
+            "#,
+        );
+
+        assert!(matches!(router.route(&name("example.org")), Route::Refuse));
+        assert!(matches!(router.route(&name("example.com")), Route::Refuse));
+        assert!(matches!(
+            router.route(&name("www.corp.example.com")),
+            Route::Resolve(_)
+        ));
+        assert!(router.resolves());
+
+        assert!(!self::router("").resolves());
+    }
+}
@@ -0,0 +1,399 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use dns::{
+    Class, DomainName, Notify, OpCode, Packet, Question, RCode, Record, ResourceRecord, Type, tsig,
+};
+use log::debug;
+
+use crate::authority::{self, Authority};
+use crate::cache::{Cache, Refresh, Stale};
+use crate::handler::{self, Handler, HandlerError, Signer};
+use crate::notify::Secondaries;
+use crate::resolver::{Resolution, Resolve, ResolveError};
+use crate::router::{Route, Router};
+use crate::zone::Zone;
+
+/// How long a client waits for a resolution before it is given an expired answer if
+/// there is one, the client response timer of
+/// [RFC 8767 Section 5](https://www.rfc-editor.org/rfc/rfc8767#section-5).
+const CLIENT_RESPONSE_TIMER: Duration = Duration::from_millis(1800);
+
+/// Dispatches requests to the parts of the server responsible for their opcode.
+pub struct Server {
+    pub authority: Arc<Authority>,
+
+    /// Sends queries outside of the served zones on, by forwarding or recursion.
+    pub router: Router,
+
+    /// Answers of earlier resolutions, shared by all routes.
+    pub cache: Arc<Cache>,
+
+    /// Leaves out additional data that is not needed to use the answer.
+    pub minimal_responses: bool,
+
+    pub secondaries: Arc<Secondaries>,
+
+    /// Keys requests may be signed with.
+    pub keys: Arc<[tsig::Key]>,
+}
+
+impl Handler for Server {
+    fn handle<'a>(
+        &self,
+        request: Packet<'a>,
+        source: SocketAddr,
+        signer: Option<&Signer>,
+    ) -> Result<Packet<'a>, HandlerError> {
+        let id = request.header.id;
+        let opcode = request.header.opcode();
+
+        let response = match opcode {
+            OpCode::Notify => match Notify::try_from(request) {
+                Ok(notify) => self.secondaries.handle(notify, source, signer),
+                Err(rcode) => handler::error(id, opcode, rcode),
+            },
+            OpCode::Query if request.questions.len() != 1 => {
+                handler::error(id, opcode, RCode::FormatErr)
+            }
+            OpCode::Query => self.query(request)?,
+            _ => handler::error(id, opcode, RCode::NotImp),
+        };
+
+        Ok(response)
+    }
+
+    fn keyring(&self) -> &[tsig::Key] {
+        &self.keys
+    }
+
+    fn keys(&self, name: &DomainName) -> Vec<ResourceRecord<'static>> {
+        match self.authority.find(name) {
+            Some(zone) => zone.records(name, &Type::KEY),
+            None => Vec::new(),
+        }
+    }
+
+    /// Queries outside of the served zones that are resolved and not cached.
+    fn is_slow(&self, request: &Packet) -> bool {
+        let [question] = request.questions.as_slice() else {
+            return false;
+        };
+
+        request.header.opcode() == OpCode::Query
+            && request.header.recursion_desired()
+            && question.class == Class::IN
+            && self.zone(&question.name).is_none()
+            && matches!(self.router.route(&question.name), Route::Resolve(_))
+            && !self.cache.contains(question)
+    }
+}
+
+impl Server {
+    /// Answers a query from the served zones, or by resolving it as routed when the
+    /// client desires recursion.
+    fn query<'a>(&self, request: Packet<'a>) -> Result<Packet<'a>, HandlerError> {
+        let id = request.header.id;
+        let opcode = request.header.opcode();
+        let recursion_desired = request.header.recursion_desired();
+
+        let question = &request.questions[0];
+        let zone = match question.class {
+            Class::IN | Class::ANY => self.zone(&question.name),
+            _ => None,
+        };
+
+        let mut response = match zone {
+            Some(zone) => authority::answer(&zone, &request),
+            None if question.class != Class::IN => handler::error(id, opcode, RCode::Refused),
+            None => match self.router.route(&question.name) {
+                Route::Resolve(resolver) if recursion_desired => {
+                    let resolution = self.resolve(resolver, question)?;
+
+                    let mut response = handler::error(id, opcode, resolution.rcode);
+                    response.answers = resolution.answers;
+                    response.authorities = resolution.authorities;
+                    response
+                }
+                Route::Local => handler::error(id, opcode, RCode::NXDomain),
+                Route::Resolve(_) | Route::Refuse => handler::error(id, opcode, RCode::Refused),
+            },
+        };
+
+        if !self.minimal_responses {
+            self.add_addresses(&mut response);
+        }
+
+        response.header.set_recursion_desired(recursion_desired);
+        response
+            .header
+            .set_recursion_available(self.router.resolves());
+        response.questions = request.questions;
+
+        Ok(response)
+    }
+
+    /// The served zone `name` is answered from. Names below a route with a longer
+    /// suffix than the origin of the zone go where the route says, the most specific
+    /// of the two wins.
+    fn zone(&self, name: &DomainName) -> Option<Arc<Zone>> {
+        let zone = self.authority.find(name)?;
+        match self.router.suffix(name) {
+            Some(suffix) if suffix.labels.len() > zone.origin.labels.len() => None,
+            _ => Some(zone),
+        }
+    }
+
+    /// Adds the addresses of the names that MX, NS and SRV answers refer to as
+    /// additional data, as per [RFC 1035 Section 3.3](https://www.rfc-editor.org/rfc/rfc1035#section-3.3)
+    /// and [RFC 2782](https://www.rfc-editor.org/rfc/rfc2782). They come from the
+    /// served zones, or from the cache for names outside of them.
+    fn add_addresses(&self, response: &mut Packet) {
+        let mut targets: Vec<DomainName<'static>> = Vec::new();
+        for record in &response.answers {
+            let ResourceRecord::Record { data, .. } = record else {
+                continue;
+            };
+            let (Record::MX {
+                exchange: target, ..
+            }
+            | Record::NS { nsdname: target }
+            | Record::SRV { target, .. }) = data
+            else {
+                continue;
+            };
+
+            if !targets.contains(target) {
+                targets.push(target.clone().into_owned());
+            }
+        }
+
+        for target in targets {
+            // addresses that are already part of the response
+            let present = response
+                .answers
+                .iter()
+                .chain(&response.additionals)
+                .any(|record| is_address_of(record, &target));
+            if present {
+                continue;
+            }
+
+            let mut addresses = match self.zone(&target) {
+                Some(zone) => zone.addresses(&target),
+                None => Vec::new(),
+            };
+            if addresses.is_empty() {
+                addresses = self.cache.addresses(&target);
+            }
+
+            response.additionals.extend(addresses);
+        }
+    }
+
+    /// Answers `question` from the cache, or by resolving it. A cached answer that is
+    /// asked for often is refreshed shortly before it expires, and an expired one is
+    /// served as long as resolution fails, as per
+    /// [RFC 8767 Section 4](https://www.rfc-editor.org/rfc/rfc8767#section-4), or
+    /// takes longer than [`CLIENT_RESPONSE_TIMER`]. Queries for an answer that is
+    /// being refreshed, or failed to be a moment ago, get the expired one right away.
+    fn resolve(
+        &self,
+        resolver: &Arc<dyn Resolve>,
+        question: &Question,
+    ) -> Result<Resolution, ResolveError> {
+        if let Some(hit) = self.cache.get(question) {
+            if hit.prefetch {
+                self.prefetch(Arc::clone(resolver), question.clone().into_owned());
+            }
+            return Ok(hit.resolution);
+        }
+
+        let result = match self.cache.stale(question) {
+            Some(Stale::Serve(resolution)) => {
+                debug!("serving stale answer for {} until refreshed", question.name);
+                return Ok(resolution);
+            }
+            Some(Stale::Refresh(refresh)) => {
+                let resolution =
+                    self.refresh(Arc::clone(resolver), question.clone().into_owned(), refresh);
+                match resolution.recv_timeout(CLIENT_RESPONSE_TIMER) {
+                    Ok(result) => result,
+                    Err(_) => match self.cache.get_stale(question) {
+                        Some(resolution) => {
+                            debug!(
+                                "serving stale answer for {} while resolving it",
+                                question.name
+                            );
+                            return Ok(resolution);
+                        }
+                        None => resolution.recv().expect("resolution thread panicked"),
+                    },
+                }
+            }
+            None => {
+                let result = resolver.resolve(question);
+                if let Ok(resolution) = &result {
+                    self.cache.insert(question, resolution);
+                }
+                result
+            }
+        };
+
+        match result {
+            Ok(resolution) => Ok(resolution),
+            Err(err) => match self.cache.get_stale(question) {
+                Some(resolution) => {
+                    debug!("serving stale answer for {}: {}", question.name, err);
+                    Ok(resolution)
+                }
+                None => Err(err),
+            },
+        }
+    }
+
+    /// Resolves `question` in the background to replace its expired answer, which the
+    /// client may be given instead if the resolution is slow. The result is sent once
+    /// it is cached.
+    fn refresh(
+        &self,
+        resolver: Arc<dyn Resolve>,
+        question: Question<'static>,
+        refresh: Refresh,
+    ) -> Receiver<Result<Resolution, ResolveError>> {
+        let cache = Arc::clone(&self.cache);
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let result = resolver.resolve(&question);
+            if let Ok(resolution) = &result {
+                cache.insert(&question, resolution);
+            }
+            cache.refreshed(refresh, result.is_ok());
+            // the client may have been answered already
+            sender.send(result).ok();
+        });
+
+        receiver
+    }
+
+    /// Resolves `question` again in the background to refresh its cached answer.
+    fn prefetch(&self, resolver: Arc<dyn Resolve>, question: Question<'static>) {
+        let cache = Arc::clone(&self.cache);
+
+        thread::spawn(move || {
+            debug!("prefetching {} {}", question.name, question.r#type);
+            match resolver.resolve(&question) {
+                Ok(resolution) => cache.insert(&question, &resolution),
+                Err(err) => debug!("prefetching {} failed: {}", question.name, err),
+            }
+            cache.prefetched();
+        });
+    }
+}
+
+fn is_address_of(record: &ResourceRecord, target: &DomainName) -> bool {
+    matches!(
+        record,
+        ResourceRecord::Record {
+            name,
+            data: Record::A { .. } | Record::AAAA { .. },
+            ..
+        } if name == target
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::cache::STALE_TTL;
+    use crate::config::{self, Config};
+    use crate::upstream::Network;
+
+    use super::*;
+
+    const MAX_STALE: u32 = 60;
+
+    /// Fails to resolve anything, counting the attempts.
+    #[derive(Default)]
+    struct Failing {
+        attempts: AtomicUsize,
+    }
+
+    impl Resolve for Failing {
+        fn resolve(&self, _question: &Question) -> Result<Resolution, ResolveError> {
+            self.attempts.fetch_add(1, Ordering::Relaxed);
+            Err(ResolveError::Upstreams)
+        }
+    }
+
+    fn server() -> Server {
+        let authority = Arc::new(Authority::default());
+        let keys: Arc<[tsig::Key]> = Arc::from([]);
+        let config: Config = toml::from_str("").unwrap();
+
+        Server {
+            router: Router::new(&config, Arc::new(Network)),
+            cache: Arc::new(Cache::new(&config::Cache {
+                max_stale: MAX_STALE,
+                ..Default::default()
+            })),
+            minimal_responses: true,
+            secondaries: Secondaries::new(Vec::new(), Arc::clone(&keys), Arc::clone(&authority)),
+            authority,
+            keys,
+        }
+    }
+
+    fn ttls(resolution: &Resolution) -> Vec<u32> {
+        resolution
+            .answers
+            .iter()
+            .filter_map(|record| match record {
+                ResourceRecord::Record { ttl, .. } => Some(*ttl),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn expired_answers_are_served_while_resolution_fails() {
+        let server = server();
+        let failing = Arc::new(Failing::default());
+        let resolver: Arc<dyn Resolve> = failing.clone();
+        let question = Question {
+            name: "www.example.com".parse().unwrap(),
+            r#type: Type::A,
+            class: Class::IN,
+        };
+        let address =
+            ResourceRecord::address(question.name.clone(), 300, "192.0.2.1".parse().unwrap());
+        server.cache.insert(
+            &question,
+            &Resolution {
+                rcode: RCode::NoError,
+                answers: vec![address],
+                authorities: Vec::new(),
+            },
+        );
+        server.cache.age(Duration::from_secs(301));
+
+        let resolution = server.resolve(&resolver, &question).unwrap();
+        assert_eq!(ttls(&resolution), [STALE_TTL]);
+        assert_eq!(failing.attempts.load(Ordering::Relaxed), 1);
+
+        // the failed refresh isn't retried right away
+        let resolution = server.resolve(&resolver, &question).unwrap();
+        assert_eq!(ttls(&resolution), [STALE_TTL]);
+        assert_eq!(failing.attempts.load(Ordering::Relaxed), 1);
+        assert_eq!(server.cache.stats().stale, 2);
+
+        server.cache.age(Duration::from_secs(u64::from(MAX_STALE)));
+        assert!(server.resolve(&resolver, &question).is_err());
+        assert_eq!(failing.attempts.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,615 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, Sender, SyncSender, channel, sync_channel};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use dns::{
+    OpCode,
+    dso::{Dso, Outcome, Session},
+    proto::{Parse, Parser, Serialize, Serializer},
+};
+use log::{debug, error, info, warn};
+
+use crate::config::Tcp;
+use crate::handler::{self, Handler, Transport};
+
+/// Requests of a connection read but not answered yet before reading from it pauses.
+/// They are answered as soon as they are done, possibly out of order.
+const PIPELINE: usize = 16;
+
+/// Threads answering the requests of all connections that need resolving, so that
+/// these don't hold up the ones answered from the zones or the cache.
+const RESOLUTION_WORKERS: usize = 64;
+
+/// How often an idle connection checks its timeouts.
+const TICK: Duration = Duration::from_secs(1);
+
+/// Time a message may take to arrive once its first byte was read, so clients
+/// sending a byte at a time can't hold on to a connection forever.
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Accepts DNS over TCP connections on `addr` as per
+/// [RFC 7766](https://www.rfc-editor.org/rfc/rfc7766), handling requests with
+/// `handler`.
+pub fn listen(addr: SocketAddr, handler: Arc<dyn Handler>, config: Tcp) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!("listening on {} over tcp", addr);
+
+    accept(listener, handler, config);
+    Ok(())
+}
+
+/// Accepts connections on `listener` in the background.
+fn accept(listener: TcpListener, handler: Arc<dyn Handler>, config: Tcp) {
+    let connections = Arc::new(AtomicUsize::new(0));
+    let jobs = Jobs {
+        answers: pool(Arc::clone(&handler), config.workers),
+        resolutions: pool(Arc::clone(&handler), RESOLUTION_WORKERS),
+        handler,
+    };
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    error!("failed to accept connection: {}", err);
+                    continue;
+                }
+            };
+
+            if connections.fetch_add(1, Ordering::SeqCst) >= config.max_connections {
+                connections.fetch_sub(1, Ordering::SeqCst);
+                if let Ok(peer) = stream.peer_addr() {
+                    warn!(
+                        "refusing connection from {}, limit of {} reached",
+                        peer, config.max_connections
+                    );
+                }
+                continue;
+            }
+
+            let jobs = jobs.clone();
+            let connections = Arc::clone(&connections);
+            let idle_timeout = Duration::from_secs(config.idle_timeout);
+
+            thread::spawn(move || {
+                if let Err(err) = Connection::new(stream, idle_timeout).and_then(|c| c.serve(&jobs))
+                {
+                    debug!("connection closed: {}", err);
+                }
+
+                connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+}
+
+/// A request and the connection its response goes out on.
+type Job = (Vec<u8>, Arc<Shared>);
+
+/// The queues of the worker pools requests are handed to.
+#[derive(Clone)]
+struct Jobs {
+    handler: Arc<dyn Handler>,
+
+    /// Requests answered right away.
+    answers: SyncSender<Job>,
+
+    /// Requests that need resolving, see [`Handler::is_slow`].
+    resolutions: SyncSender<Job>,
+}
+
+impl Jobs {
+    /// Hands `request` to the pool that answers it, fails once the pools are gone.
+    fn send(&self, request: Vec<u8>, connection: Arc<Shared>) -> Result<(), ()> {
+        let queue = match handler::is_slow(self.handler.as_ref(), &request) {
+            true => &self.resolutions,
+            false => &self.answers,
+        };
+        queue.send((request, connection)).map_err(|_| ())
+    }
+}
+
+/// Starts `workers` threads answering the requests of all connections with
+/// `handler`, and returns the queue they take them from.
+fn pool(handler: Arc<dyn Handler>, workers: usize) -> SyncSender<Job> {
+    let (jobs, queue) = sync_channel::<Job>(workers.max(1) * PIPELINE);
+    let queue = Arc::new(Mutex::new(queue));
+
+    for _ in 0..workers.max(1) {
+        let worker = Worker {
+            handler: Arc::clone(&handler),
+            queue: Arc::clone(&queue),
+        };
+        thread::spawn(move || worker.run());
+    }
+
+    jobs
+}
+
+/// The parts of a connection the workers answering its requests use.
+struct Shared {
+    peer: SocketAddr,
+
+    /// Messages to send, written by the writer thread of the connection.
+    writer: Sender<Vec<u8>>,
+    start: Instant,
+
+    /// Milliseconds since `start` at which the connection was last used.
+    last_activity: AtomicU64,
+
+    /// Requests read but not answered yet.
+    pending: Mutex<usize>,
+
+    /// Signaled whenever a request was answered.
+    answered: Condvar,
+}
+
+impl Shared {
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    fn pending(&self) -> usize {
+        *self.pending.lock().unwrap()
+    }
+
+    /// Waits until fewer than `limit` requests are pending and adds one more.
+    fn begin(&self, limit: usize) {
+        let mut pending = self.pending.lock().unwrap();
+        while *pending >= limit {
+            pending = self.answered.wait(pending).unwrap();
+        }
+        *pending += 1;
+    }
+
+    fn end(&self) {
+        self.last_activity.store(self.now(), Ordering::SeqCst);
+        *self.pending.lock().unwrap() -= 1;
+        self.answered.notify_all();
+    }
+
+    /// Waits until all requests read are answered.
+    fn drain(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        while *pending > 0 {
+            pending = self.answered.wait(pending).unwrap();
+        }
+    }
+}
+
+struct Connection {
+    reader: TcpStream,
+    idle_timeout: Duration,
+    shared: Arc<Shared>,
+    writer: JoinHandle<()>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream, idle_timeout: Duration) -> io::Result<Self> {
+        stream.set_read_timeout(Some(TICK))?;
+        stream.set_write_timeout(Some(idle_timeout.max(TICK)))?;
+        stream.set_nodelay(true)?;
+
+        let peer = stream.peer_addr()?;
+        let (writer, messages) = channel();
+        let mut output = stream.try_clone()?;
+
+        Ok(Self {
+            shared: Arc::new(Shared {
+                peer,
+                writer,
+                start: Instant::now(),
+                last_activity: AtomicU64::new(0),
+                pending: Mutex::new(0),
+                answered: Condvar::new(),
+            }),
+            reader: stream,
+            idle_timeout,
+            writer: thread::spawn(move || write(&mut output, peer, &messages)),
+        })
+    }
+
+    fn peer(&self) -> SocketAddr {
+        self.shared.peer
+    }
+
+    fn now(&self) -> u64 {
+        self.shared.now()
+    }
+
+    /// Reads requests until the client closes the connection or it times out, while
+    /// the workers of `jobs` answer them.
+    fn serve(mut self, jobs: &Jobs) -> io::Result<()> {
+        debug!("connection from {}", self.peer());
+
+        let timeout = self.idle_timeout.as_millis() as u32;
+        let mut session = Session::new(timeout, timeout, self.now());
+        let mut frames = Frames::default();
+
+        let result = loop {
+            let request = match frames.next(&mut self.reader) {
+                Ok(Some(request)) => request,
+                Ok(None) => break Ok(()),
+                Err(err) if frames.overdue() => {
+                    debug!("message from {} incomplete in time", self.peer());
+                    break Err(err);
+                }
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    if self.timed_out(&mut session)? {
+                        break Ok(());
+                    }
+                    continue;
+                }
+                Err(err) => break Err(err),
+            };
+
+            let now = self.now();
+            self.shared.last_activity.store(now, Ordering::SeqCst);
+
+            // DSO messages manage the session itself and are no regular requests
+            if request.len() > 2 && OpCode::from((request[2] >> 3) & 0b1111) == OpCode::DSO {
+                if !self.handle_dso(&mut session, &request, now)? {
+                    break Ok(());
+                }
+                continue;
+            }
+
+            session.activity(now);
+            self.shared.begin(PIPELINE);
+            if jobs.send(request, Arc::clone(&self.shared)).is_err() {
+                self.shared.end();
+                break Ok(());
+            }
+        };
+
+        // answer the requests already read and send the responses before closing
+        self.shared.drain();
+        drop(self.shared);
+        self.writer.join().ok();
+        self.reader.shutdown(Shutdown::Both).ok();
+
+        result
+    }
+
+    /// Checks the idle timeout, or the DSO inactivity timeout once a session is
+    /// established. Returns whether the connection should be closed.
+    fn timed_out(&self, session: &mut Session) -> io::Result<bool> {
+        if session.is_established() {
+            return match session.poll(self.now()) {
+                Outcome::Respond(message) => {
+                    write_dso(&self.shared.writer, message)?;
+                    Ok(false)
+                }
+                Outcome::Ignore => Ok(false),
+                Outcome::Close => {
+                    debug!("dso session with {} timed out", self.peer());
+                    Ok(true)
+                }
+            };
+        }
+
+        let idle = self
+            .now()
+            .saturating_sub(self.shared.last_activity.load(Ordering::SeqCst));
+        if self.shared.pending() == 0 && idle > self.idle_timeout.as_millis() as u64 {
+            debug!("connection from {} idle, closing", self.peer());
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Handles a DSO message, returns whether the connection stays open.
+    fn handle_dso(&self, session: &mut Session, request: &[u8], now: u64) -> io::Result<bool> {
+        // malformed DSO messages are fatal as per RFC 8490 Section 5.4
+        let message = match Dso::parse(&mut Parser::new(request)) {
+            Ok(message) => message,
+            Err(err) => {
+                warn!("invalid dso message from {}: {}", self.peer(), err);
+                return Ok(false);
+            }
+        };
+
+        match session.handle(&message, now) {
+            Outcome::Respond(response) => {
+                write_dso(&self.shared.writer, response)?;
+                Ok(true)
+            }
+            Outcome::Ignore => Ok(true),
+            Outcome::Close => {
+                warn!("dso protocol error from {}, closing", self.peer());
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Answers requests of any connection taken from the queue shared by the pool.
+struct Worker {
+    handler: Arc<dyn Handler>,
+    queue: Arc<Mutex<Receiver<Job>>>,
+}
+
+impl Worker {
+    fn run(self) {
+        let mut response = vec![0; usize::from(u16::MAX)];
+
+        loop {
+            let (request, connection) = match self.queue.lock().unwrap().recv() {
+                Ok(job) => job,
+                Err(_) => return,
+            };
+
+            if let Some(len) = handler::respond(
+                self.handler.as_ref(),
+                &request,
+                connection.peer,
+                Transport::Tcp,
+                &mut response,
+            ) && write_frame(&connection.writer, &response[..len]).is_err()
+            {
+                debug!(
+                    "connection to {} closed before its response",
+                    connection.peer
+                );
+            }
+
+            connection.end();
+        }
+    }
+}
+
+/// Writes the messages of a connection in the order they are queued, until all
+/// senders are gone.
+///
+/// A client that doesn't read its responses in time loses the connection on the
+/// first write that times out, so that it holds up neither workers nor memory.
+fn write(stream: &mut TcpStream, peer: SocketAddr, messages: &Receiver<Vec<u8>>) {
+    for frame in messages {
+        if let Err(err) = stream.write_all(&frame) {
+            warn!("failed to send response to {}, closing: {}", peer, err);
+            stream.shutdown(Shutdown::Both).ok();
+            return;
+        }
+    }
+}
+
+fn write_dso(writer: &Sender<Vec<u8>>, message: Dso) -> io::Result<()> {
+    let mut buf = [0; 4096];
+    let len = message
+        .serialize(&mut Serializer::new(&mut buf))
+        .map_err(|err| io::Error::other(format!("{:?}", err)))?;
+
+    write_frame(writer, &buf[..len])
+}
+
+/// Queues `message` prefixed with its length for the writer of the connection, fails
+/// once the connection is closed.
+fn write_frame(writer: &Sender<Vec<u8>>, message: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(message.len() + 2);
+    frame.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    frame.extend_from_slice(message);
+
+    writer.send(frame).map_err(|_| ErrorKind::BrokenPipe.into())
+}
+
+/// Splits the bytes read from a connection into length prefixed messages, keeping
+/// partial messages across read timeouts.
+#[derive(Default)]
+struct Frames {
+    buf: Vec<u8>,
+
+    /// When the first byte of the partial message in `buf` was read.
+    started: Option<Instant>,
+}
+
+impl Frames {
+    /// Reads the next message, or `None` once the client closed the connection.
+    /// Fails once a message takes longer than [`MESSAGE_TIMEOUT`] to arrive.
+    fn next(&mut self, stream: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(&[high, low]) = self.buf.first_chunk() {
+                let len = usize::from(u16::from_be_bytes([high, low]));
+                if self.buf.len() >= 2 + len {
+                    let message = self.buf[2..2 + len].to_vec();
+                    self.buf.drain(..2 + len);
+                    self.started = (!self.buf.is_empty()).then(Instant::now);
+                    return Ok(Some(message));
+                }
+            }
+
+            if self.overdue() {
+                return Err(ErrorKind::TimedOut.into());
+            }
+
+            let mut chunk = [0; 4096];
+            let len = stream.read(&mut chunk)?;
+            if len == 0 {
+                return Ok(None);
+            }
+
+            self.buf.extend_from_slice(&chunk[..len]);
+            self.started.get_or_insert_with(Instant::now);
+        }
+    }
+
+    /// Whether the partial message took too long to arrive.
+    fn overdue(&self) -> bool {
+        self.started
+            .is_some_and(|started| started.elapsed() > MESSAGE_TIMEOUT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use dns::{Class, Packet, Question, RCode, Type};
+
+    use super::*;
+    use crate::handler::{HandlerError, Signer};
+
+    /// Hands out the bytes of a connection in the chunks given, a chunk of `None`
+    /// being a read that timed out.
+    struct Script(VecDeque<Option<Vec<u8>>>);
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.pop_front() {
+                Some(Some(chunk)) => {
+                    buf[..chunk.len()].copy_from_slice(&chunk);
+                    Ok(chunk.len())
+                }
+                Some(None) => Err(ErrorKind::WouldBlock.into()),
+                None => Ok(0),
+            }
+        }
+    }
+
+    fn script(chunks: &[Option<&[u8]>]) -> Script {
+        Script(
+            chunks
+                .iter()
+                .map(|chunk| chunk.map(<[u8]>::to_vec))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn frames_with_a_split_prefix() {
+        let mut stream = script(&[Some(&[0]), None, Some(&[3, b'a']), Some(b"bc")]);
+        let mut frames = Frames::default();
+
+        // the read timing out in between keeps the partial message
+        assert_eq!(
+            frames.next(&mut stream).unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+        assert_eq!(frames.next(&mut stream).unwrap().unwrap(), b"abc");
+        assert!(frames.next(&mut stream).unwrap().is_none());
+    }
+
+    #[test]
+    fn frames_with_several_messages_in_one_read() {
+        let mut stream = script(&[
+            Some(&[0, 1, b'a', 0, 0, 0, 2, b'b', b'c', 0]),
+            Some(&[1, b'd']),
+        ]);
+        let mut frames = Frames::default();
+
+        assert_eq!(frames.next(&mut stream).unwrap().unwrap(), b"a");
+        assert_eq!(frames.next(&mut stream).unwrap().unwrap(), b"");
+        assert_eq!(frames.next(&mut stream).unwrap().unwrap(), b"bc");
+        assert!(frames.started.is_some());
+        assert_eq!(frames.next(&mut stream).unwrap().unwrap(), b"d");
+        assert!(frames.started.is_none());
+        assert!(frames.next(&mut stream).unwrap().is_none());
+    }
+
+    #[test]
+    fn frames_taking_too_long() {
+        let mut stream = script(&[Some(&[0, 5, b'a']), None, Some(b"b")]);
+        let mut frames = Frames::default();
+
+        assert_eq!(
+            frames.next(&mut stream).unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+        assert!(!frames.overdue());
+
+        frames.started = Some(Instant::now() - MESSAGE_TIMEOUT - TICK);
+        assert!(frames.overdue());
+        assert_eq!(
+            frames.next(&mut stream).unwrap_err().kind(),
+            ErrorKind::TimedOut
+        );
+    }
+
+    /// Answers questions for names starting with "slow" after a while, and all others
+    /// right away.
+    struct Delays;
+
+    impl Delays {
+        fn slow(request: &Packet) -> bool {
+            request.questions[0].name.labels[0].starts_with("slow")
+        }
+    }
+
+    impl Handler for Delays {
+        fn handle<'a>(
+            &self,
+            request: Packet<'a>,
+            _source: SocketAddr,
+            _signer: Option<&Signer>,
+        ) -> Result<Packet<'a>, HandlerError> {
+            if Self::slow(&request) {
+                thread::sleep(Duration::from_millis(300));
+            }
+
+            let mut response = handler::error(0, OpCode::Query, RCode::NoError);
+            response.questions = request.questions;
+            Ok(response)
+        }
+
+        fn is_slow(&self, request: &Packet) -> bool {
+            Self::slow(request)
+        }
+    }
+
+    fn frame(id: u16, name: &str) -> Vec<u8> {
+        let mut request = handler::error(id, OpCode::Query, RCode::NoError);
+        request.header.set_qr(dns::QR::Query);
+        request.header.qdcount = 1;
+        request.questions.push(Question {
+            name: name.parse().unwrap(),
+            r#type: Type::A,
+            class: Class::IN,
+        });
+
+        let mut buf = vec![0; 512];
+        let len = request.serialize(&mut Serializer::new(&mut buf)).unwrap();
+        let mut frame = (len as u16).to_be_bytes().to_vec();
+        frame.extend_from_slice(&buf[..len]);
+        frame
+    }
+
+    #[test]
+    fn pipelined_requests_are_answered_as_they_are_done() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // a single worker, that a slow request would hold up
+        let config = Tcp {
+            workers: 1,
+            ..Tcp::default()
+        };
+        accept(listener, Arc::new(Delays), config);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut requests = frame(1, "slow.example.com");
+        requests.extend(frame(2, "fast.example.com"));
+        requests.extend(frame(3, "fast.example.com"));
+        stream.write_all(&requests).unwrap();
+
+        let mut frames = Frames::default();
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let response = frames.next(&mut stream).unwrap().unwrap();
+            let response = Packet::parse(&mut Parser::new(&response)).unwrap();
+            assert_eq!(response.questions.len(), 1);
+            ids.push(response.header.id);
+        }
+        assert_eq!(ids, [2, 3, 1]);
+
+        // the connection closes once the client is done
+        stream.shutdown(Shutdown::Write).unwrap();
+        assert!(frames.next(&mut stream).unwrap().is_none());
+    }
+}
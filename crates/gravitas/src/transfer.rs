@@ -0,0 +1,161 @@
+use std::fmt::Display;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use dns::{
+    Class, DomainName, Header, Packet, Question, RCode, Record, ResourceRecord, Type,
+    proto::{Parse, Parser, Serialize, Serializer},
+    tsig::{self, StreamVerifier, TsigError},
+};
+use log::debug;
+
+use crate::handler::unix_time;
+use crate::zone::{Zone, ZoneError};
+
+/// Timeout of connecting to the primary and of each read while the zone comes in.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum TransferError {
+    Io(io::Error),
+    Refused(RCode),
+    Tsig(TsigError),
+    Invalid(String),
+    Zone(ZoneError),
+}
+
+impl Display for TransferError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Refused(rcode) => write!(f, "primary answered {:?}", rcode),
+            Self::Tsig(err) => write!(f, "signature of the transfer: {}", err),
+            Self::Invalid(message) => write!(f, "invalid transfer: {}", message),
+            Self::Zone(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<io::Error> for TransferError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<TsigError> for TransferError {
+    fn from(err: TsigError) -> Self {
+        Self::Tsig(err)
+    }
+}
+
+/// Transfers the zone `origin` from `primary` over TCP, as per
+/// [RFC 5936](https://www.rfc-editor.org/rfc/rfc5936).
+///
+/// With a `key` the request is signed and every message of the response has to be
+/// covered by a signature, as per
+/// [RFC 8945 Section 5.3.1](https://www.rfc-editor.org/rfc/rfc8945#section-5.3.1).
+pub fn transfer(
+    origin: &DomainName<'static>,
+    primary: SocketAddr,
+    key: Option<&tsig::Key>,
+) -> Result<Zone, TransferError> {
+    let id = rand::random();
+    let query = Packet {
+        header: Header {
+            id,
+            qdcount: 1,
+            ..Default::default()
+        },
+        questions: vec![Question {
+            name: origin.clone(),
+            r#type: Type::AXFR,
+            class: Class::IN,
+        }],
+        answers: Vec::new(),
+        authorities: Vec::new(),
+        additionals: Vec::new(),
+    };
+
+    let mut request = vec![0; query.size()];
+    let len = query
+        .serialize(&mut Serializer::new(&mut request))
+        .map_err(|err| TransferError::Invalid(format!("{:?}", err)))?;
+    request.truncate(len);
+
+    let mut verifier = None;
+    if let Some(key) = key {
+        let mac = tsig::sign(&mut request, key, None, unix_time(), tsig::DEFAULT_FUDGE)?;
+        verifier = Some(StreamVerifier::new(key, &mac));
+    }
+
+    let mut stream = TcpStream::connect_timeout(&primary, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut frame = Vec::with_capacity(request.len() + 2);
+    frame.extend_from_slice(&(request.len() as u16).to_be_bytes());
+    frame.extend_from_slice(&request);
+    stream.write_all(&frame)?;
+
+    // the zone starts and ends with its SOA, as per RFC 5936 Section 2.2
+    let mut records: Vec<ResourceRecord<'static>> = Vec::new();
+    let mut messages = 0;
+    loop {
+        let mut len = [0; 2];
+        stream.read_exact(&mut len)?;
+        let mut message = vec![0; usize::from(u16::from_be_bytes(len))];
+        stream.read_exact(&mut message)?;
+        messages += 1;
+
+        if let Some(verifier) = &mut verifier {
+            verifier.verify(&message, unix_time())?;
+        }
+
+        let response = Packet::parse(&mut Parser::new(&message))
+            .map_err(|err| TransferError::Invalid(err.to_string()))?;
+        if response.header.id != id {
+            return Err(TransferError::Invalid(format!(
+                "unexpected id {}",
+                response.header.id
+            )));
+        }
+        if response.header.rcode() != RCode::NoError {
+            return Err(TransferError::Refused(response.header.rcode()));
+        }
+
+        for record in response.answers {
+            let soa = is_soa(&record);
+            if records.is_empty() && !soa {
+                return Err(TransferError::Invalid(
+                    "zone doesn't start with its soa".into(),
+                ));
+            }
+            if soa && !records.is_empty() {
+                if let Some(verifier) = &verifier {
+                    verifier.finish()?;
+                }
+
+                debug!(
+                    "transferred {} records of {} in {} messages",
+                    records.len(),
+                    origin,
+                    messages
+                );
+                return Zone::new(origin.clone(), records).map_err(TransferError::Zone);
+            }
+
+            records.push(record.into_owned());
+        }
+    }
+}
+
+fn is_soa(record: &ResourceRecord) -> bool {
+    matches!(
+        record,
+        ResourceRecord::Record {
+            data: Record::SOA { .. },
+            ..
+        }
+    )
+}
@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use dns::{
+    Header, Packet, Question, ResourceRecord,
+    proto::{Parse, Parser, Serialize, Serializer},
+};
+use log::debug;
+
+use crate::handler::Transport;
+
+/// Payload size advertised to other servers, small enough to avoid fragmentation as
+/// recommended by DNS Flag Day 2020.
+pub const UDP_SIZE: u16 = 1232;
+
+/// Attempts at binding a random source port before leaving the choice to the system.
+const BIND_ATTEMPTS: usize = 8;
+
+/// Round trip time assumed for servers that weren't asked yet, at most. Each server
+/// starts out with a random value below it, so that all of them get tried eventually.
+const INITIAL_RTT: Duration = Duration::from_millis(50);
+
+/// Round trip time a server is penalized up to while it doesn't respond.
+const MAX_RTT: Duration = Duration::from_secs(10);
+
+/// Sends queries to other name servers. Resolution only goes through this, so it can
+/// also run against servers that aren't on the network.
+pub trait Exchange: Send + Sync {
+    /// Sends `query` to `server` over `transport` and returns the first response with
+    /// the same id received within `timeout`.
+    fn exchange(
+        &self,
+        query: &[u8],
+        server: SocketAddr,
+        transport: Transport,
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>>;
+}
+
+/// Exchanges messages with name servers over the network.
+///
+/// Every UDP query is sent from a new socket on a random port, which together with
+/// the random id makes spoofed responses hard to get accepted as per
+/// [RFC 5452 Section 9.2](https://www.rfc-editor.org/rfc/rfc5452#section-9.2).
+pub struct Network;
+
+impl Exchange for Network {
+    fn exchange(
+        &self,
+        query: &[u8],
+        server: SocketAddr,
+        transport: Transport,
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        match transport {
+            Transport::Udp => exchange_udp(query, server, timeout),
+            Transport::Tcp => exchange_tcp(query, server, timeout),
+        }
+    }
+}
+
+fn exchange_udp(query: &[u8], server: SocketAddr, timeout: Duration) -> io::Result<Vec<u8>> {
+    let socket = bind_random(server)?;
+    socket.connect(server)?;
+    socket.send(query)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut buf = vec![0; usize::from(u16::MAX)];
+    loop {
+        let remaining = deadline
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+            .ok_or(ErrorKind::TimedOut)?;
+        socket.set_read_timeout(Some(remaining))?;
+
+        let len = socket.recv(&mut buf)?;
+        if len >= 2 && buf[..2] == query[..2] {
+            buf.truncate(len);
+            return Ok(buf);
+        }
+    }
+}
+
+/// A UDP socket on a random port, of the address family of `server`.
+fn bind_random(server: SocketAddr) -> io::Result<UdpSocket> {
+    let local: IpAddr = match server {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+
+    for _ in 0..BIND_ATTEMPTS {
+        if let Ok(socket) = UdpSocket::bind((local, rand::random_range(1024..=u16::MAX))) {
+            return Ok(socket);
+        }
+    }
+
+    UdpSocket::bind((local, 0))
+}
+
+fn exchange_tcp(query: &[u8], server: SocketAddr, timeout: Duration) -> io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect_timeout(&server, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut frame = Vec::with_capacity(query.len() + 2);
+    frame.extend_from_slice(&(query.len() as u16).to_be_bytes());
+    frame.extend_from_slice(query);
+    stream.write_all(&frame)?;
+
+    let mut len = [0; 2];
+    stream.read_exact(&mut len)?;
+    let mut buf = vec![0; usize::from(u16::from_be_bytes(len))];
+    stream.read_exact(&mut buf)?;
+
+    Ok(buf)
+}
+
+#[derive(Debug)]
+pub enum UpstreamError {
+    Io(io::Error),
+    Serialize,
+    Parse(String),
+
+    /// The response doesn't answer the question that was asked.
+    Mismatch,
+}
+
+impl Display for UpstreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Serialize => write!(f, "couldn't serialize query"),
+            Self::Parse(err) => write!(f, "invalid response: {}", err),
+            Self::Mismatch => write!(f, "response doesn't match the query"),
+        }
+    }
+}
+
+impl std::error::Error for UpstreamError {}
+
+/// A query for `question` with a random id, advertising [`UDP_SIZE`] with EDNS.
+pub fn query(question: Question<'static>, recursion_desired: bool) -> Packet<'static> {
+    let mut header = Header {
+        id: rand::random(),
+        qdcount: 1,
+        arcount: 1,
+        ..Default::default()
+    };
+    header.set_recursion_desired(recursion_desired);
+
+    Packet {
+        header,
+        questions: vec![question],
+        answers: Vec::new(),
+        authorities: Vec::new(),
+        additionals: vec![ResourceRecord::OPTRecord {
+            size: UDP_SIZE,
+            flags: 0,
+            options: Vec::new(),
+        }],
+    }
+}
+
+/// Sends `query` to `server`, repeating it over TCP if the response is truncated as
+/// per [RFC 7766 Section 5](https://www.rfc-editor.org/rfc/rfc7766#section-5).
+///
+/// Only a response with the id and question of the query is accepted, as per
+/// [RFC 5452 Section 9.1](https://www.rfc-editor.org/rfc/rfc5452#section-9.1). The
+/// round trip time is recorded in `rtt`.
+pub fn ask(
+    exchange: &dyn Exchange,
+    query: &Packet<'static>,
+    server: SocketAddr,
+    timeout: Duration,
+    rtt: &Rtt,
+) -> Result<Packet<'static>, UpstreamError> {
+    let mut buf = [0; 512];
+    let len = query
+        .clone()
+        .serialize(&mut Serializer::new(&mut buf))
+        .map_err(|_| UpstreamError::Serialize)?;
+
+    let mut transport = Transport::Udp;
+    loop {
+        let start = Instant::now();
+        let response = match exchange.exchange(&buf[..len], server, transport, timeout) {
+            Ok(response) => response,
+            Err(err) => {
+                rtt.failure(server.ip());
+                return Err(UpstreamError::Io(err));
+            }
+        };
+        rtt.sample(server.ip(), start.elapsed());
+
+        let response = Packet::parse(&mut Parser::new(&response))
+            .map_err(|err| UpstreamError::Parse(err.to_string()))?
+            .into_owned();
+
+        if !matches(query, &response) {
+            return Err(UpstreamError::Mismatch);
+        }
+
+        if response.header.truncated() && transport == Transport::Udp {
+            debug!("truncated response from {}, retrying over tcp", server);
+            transport = Transport::Tcp;
+            continue;
+        }
+
+        return Ok(response);
+    }
+}
+
+/// Checks that `response` is a response to `query`, for the same question.
+fn matches(query: &Packet, response: &Packet) -> bool {
+    let [question] = query.questions.as_slice() else {
+        return false;
+    };
+
+    response.header.is_response()
+        && response.header.id == query.header.id
+        && response.header.opcode() == query.header.opcode()
+        && matches!(
+            response.questions.as_slice(),
+            [answered] if answered.name == question.name
+                && answered.r#type == question.r#type
+                && answered.class == question.class
+        )
+}
+
+/// Smoothed round trip times of servers, used to prefer the ones that answer fastest.
+#[derive(Default)]
+pub struct Rtt {
+    servers: Mutex<HashMap<IpAddr, Duration>>,
+}
+
+impl Rtt {
+    /// The expected round trip time of `server`.
+    pub fn estimate(&self, server: IpAddr) -> Duration {
+        *self
+            .servers
+            .lock()
+            .unwrap()
+            .entry(server)
+            .or_insert_with(|| rand::random_range(Duration::ZERO..INITIAL_RTT))
+    }
+
+    /// Adds a measured round trip time, weighted like the SRTT of
+    /// [RFC 6298 Section 2](https://www.rfc-editor.org/rfc/rfc6298#section-2).
+    pub fn sample(&self, server: IpAddr, rtt: Duration) {
+        self.servers
+            .lock()
+            .unwrap()
+            .entry(server)
+            .and_modify(|srtt| *srtt = (*srtt * 7 + rtt) / 8)
+            .or_insert(rtt);
+    }
+
+    /// Penalizes a server that didn't respond, so others are preferred for a while.
+    pub fn failure(&self, server: IpAddr) {
+        self.servers
+            .lock()
+            .unwrap()
+            .entry(server)
+            .and_modify(|srtt| *srtt = (*srtt * 2).max(INITIAL_RTT).min(MAX_RTT))
+            .or_insert(INITIAL_RTT * 2);
+    }
+
+    /// Orders `servers` by their expected round trip time, fastest first.
+    pub fn sort(&self, servers: &mut [SocketAddr]) {
+        servers.sort_by_cached_key(|server| self.estimate(server.ip()));
+    }
+}
@@ -0,0 +1,439 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use dns::{Class, DomainName, Record, ResourceRecord, Serial, Type, base64};
+
+use super::ZoneError;
+
+/// Parses the records of a zone in master file format as per
+/// [RFC 1035 Section 5](https://www.rfc-editor.org/rfc/rfc1035#section-5).
+///
+/// Relative names are completed with `origin` until a `$ORIGIN` changes it. Records
+/// without a TTL use the one of `$TTL`, or that of the previous record. Record types
+/// without a presentation format here can be given in the generic format of
+/// [RFC 3597 Section 5](https://www.rfc-editor.org/rfc/rfc3597#section-5).
+pub fn parse(
+    content: &str,
+    origin: &DomainName<'static>,
+) -> Result<Vec<ResourceRecord<'static>>, ZoneError> {
+    let mut origin = origin.clone();
+    let mut default_ttl = None;
+    let mut last_ttl = None;
+    let mut owner: Option<DomainName<'static>> = None;
+    let mut records = Vec::new();
+
+    for entry in entries(content)? {
+        let line = entry.line;
+        let mut tokens = entry.tokens.iter().peekable();
+
+        match tokens.peek().map(|token| token.text.as_str()) {
+            Some("$ORIGIN") => {
+                tokens.next();
+                origin = name(next(&mut tokens, line, "origin")?, &origin, line)?;
+                continue;
+            }
+            Some("$TTL") => {
+                tokens.next();
+                default_ttl = Some(ttl(next(&mut tokens, line, "ttl")?, line)?);
+                continue;
+            }
+            Some(directive) if directive.starts_with('$') => {
+                return Err(syntax(line, format!("unsupported directive {}", directive)));
+            }
+            _ => {}
+        }
+
+        if !entry.blank_owner {
+            owner = Some(name(next(&mut tokens, line, "owner")?, &origin, line)?);
+        }
+        let owner = owner
+            .clone()
+            .ok_or_else(|| syntax(line, "record without owner".into()))?;
+
+        let mut record_ttl = None;
+        let mut class = Class::IN;
+        let r#type = loop {
+            let token = next(&mut tokens, line, "type")?;
+            let upper = token.to_ascii_uppercase();
+
+            if token.starts_with(|c: char| c.is_ascii_digit()) {
+                record_ttl = Some(ttl(token, line)?);
+            } else if matches!(upper.as_str(), "IN" | "CH" | "HS") || upper.starts_with("CLASS") {
+                class = token
+                    .parse()
+                    .map_err(|_| syntax(line, format!("invalid class {}", token)))?;
+            } else {
+                break token
+                    .parse::<Type>()
+                    .map_err(|_| syntax(line, format!("unknown type {}", token)))?;
+            }
+        };
+
+        let ttl = record_ttl
+            .or(default_ttl)
+            .or(last_ttl)
+            .ok_or_else(|| syntax(line, "record without ttl and no $TTL".into()))?;
+        last_ttl = Some(ttl);
+
+        let rdata: Vec<&Token> = tokens.collect();
+        let record = match rdata.first() {
+            Some(token) if token.text == "\\#" && !token.quoted => ResourceRecord::Unknown {
+                name: owner,
+                r#type,
+                class,
+                ttl,
+                data: generic(&rdata[1..], line)?.into(),
+            },
+            _ => ResourceRecord::Record {
+                name: owner,
+                class,
+                ttl,
+                data: record(&r#type, &rdata, &origin, line)?,
+            },
+        };
+
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Parses the rdata of `r#type` in presentation format.
+fn record(
+    r#type: &Type,
+    rdata: &[&Token],
+    origin: &DomainName<'static>,
+    line: usize,
+) -> Result<Record<'static>, ZoneError> {
+    let mut fields = rdata.iter().map(|token| token.text.as_str());
+    let mut field = |what: &str| {
+        fields
+            .next()
+            .ok_or_else(|| syntax(line, format!("missing {}", what)))
+    };
+
+    let record = match r#type {
+        Type::A => field("address")?
+            .parse::<Ipv4Addr>()
+            .map_err(|err| syntax(line, err.to_string()))?
+            .into(),
+        Type::AAAA => field("address")?
+            .parse::<Ipv6Addr>()
+            .map_err(|err| syntax(line, err.to_string()))?
+            .into(),
+        Type::NS => Record::NS {
+            nsdname: name(field("name server")?, origin, line)?,
+        },
+        Type::CNAME => Record::CNAME {
+            cname: name(field("canonical name")?, origin, line)?,
+        },
+        Type::PTR => Record::PTR {
+            ptrdname: name(field("pointer")?, origin, line)?,
+        },
+        Type::MX => Record::MX {
+            preference: number(field("preference")?, line)?,
+            exchange: name(field("exchange")?, origin, line)?,
+        },
+        Type::SRV => Record::SRV {
+            priority: number(field("priority")?, line)?,
+            weight: number(field("weight")?, line)?,
+            port: number(field("port")?, line)?,
+            target: name(field("target")?, origin, line)?,
+        },
+        Type::SOA => Record::SOA {
+            mname: name(field("primary name server")?, origin, line)?,
+            rname: name(field("mailbox")?, origin, line)?,
+            serial: Serial(number(field("serial")?, line)?),
+            refresh: ttl(field("refresh")?, line)?,
+            retry: ttl(field("retry")?, line)?,
+            expire: ttl(field("expire")?, line)?,
+            minimum: ttl(field("minimum")?, line)?,
+        },
+        Type::TXT => {
+            if rdata.is_empty() {
+                return Err(syntax(line, "missing text".into()));
+            }
+
+            let mut text = Vec::new();
+            for token in rdata {
+                let string = unescape(&token.text, line)?;
+                text.push(
+                    u8::try_from(string.len())
+                        .map_err(|_| syntax(line, "text longer than 255 bytes".into()))?,
+                );
+                text.extend(string);
+            }
+
+            return Ok(Record::TXT { text: text.into() });
+        }
+        Type::KEY => {
+            let flags = number(field("flags")?, line)?;
+            let protocol = number(field("protocol")?, line)?;
+            let algorithm = number(field("algorithm")?, line)?;
+
+            // the key may be split by whitespace, as per RFC 2535 Section 7.1
+            let encoded: String = fields.collect();
+            let public_key = base64::decode(&encoded)
+                .filter(|key| !key.is_empty())
+                .ok_or_else(|| syntax(line, "invalid public key".into()))?;
+
+            return Ok(Record::KEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key: public_key.into(),
+            });
+        }
+        other => {
+            return Err(syntax(
+                line,
+                format!("no presentation format for {}, use \\# instead", other),
+            ));
+        }
+    };
+
+    if fields.next().is_some() {
+        return Err(syntax(
+            line,
+            format!("trailing data after {} record", r#type),
+        ));
+    }
+
+    Ok(record)
+}
+
+/// Parses rdata in the generic `\# <length> <hex>` format.
+fn generic(rdata: &[&Token], line: usize) -> Result<Vec<u8>, ZoneError> {
+    let Some((len, hex)) = rdata.split_first() else {
+        return Err(syntax(line, "missing rdata length".into()));
+    };
+    let len: usize = number(&len.text, line)?;
+
+    let hex: String = hex.iter().map(|token| token.text.as_str()).collect();
+    if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+        return Err(syntax(line, "invalid hex digits".into()));
+    }
+
+    let data = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| syntax(line, "invalid hex digit".into()))?;
+
+    if data.len() != len {
+        return Err(syntax(
+            line,
+            format!("rdata length {} does not match {} bytes", len, data.len()),
+        ));
+    }
+
+    Ok(data)
+}
+
+/// Parses a name, relative to `origin` unless it ends with the root label.
+fn name(
+    text: &str,
+    origin: &DomainName<'static>,
+    line: usize,
+) -> Result<DomainName<'static>, ZoneError> {
+    if text == "@" {
+        return Ok(origin.clone());
+    }
+    if text.contains('\\') {
+        return Err(syntax(
+            line,
+            format!("escapes in name {} not supported", text),
+        ));
+    }
+
+    let mut name: DomainName = text
+        .parse()
+        .map_err(|err| syntax(line, format!("invalid name {}: {}", text, err)))?;
+    if !text.ends_with('.') {
+        name.labels.extend(origin.labels.iter().cloned());
+    }
+
+    Ok(name)
+}
+
+/// Parses a TTL in seconds, or with units as in `1h30m`.
+fn ttl(text: &str, line: usize) -> Result<u32, ZoneError> {
+    if let Ok(ttl) = text.parse() {
+        return Ok(ttl);
+    }
+
+    let mut ttl = 0u32;
+    let mut value = 0u32;
+    for c in text.chars() {
+        let unit = match c.to_ascii_lowercase() {
+            '0'..='9' => {
+                value = value
+                    .checked_mul(10)
+                    .and_then(|value| value.checked_add(c as u32 - '0' as u32))
+                    .ok_or_else(|| syntax(line, format!("ttl {} too large", text)))?;
+                continue;
+            }
+            's' => 1,
+            'm' => 60,
+            'h' => 3_600,
+            'd' => 86_400,
+            'w' => 604_800,
+            _ => return Err(syntax(line, format!("invalid ttl {}", text))),
+        };
+
+        ttl = value
+            .checked_mul(unit)
+            .and_then(|seconds| ttl.checked_add(seconds))
+            .ok_or_else(|| syntax(line, format!("ttl {} too large", text)))?;
+        value = 0;
+    }
+
+    if value != 0 {
+        return Err(syntax(line, format!("ttl {} misses a unit", text)));
+    }
+
+    Ok(ttl)
+}
+
+fn number<T: std::str::FromStr>(text: &str, line: usize) -> Result<T, ZoneError> {
+    text.parse()
+        .map_err(|_| syntax(line, format!("invalid number {}", text)))
+}
+
+/// Resolves `\X` and `\DDD` escapes of a character string.
+fn unescape(text: &str, line: usize) -> Result<Vec<u8>, ZoneError> {
+    let mut bytes = text.bytes();
+    let mut string = Vec::with_capacity(text.len());
+
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            string.push(byte);
+            continue;
+        }
+
+        match bytes.next() {
+            Some(digit @ b'0'..=b'9') => {
+                let digits = [digit, bytes.next().unwrap_or(0), bytes.next().unwrap_or(0)];
+                let value = std::str::from_utf8(&digits)
+                    .ok()
+                    .and_then(|digits| digits.parse().ok())
+                    .ok_or_else(|| syntax(line, "invalid \\DDD escape".into()))?;
+                string.push(value);
+            }
+            Some(byte) => string.push(byte),
+            None => return Err(syntax(line, "trailing backslash".into())),
+        }
+    }
+
+    Ok(string)
+}
+
+fn next<'t>(
+    tokens: &mut impl Iterator<Item = &'t Token>,
+    line: usize,
+    what: &str,
+) -> Result<&'t str, ZoneError> {
+    tokens
+        .next()
+        .map(|token| token.text.as_str())
+        .ok_or_else(|| syntax(line, format!("missing {}", what)))
+}
+
+fn syntax(line: usize, message: String) -> ZoneError {
+    ZoneError::Syntax { line, message }
+}
+
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+/// A directive or record, which may span lines within parentheses.
+struct Entry {
+    line: usize,
+
+    /// Entries starting with whitespace belong to the previous owner.
+    blank_owner: bool,
+
+    tokens: Vec<Token>,
+}
+
+/// Splits the file into entries of tokens, dropping comments. Escapes are kept as
+/// they are, quotes are removed.
+fn entries(content: &str) -> Result<Vec<Entry>, ZoneError> {
+    let mut entries = Vec::new();
+    let mut current: Option<Entry> = None;
+    let mut depth = 0;
+
+    for (i, text) in content.lines().enumerate() {
+        let line = i + 1;
+        let entry = current.get_or_insert_with(|| Entry {
+            line,
+            blank_owner: text.starts_with([' ', '\t']),
+            tokens: Vec::new(),
+        });
+
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                ';' => break,
+                '(' => depth += 1,
+                ')' if depth == 0 => return Err(syntax(line, "unbalanced )".into())),
+                ')' => depth -= 1,
+                '"' => {
+                    let mut token = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') => {
+                                token.push('\\');
+                                token.extend(chars.next());
+                            }
+                            Some(c) => token.push(c),
+                            None => return Err(syntax(line, "unterminated quote".into())),
+                        }
+                    }
+                    entry.tokens.push(Token {
+                        text: token,
+                        quoted: true,
+                    });
+                }
+                c if c.is_whitespace() => {}
+                c => {
+                    let mut token = String::from(c);
+                    if c == '\\' {
+                        token.extend(chars.next());
+                    }
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() || matches!(c, ';' | '(' | ')' | '"') {
+                            break;
+                        }
+                        chars.next();
+                        token.push(c);
+                        if c == '\\' {
+                            token.extend(chars.next());
+                        }
+                    }
+                    entry.tokens.push(Token {
+                        text: token,
+                        quoted: false,
+                    });
+                }
+            }
+        }
+
+        if depth == 0
+            && let Some(entry) = current.take()
+            && !entry.tokens.is_empty()
+        {
+            entries.push(entry);
+        }
+    }
+
+    if let Some(entry) = current
+        && depth > 0
+    {
+        return Err(syntax(entry.line, "unbalanced (".into()));
+    }
+
+    Ok(entries)
+}
@@ -0,0 +1,621 @@
+pub(crate) mod file;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::path::Path;
+
+use dns::{Class, DomainName, RCode, Record, ResourceRecord, Serial, Type};
+
+/// Length of a CNAME chain followed within a zone.
+const MAX_CHAIN: usize = 8;
+
+#[derive(Debug)]
+pub enum ZoneError {
+    Io(std::io::Error),
+    Syntax { line: usize, message: String },
+    Invalid(String),
+}
+
+impl Display for ZoneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "couldn't read zone file: {}", err),
+            Self::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            Self::Invalid(message) => write!(f, "invalid zone: {}", message),
+        }
+    }
+}
+
+/// The records of a zone this server is authoritative for, indexed by owner.
+pub struct Zone {
+    pub origin: DomainName<'static>,
+
+    /// Records by lowercased owner name.
+    records: HashMap<String, Vec<ResourceRecord<'static>>>,
+
+    /// Names that exist in the zone, including empty non-terminals.
+    names: HashSet<String>,
+}
+
+/// The outcome of a lookup in a zone, as per
+/// [RFC 1034 Section 4.3.2](https://www.rfc-editor.org/rfc/rfc1034#section-4.3.2).
+pub struct Lookup {
+    pub rcode: RCode,
+
+    /// Cleared for referrals, where the data belongs to the child zone.
+    pub authoritative: bool,
+
+    pub answers: Vec<ResourceRecord<'static>>,
+    pub authorities: Vec<ResourceRecord<'static>>,
+    pub additionals: Vec<ResourceRecord<'static>>,
+}
+
+/// Where a lookup goes on after a step.
+enum Step {
+    Done,
+    Follow(DomainName<'static>),
+}
+
+impl Zone {
+    /// Reads the zone `origin` from a master file.
+    pub fn load(path: &Path, origin: DomainName<'static>) -> Result<Self, ZoneError> {
+        let content = std::fs::read_to_string(path).map_err(ZoneError::Io)?;
+        let records = file::parse(&content, &origin)?;
+        Self::new(origin, records)
+    }
+
+    /// Builds the zone from its records, which must include the SOA and NS records of
+    /// the apex and nothing outside of the zone.
+    pub fn new(
+        origin: DomainName<'static>,
+        records: Vec<ResourceRecord<'static>>,
+    ) -> Result<Self, ZoneError> {
+        let mut zone = Self {
+            origin,
+            records: HashMap::new(),
+            names: HashSet::new(),
+        };
+
+        for record in records {
+            let (ResourceRecord::Record { name, class, .. }
+            | ResourceRecord::Unknown { name, class, .. }) = &record
+            else {
+                continue;
+            };
+
+            if *class != Class::IN {
+                return Err(ZoneError::Invalid(format!(
+                    "{} has class {}, only IN is served",
+                    name, class
+                )));
+            }
+
+            if !name.ends_with(&zone.origin) {
+                return Err(ZoneError::Invalid(format!(
+                    "{} is outside of {}",
+                    name, zone.origin
+                )));
+            }
+
+            // every name between the record and the apex exists
+            for len in zone.origin.labels.len()..=name.labels.len() {
+                zone.names
+                    .insert(key(&name.labels[name.labels.len() - len..]));
+            }
+
+            zone.records
+                .entry(key(&name.labels))
+                .or_default()
+                .push(record);
+        }
+
+        let apex = zone.rrset(&key(&zone.origin.labels), &Type::SOA);
+        if apex.len() != 1 {
+            return Err(ZoneError::Invalid(format!(
+                "{} needs exactly one soa record at the apex",
+                zone.origin
+            )));
+        }
+        if zone.rrset(&key(&zone.origin.labels), &Type::NS).is_empty() {
+            return Err(ZoneError::Invalid(format!(
+                "{} has no ns records at the apex",
+                zone.origin
+            )));
+        }
+
+        Ok(zone)
+    }
+
+    /// The SOA record of the apex.
+    pub fn soa(&self) -> &ResourceRecord<'static> {
+        self.rrset(&key(&self.origin.labels), &Type::SOA)[0]
+    }
+
+    pub fn serial(&self) -> Serial {
+        match self.soa() {
+            ResourceRecord::Record {
+                data: Record::SOA { serial, .. },
+                ..
+            } => *serial,
+            _ => unreachable!("apex record checked to be an soa"),
+        }
+    }
+
+    /// Replaces the serial of the SOA record of the apex.
+    pub fn set_serial(&mut self, serial: Serial) {
+        let apex = self.records.get_mut(&key(&self.origin.labels));
+        for record in apex.into_iter().flatten() {
+            if let ResourceRecord::Record {
+                data: Record::SOA {
+                    serial: current, ..
+                },
+                ..
+            } = record
+            {
+                *current = serial;
+            }
+        }
+    }
+
+    /// Whether `other` holds the same records as this zone, apart from the serial.
+    pub fn same_records(&self, other: &Zone) -> bool {
+        // records have no equality, their debug output stands in for it
+        let records = |zone: &Zone| {
+            let mut records: Vec<String> = zone
+                .records
+                .values()
+                .flatten()
+                .map(|record| {
+                    let mut record = record.clone();
+                    if let ResourceRecord::Record {
+                        data: Record::SOA { serial, .. },
+                        ..
+                    } = &mut record
+                    {
+                        *serial = Serial(0);
+                    }
+                    format!("{:?}", record)
+                })
+                .collect();
+            records.sort();
+            records
+        };
+
+        records(self) == records(other)
+    }
+
+    /// The records of `name` and `type`, without following CNAMEs or expanding
+    /// wildcards.
+    pub fn records(&self, name: &DomainName, r#type: &Type) -> Vec<ResourceRecord<'static>> {
+        self.rrset(&key(&name.labels), r#type)
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    /// The A and AAAA records of `name`, used as additional data for records that
+    /// refer to it. Wildcards are not expanded for this, and names at or below a zone
+    /// cut have no addresses of this zone, as those are only glue.
+    pub fn addresses(&self, name: &DomainName) -> Vec<ResourceRecord<'static>> {
+        if self.delegation(name, &Type::A).is_some() {
+            return Vec::new();
+        }
+
+        self.glue(name)
+    }
+
+    /// The A and AAAA records of `name`, wherever it is in the zone.
+    fn glue(&self, name: &DomainName) -> Vec<ResourceRecord<'static>> {
+        let key = key(&name.labels);
+        self.rrset(&key, &Type::A)
+            .into_iter()
+            .chain(self.rrset(&key, &Type::AAAA))
+            .cloned()
+            .collect()
+    }
+
+    /// Answers `qname` and `qtype` from the zone, which `qname` must be in.
+    ///
+    /// Names below a zone cut are referred to the child zone, with glue from this zone.
+    /// Wildcards are expanded as per [RFC 4592](https://www.rfc-editor.org/rfc/rfc4592)
+    /// and CNAMEs are followed as long as their target is in the zone. Negative answers
+    /// carry the SOA as per [RFC 2308 Section 3](https://www.rfc-editor.org/rfc/rfc2308#section-3).
+    pub fn lookup(&self, qname: &DomainName, qtype: &Type) -> Lookup {
+        let mut lookup = Lookup {
+            rcode: RCode::NoError,
+            authoritative: true,
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        };
+
+        let mut visited = vec![qname.clone().into_owned()];
+        while visited.len() <= MAX_CHAIN {
+            match self.step(&visited[visited.len() - 1], qtype, &mut lookup) {
+                Step::Follow(target)
+                    if target.ends_with(&self.origin) && !visited.contains(&target) =>
+                {
+                    visited.push(target)
+                }
+                _ => break,
+            }
+        }
+
+        lookup
+    }
+
+    /// Looks up a single name, adding what was found to `lookup`.
+    fn step(&self, name: &DomainName<'static>, qtype: &Type, lookup: &mut Lookup) -> Step {
+        if let Some(cut) = self.delegation(name, qtype) {
+            self.refer(&cut, lookup);
+            return Step::Done;
+        }
+
+        let name_key = key(&name.labels);
+        if let Some(node) = self.records.get(&name_key) {
+            return self.answer(node, None, qtype, lookup);
+        }
+
+        if self.names.contains(&name_key) {
+            self.negative(RCode::NoError, lookup);
+            return Step::Done;
+        }
+
+        // RFC 4592 Section 3.3.1, the wildcard is a child of the closest encloser
+        let encloser = (1..name.labels.len())
+            .map(|skip| &name.labels[skip..])
+            .find(|labels| self.names.contains(&key(labels)))
+            .unwrap_or(&self.origin.labels);
+
+        let wildcard: Vec<&str> = std::iter::once("*")
+            .chain(encloser.iter().map(|label| label.as_ref()))
+            .collect();
+
+        let wildcard = key(&wildcard);
+        match self.records.get(&wildcard) {
+            Some(node) => self.answer(node, Some(name), qtype, lookup),
+            // a wildcard that is an empty non-terminal still matches, without data, as
+            // per RFC 4592 Section 2.2.2
+            None if self.names.contains(&wildcard) => {
+                self.negative(RCode::NoError, lookup);
+                Step::Done
+            }
+            None => {
+                self.negative(RCode::NXDomain, lookup);
+                Step::Done
+            }
+        }
+    }
+
+    /// Answers from the records of an existing name, or of a wildcard when `owner` is
+    /// set to the name it is expanded to.
+    fn answer(
+        &self,
+        node: &[ResourceRecord<'static>],
+        owner: Option<&DomainName<'static>>,
+        qtype: &Type,
+        lookup: &mut Lookup,
+    ) -> Step {
+        let synthesize = |record: &ResourceRecord<'static>| {
+            let mut record = record.clone();
+            if let Some(owner) = owner
+                && let ResourceRecord::Record { name, .. } | ResourceRecord::Unknown { name, .. } =
+                    &mut record
+            {
+                *name = owner.clone();
+            }
+            record
+        };
+
+        let matching: Vec<_> = node
+            .iter()
+            .filter(|record| *qtype == Type::ANY || Type::from(*record) == *qtype)
+            .map(synthesize)
+            .collect();
+        if !matching.is_empty() {
+            lookup.answers.extend(matching);
+            return Step::Done;
+        }
+
+        if let Some(cname) = node
+            .iter()
+            .find(|record| Type::from(*record) == Type::CNAME)
+        {
+            let target = match cname {
+                ResourceRecord::Record {
+                    data: Record::CNAME { cname, .. },
+                    ..
+                } => cname.clone(),
+                _ => return Step::Done,
+            };
+
+            lookup.answers.push(synthesize(cname));
+            return Step::Follow(target);
+        }
+
+        self.negative(RCode::NoError, lookup);
+        Step::Done
+    }
+
+    /// Finds the topmost zone cut above or at `name`. The NS records at a cut belong to
+    /// the child, but a DS query for the cut itself is answered by the parent.
+    fn delegation(&self, name: &DomainName, qtype: &Type) -> Option<String> {
+        (self.origin.labels.len() + 1..=name.labels.len())
+            .map(|len| (len, key(&name.labels[name.labels.len() - len..])))
+            .filter(|(len, _)| *len < name.labels.len() || *qtype != Type::DS)
+            .find(|(_, cut)| !self.rrset(cut, &Type::NS).is_empty())
+            .map(|(_, cut)| cut)
+    }
+
+    /// Refers to the child zone at `cut`, with addresses of its name servers from this
+    /// zone as glue.
+    fn refer(&self, cut: &str, lookup: &mut Lookup) {
+        let ns = self.rrset(cut, &Type::NS);
+
+        for record in &ns {
+            let ResourceRecord::Record {
+                data: Record::NS { nsdname },
+                ..
+            } = record
+            else {
+                continue;
+            };
+
+            if nsdname.ends_with(&self.origin) {
+                lookup.additionals.extend(self.glue(nsdname));
+            }
+        }
+
+        lookup.authorities.extend(ns.into_iter().cloned());
+        lookup.authoritative = !lookup.answers.is_empty();
+    }
+
+    /// Ends the lookup without data, with the SOA whose TTL caps the negative caching.
+    fn negative(&self, rcode: RCode, lookup: &mut Lookup) {
+        let mut soa = self.soa().clone();
+        if let ResourceRecord::Record {
+            ttl,
+            data: Record::SOA { minimum, .. },
+            ..
+        } = &mut soa
+        {
+            *ttl = (*ttl).min(*minimum);
+        }
+
+        lookup.rcode = rcode;
+        lookup.authorities.push(soa);
+    }
+
+    fn rrset(&self, key: &str, r#type: &Type) -> Vec<&ResourceRecord<'static>> {
+        self.records
+            .get(key)
+            .into_iter()
+            .flatten()
+            .filter(|record| Type::from(*record) == *r#type)
+            .collect()
+    }
+}
+
+/// The lowercased presentation of a name given by its labels, as used for indexing.
+pub fn key<L: AsRef<str>>(labels: &[L]) -> String {
+    let mut key = String::new();
+    for label in labels {
+        key.push_str(&label.as_ref().to_ascii_lowercase());
+        key.push('.');
+    }
+    if key.is_empty() {
+        key.push('.');
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZONE: &str = "
+$TTL 1h
+@           SOA   ns1 hostmaster 1 2h 15m 1w 300
+@           NS    ns1
+ns1         A     192.0.2.1
+www         A     192.0.2.2
+mail        MX    10 www
+alias       CNAME www
+outside     CNAME www.example.net.
+*.wild      TXT   \"wildcard\"
+host.wild   A     192.0.2.3
+a.b.empty   A     192.0.2.4
+sub.*.ent   A     192.0.2.5
+child       NS    ns.child
+child       NS    ns.example.net.
+ns.child    A     192.0.2.6
+";
+
+    fn zone() -> Zone {
+        let origin = name("example.com");
+        Zone::new(origin.clone(), file::parse(ZONE, &origin).unwrap()).unwrap()
+    }
+
+    fn name(name: &str) -> DomainName<'static> {
+        name.parse().unwrap()
+    }
+
+    fn lookup(qname: &str, qtype: Type) -> Lookup {
+        zone().lookup(&name(qname), &qtype)
+    }
+
+    fn types(records: &[ResourceRecord]) -> Vec<Type> {
+        records.iter().map(Type::from).collect()
+    }
+
+    fn owners(records: &[ResourceRecord]) -> Vec<String> {
+        records
+            .iter()
+            .map(|record| match record {
+                ResourceRecord::Record { name, .. } => name.to_string(),
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn answer() {
+        let lookup = lookup("www.example.com", Type::A);
+        assert_eq!(lookup.rcode, RCode::NoError);
+        assert!(lookup.authoritative);
+        assert_eq!(types(&lookup.answers), [Type::A]);
+        assert!(lookup.authorities.is_empty());
+    }
+
+    #[test]
+    fn names_are_case_insensitive() {
+        let lookup = lookup("WWW.Example.COM", Type::A);
+        assert_eq!(types(&lookup.answers), [Type::A]);
+    }
+
+    #[test]
+    fn nxdomain() {
+        let lookup = lookup("missing.example.com", Type::A);
+        assert_eq!(lookup.rcode, RCode::NXDomain);
+        assert!(lookup.authoritative);
+        assert!(lookup.answers.is_empty());
+        assert_eq!(types(&lookup.authorities), [Type::SOA]);
+    }
+
+    #[test]
+    fn nodata() {
+        let lookup = lookup("www.example.com", Type::MX);
+        assert_eq!(lookup.rcode, RCode::NoError);
+        assert!(lookup.authoritative);
+        assert!(lookup.answers.is_empty());
+        assert_eq!(types(&lookup.authorities), [Type::SOA]);
+    }
+
+    #[test]
+    fn negative_ttl_is_capped_by_the_soa_minimum() {
+        let lookup = lookup("missing.example.com", Type::A);
+        let ResourceRecord::Record { ttl, .. } = &lookup.authorities[0] else {
+            panic!("no soa");
+        };
+        assert_eq!(*ttl, 300);
+    }
+
+    #[test]
+    fn empty_non_terminal_is_nodata() {
+        for qname in ["b.empty.example.com", "empty.example.com"] {
+            let lookup = lookup(qname, Type::A);
+            assert_eq!(lookup.rcode, RCode::NoError);
+            assert!(lookup.answers.is_empty());
+            assert_eq!(types(&lookup.authorities), [Type::SOA]);
+        }
+    }
+
+    #[test]
+    fn cname_is_followed_within_the_zone() {
+        let lookup = lookup("alias.example.com", Type::A);
+        assert_eq!(lookup.rcode, RCode::NoError);
+        assert_eq!(types(&lookup.answers), [Type::CNAME, Type::A]);
+        assert_eq!(
+            owners(&lookup.answers),
+            ["alias.example.com.", "www.example.com."]
+        );
+    }
+
+    #[test]
+    fn cname_out_of_the_zone_ends_the_answer() {
+        let lookup = lookup("outside.example.com", Type::A);
+        assert_eq!(lookup.rcode, RCode::NoError);
+        assert_eq!(types(&lookup.answers), [Type::CNAME]);
+        assert!(lookup.authorities.is_empty());
+    }
+
+    #[test]
+    fn wildcard_is_expanded() {
+        let lookup = lookup("anything.wild.example.com", Type::TXT);
+        assert_eq!(lookup.rcode, RCode::NoError);
+        assert!(lookup.authoritative);
+        assert_eq!(types(&lookup.answers), [Type::TXT]);
+        assert_eq!(owners(&lookup.answers), ["anything.wild.example.com."]);
+    }
+
+    #[test]
+    fn wildcard_without_the_type_is_nodata() {
+        let lookup = lookup("anything.wild.example.com", Type::A);
+        assert_eq!(lookup.rcode, RCode::NoError);
+        assert!(lookup.answers.is_empty());
+        assert_eq!(types(&lookup.authorities), [Type::SOA]);
+    }
+
+    #[test]
+    fn wildcard_doesnt_match_existing_names() {
+        // RFC 4592 Section 2.2.1, the name exists so the wildcard isn't used
+        let lookup = lookup("host.wild.example.com", Type::TXT);
+        assert_eq!(lookup.rcode, RCode::NoError);
+        assert!(lookup.answers.is_empty());
+    }
+
+    #[test]
+    fn wildcard_only_matches_below_the_closest_encloser() {
+        // host.wild exists, so it is the closest encloser and has no wildcard below it
+        let lookup = lookup("deeper.host.wild.example.com", Type::TXT);
+        assert_eq!(lookup.rcode, RCode::NXDomain);
+        assert!(lookup.answers.is_empty());
+    }
+
+    #[test]
+    fn empty_non_terminal_wildcard_is_nodata() {
+        let lookup = lookup("anything.ent.example.com", Type::A);
+        assert_eq!(lookup.rcode, RCode::NoError);
+        assert!(lookup.answers.is_empty());
+        assert_eq!(types(&lookup.authorities), [Type::SOA]);
+    }
+
+    #[test]
+    fn referral() {
+        let lookup = lookup("www.child.example.com", Type::A);
+        assert_eq!(lookup.rcode, RCode::NoError);
+        assert!(!lookup.authoritative);
+        assert!(lookup.answers.is_empty());
+        assert_eq!(types(&lookup.authorities), [Type::NS, Type::NS]);
+
+        // glue only for the name server inside the zone
+        assert_eq!(types(&lookup.additionals), [Type::A]);
+        assert_eq!(owners(&lookup.additionals), ["ns.child.example.com."]);
+    }
+
+    #[test]
+    fn referral_at_the_cut() {
+        let lookup = lookup("child.example.com", Type::NS);
+        assert!(!lookup.authoritative);
+        assert!(lookup.answers.is_empty());
+        assert_eq!(types(&lookup.authorities), [Type::NS, Type::NS]);
+    }
+
+    #[test]
+    fn ds_at_the_cut_is_answered_by_the_parent() {
+        let lookup = lookup("child.example.com", Type::DS);
+        assert!(lookup.authoritative);
+        assert_eq!(lookup.rcode, RCode::NoError);
+        assert_eq!(types(&lookup.authorities), [Type::SOA]);
+    }
+
+    #[test]
+    fn apex_ns_is_an_answer() {
+        let lookup = lookup("example.com", Type::NS);
+        assert!(lookup.authoritative);
+        assert_eq!(types(&lookup.answers), [Type::NS]);
+    }
+
+    #[test]
+    fn addresses_below_a_cut_are_only_glue() {
+        let zone = zone();
+        assert_eq!(types(&zone.addresses(&name("www.example.com"))), [Type::A]);
+        assert!(zone.addresses(&name("ns.child.example.com")).is_empty());
+        assert!(zone.addresses(&name("child.example.com")).is_empty());
+    }
+
+    #[test]
+    fn serial() {
+        assert_eq!(zone().serial(), Serial(1));
+    }
+}